@@ -0,0 +1,157 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// The part of `Config` which may be kept in a TOML file, e.g.
+///
+/// ```toml
+/// mavlink_listen = "udpbcast:0.0.0.0:14550"
+/// mavlink_system_id = 2
+/// msp_connection = "serial:/dev/ttyUSB0:115200"
+/// msp_baud = 115200
+/// msp_timeout_ms = 300
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub mavlink_listen: Option<String>,
+    pub mavlink_system_id: Option<u8>,
+    pub msp_connection: Option<String>,
+    pub msp_baud: Option<u32>,
+    pub msp_timeout_ms: Option<u64>,
+}
+
+impl ConfigFile {
+    /// reads and parses the file at `path`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// the command line flags equivalent to the values of the file
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(listen) = &self.mavlink_listen {
+            args.push("--mavlink-listen".into());
+            args.push(listen.into());
+        }
+        if let Some(id) = self.mavlink_system_id {
+            args.push("--mavlink-system-id".into());
+            args.push(id.to_string().into());
+        }
+        if let Some(connection) = &self.msp_connection {
+            args.push("--serial".into());
+            args.push(connection.into());
+        }
+        if let Some(baud) = self.msp_baud {
+            args.push("--baud".into());
+            args.push(baud.to_string().into());
+        }
+        if let Some(timeout) = self.msp_timeout_ms {
+            args.push("--msp-timeout-ms".into());
+            args.push(timeout.to_string().into());
+        }
+        args
+    }
+}
+
+/// puts the flags of the file given by `--config` in front of the command line flags
+///
+/// As the last occurrence of a flag wins, the command line overrides the file.
+pub fn args<I: IntoIterator<Item = OsString>>(args: I) -> io::Result<Vec<OsString>> {
+    let args: Vec<OsString> = args.into_iter().collect();
+    let mut path = None;
+    for (i, arg) in args.iter().enumerate() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            path = args.get(i + 1).cloned();
+        } else if let Some(p) = arg.strip_prefix("--config=") {
+            path = Some(p.into());
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(args),
+    };
+    let mut file = ConfigFile::load(Path::new(&path))?;
+    // repeated listen addresses add up, so those of the command line replace the file's
+    let listens = args.iter().skip(1).any(|arg| {
+        let arg = arg.to_string_lossy();
+        arg.starts_with("--mavlink-listen") || (arg.starts_with("-l") && !arg.starts_with("--"))
+    });
+    if listens {
+        file.mavlink_listen = None;
+    }
+    let mut merged: Vec<OsString> = args.iter().take(1).cloned().collect();
+    merged.extend(file.to_args());
+    merged.extend(args.into_iter().skip(1));
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::MspTransport;
+    use crate::Config;
+    use clap::Clap;
+
+    #[test]
+    fn command_line_overrides_file() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            mavlink_listen = "tcpout:127.0.0.1:5760"
+            mavlink_system_id = 2
+            msp_connection = "tcp:192.168.1.1:5761"
+            msp_timeout_ms = 300
+            "#,
+        )
+        .unwrap();
+        let mut args: Vec<OsString> = vec!["bridge".into()];
+        args.extend(file.to_args());
+        args.extend(vec!["-i".into(), "3".into()]);
+
+        let conf = Config::try_parse_from(args).unwrap();
+        assert_eq!(conf.mavlink_listen, vec!["tcpout:127.0.0.1:5760"]);
+        assert_eq!(conf.mavlink_system_id, 3);
+        assert_eq!(
+            conf.msp_connection,
+            Some(MspTransport::Tcp("192.168.1.1:5761".to_string()))
+        );
+        assert_eq!(conf.msp_baud, 115200);
+        assert_eq!(conf.msp_timeout_ms.get(), 300);
+    }
+
+    #[test]
+    fn zero_msp_timeout_is_rejected() {
+        let args = ["bridge", "-s", "/dev/null", "--msp-timeout-ms", "0"];
+        assert!(Config::try_parse_from(&args).is_err());
+    }
+
+    #[test]
+    fn command_line_replaces_listen_addresses_of_file() {
+        let path = std::env::temp_dir().join("mavlink-msp-bridge-listen.toml");
+        fs::write(&path, "mavlink_listen = \"udpbcast:0.0.0.0:14550\"").unwrap();
+        let parse = |extra: &[&str]| {
+            let mut cli: Vec<OsString> = vec!["bridge".into(), "--config".into()];
+            cli.push(path.clone().into());
+            cli.extend(["-s", "/dev/null"].iter().chain(extra).map(OsString::from));
+            Config::try_parse_from(args(cli).unwrap()).unwrap()
+        };
+
+        assert_eq!(parse(&[]).mavlink_listen, vec!["udpbcast:0.0.0.0:14550"]);
+        let conf = parse(&["-l", "udpout:127.0.0.1:14550", "-l", "tcpin:0.0.0.0:5760"]);
+        assert_eq!(
+            conf.mavlink_listen,
+            vec!["udpout:127.0.0.1:14550", "tcpin:0.0.0.0:5760"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("serial_port = \"/dev/ttyUSB0\"").is_err());
+    }
+}
@@ -1,85 +1,1176 @@
 use std::collections::HashMap;
-use std::io;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use mavlink::common::*;
+use mavlink::{MavConnection, MavHeader, Message};
 
-use futures::prelude::*;
+use smol::lock::Mutex;
+use smol::Timer;
 
+use crate::dialect::CommonConnection;
+use crate::fanout;
+use crate::metrics;
 use crate::msp::*;
+use crate::replay::{RecordingConnection, ReplayConnection};
 use crate::scheduler::Schedule;
+use crate::simulator;
 use crate::translator::*;
 use crate::Config;
 
+/// generates the messages of one slot in the schedule, usually one but possibly a batch sharing
+/// the same MSP round-trips
 pub type GeneratorFn = fn(
     conf: &Config,
+    state: &BridgeState,
     mspconn: &mut dyn MspConnection,
     context: Option<&MavMessage>,
-) -> io::Result<MavMessage>;
-
-pub fn event_loop(conf: &Config) -> ! {
-    // initializes the MSP connection
-    let mut mspconn =
-        serialport::open(&conf.msp_serialport).expect("unable to open serial SERIALPORT");
-    mspconn
-        .set_timeout(Duration::from_millis(100))
-        .expect("unable to set timeout for SERIALPORT");
-    mspconn
-        .clear(serialport::ClearBuffer::All)
-        .expect("unable to clear serial connection");
-
-    let mut generators: HashMap<u32, GeneratorFn> = HashMap::new();
-
-    let mut _response_to: HashMap<u32, u32> = HashMap::new();
-
-    generators.insert(0, heartbeat);
-    generators.insert(22, param_value);
-    generators.insert(27, raw_imu);
-    generators.insert(30, attitude);
-    //generators.insert(44, mission_count);
-
-    // testing wether MSP connection is attached to MSP FC
-    let resp: MspIdent = MspMessage::fetch(&mut mspconn).expect("unable to receive response");
+) -> io::Result<Vec<MavMessage>>;
+
+/// MSP function polled by a generator, by name and function id
+pub type MspFunction = (&'static str, u16);
+
+/// lists MSP payload types as `MspFunction`s
+macro_rules! polls {
+    ( $( $payload:ident ),* ) => {
+        &[ $( (stringify!($payload), $payload::ID) ),* ]
+    };
+}
+
+/// Generators of the MAVLink messages the bridge can send, by message id
+///
+/// The default registry holds every generator of the bridge.
+#[derive(Clone)]
+pub struct TranslatorRegistry {
+    generators: HashMap<u32, (GeneratorFn, &'static [MspFunction])>,
+}
+
+impl Default for TranslatorRegistry {
+    fn default() -> Self {
+        let mut registry = TranslatorRegistry::new();
+        registry
+            .register(0, heartbeat, polls!(MspStatus))
+            .register(1, sys_status, polls!(MspStatus, MspAnalog))
+            .register(2, system_time, polls!())
+            .register(22, param_value, polls!(MspPid, MspRcTuning))
+            .register(24, gps_raw_int, polls!(MspRawGps))
+            .register(26, scaled_imu, polls!(MspRawImu))
+            .register(27, raw_imu, polls!(MspRawImu))
+            .register(29, scaled_pressure, polls!(MspAltitude))
+            .register(30, attitude, polls!(MspAttitude))
+            .register(31, attitude_quaternion, polls!(MspAttitude))
+            .register(32, local_position_ned, polls!(MspRawGps, MspAltitude))
+            .register(33, global_position_int, polls!(MspRawGps, MspAltitude))
+            .register(36, servo_output_raw, polls!(MspServo, MspMotor))
+            .register(49, gps_global_origin, polls!(MspRawGps))
+            .register(65, rc_channels, polls!(MspRc, MspAnalog))
+            .register(
+                74,
+                vfr_hud,
+                polls!(MspAltitude, MspAttitude, MspRawGps, MspRc),
+            )
+            .register(132, distance_sensor, polls!(MspSonarAltitude))
+            .register(147, battery_status, polls!(MspBatteryState, MspAnalog))
+            .register(148, autopilot_version, polls!(MspFcVersion, MspApiVersion))
+            .register(
+                242,
+                home_position,
+                polls!(MspRawGps, MspCompGps, MspAltitude),
+            )
+            .register(
+                245,
+                extended_sys_state,
+                polls!(MspStatus, MspAltitude, MspRawGps),
+            );
+        registry
+    }
+}
+
+impl TranslatorRegistry {
+    /// an empty registry
+    pub fn new() -> Self {
+        TranslatorRegistry {
+            generators: HashMap::new(),
+        }
+    }
+
+    /// adds the generator of message `id` which polls the MSP functions `polls`, replacing a
+    /// previous one
+    pub fn register(
+        &mut self,
+        id: u32,
+        generator: GeneratorFn,
+        polls: &'static [MspFunction],
+    ) -> &mut Self {
+        self.generators.insert(id, (generator, polls));
+        self
+    }
+
+    /// the generator of message `id`
+    pub fn get(&self, id: u32) -> Option<GeneratorFn> {
+        self.generators.get(&id).map(|(generator, _)| *generator)
+    }
+
+    /// the MSP functions polled by the generator of message `id`
+    pub fn polls(&self, id: u32) -> &'static [MspFunction] {
+        self.generators.get(&id).map_or(&[], |(_, polls)| polls)
+    }
+
+    /// checks whether message `id` can be generated
+    pub fn supports(&self, id: u32) -> bool {
+        self.generators.contains_key(&id)
+    }
+
+    /// ids of all messages which can be generated, in ascending order
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.generators.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// name of MAVLink message `id`, e.g. `HEARTBEAT`
+fn message_name(id: u32) -> Option<String> {
+    let message = MavMessage::default_message_from_id(id).ok()?;
+    let debug = format!("{:?}", message);
+    debug.split('(').next().map(str::to_string)
+}
+
+/// prints the supported messages and the MSP functions they poll, one per line
+pub fn list_messages(registry: &TranslatorRegistry) {
+    for id in registry.ids() {
+        let name = message_name(id).unwrap_or_else(|| "?".to_string());
+        let polls: Vec<String> = registry
+            .polls(id)
+            .iter()
+            .map(|(name, function)| format!("{} ({})", name, function))
+            .collect();
+        let line = format!("{:>5} {:<24} {}", id, name, polls.join(", "));
+        println!("{}", line.trim_end());
+    }
+}
+
+/// schedules message `id` at `frequency` Hz, refusing messages without generator
+///
+/// The new frequency replaces the one the message had, if it does not fit the message keeps
+/// its previous frequency. A frequency of zero or below removes the message from the schedule.
+fn schedule_message(
+    schedule: &Schedule<u32>,
+    registry: &TranslatorRegistry,
+    frequency: f64,
+    id: u32,
+) -> Result<(), &'static str> {
+    if frequency > 0. && !registry.supports(id) {
+        return Err("message is not supported");
+    }
+    let previous = schedule.frequency_of(&id);
+    schedule.delete(&id);
+    match schedule.insert(frequency, id) {
+        Ok(slots) => {
+            debug!("message {} scheduled in slots {:?}", id, slots);
+            Ok(())
+        }
+        Err(e) => {
+            if let Err(restore) = schedule.insert(previous, id) {
+                warn!(
+                    "unable to restore message {} at {} Hz: {}",
+                    id, previous, restore
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// upper bound for the delay between two reconnection attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(4);
+
+/// opens the MSP connection, retrying with the backoff of `reconnect_msp` until the FC answers
+/// or `--startup-timeout-ms` passed
+///
+/// Gives up early if `stop` is signaled meanwhile.
+fn connect_msp_at_startup(
+    conf: &Config,
+    stop: &smol::channel::Receiver<()>,
+) -> io::Result<Box<dyn MspConnection + Send>> {
+    let deadline = Instant::now() + Duration::from_millis(conf.startup_timeout_ms);
+    let mut backoff = Duration::from_millis(100);
+    loop {
+        match connect_msp(conf) {
+            Ok(conn) => return Ok(conn),
+            Err(e) if Instant::now() + backoff < deadline => {
+                warn!(
+                    "unable to connect to MSP FC, retrying in {:?}: {}",
+                    backoff, e
+                );
+                thread::sleep(backoff);
+                if stop.try_recv().is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "stopped while connecting to MSP FC",
+                    ));
+                }
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// stands in for a dropped MSP connection, every I/O fails
+struct Disconnected;
+
+impl Read for Disconnected {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+}
+
+impl Write for Disconnected {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+}
+
+impl MspConnection for Disconnected {}
+
+/// opens the connection to the MSP FC and tests wether a MSP FC is attached to it
+fn connect_msp(conf: &Config) -> io::Result<Box<dyn MspConnection + Send>> {
+    let (mut mspconn, name): (Box<dyn MspConnection + Send>, _) =
+        match (&conf.replay, &conf.msp_connection) {
+            (Some(path), _) => (
+                Box::new(ReplayConnection::open(path)?),
+                format!("replay:{}", path),
+            ),
+            (None, Some(transport)) => {
+                let timeout = Duration::from_millis(conf.msp_timeout_ms.get());
+                let conn = transport.connect(conf.msp_baud, timeout, conf.msp_drain_after_error)?;
+                if conf.msp_v1_only {
+                    (
+                        Box::new(V1Tunnel::new(conn)),
+                        format!("{} (MSPv1)", transport),
+                    )
+                } else {
+                    (conn, transport.to_string())
+                }
+            }
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no MSP connection given",
+                ))
+            }
+        };
+
+    // reconnects append to the recording, the timestamps stay monotonic as they count from t0.
+    // Requests are recorded before tunneling, so the recording replays without `--msp-v1-only`
+    if let Some(path) = &conf.record_msp {
+        let log = OpenOptions::new().create(true).append(true).open(path)?;
+        mspconn = Box::new(RecordingConnection::new(mspconn, log, conf.t0));
+        info!("recording MSP traffic to {}", path);
+    }
+
+    let resp: MspIdent = MspMessage::fetch(&mut mspconn)?;
     debug!("MspIdent received {:?}", resp);
-    info!("MSP connection opened on {}", mspconn.name().unwrap());
+    info!("MSP connection opened on {}", name);
+    Ok(mspconn)
+}
+
+/// wether an error indicates that the MSP connection itself is broken
+fn is_link_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::NotConnected | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// wether an error indicates that the FC did not answer in time
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// consecutive timeouts after which a silent FC counts as lost
+const LINK_LOSS_TIMEOUTS: u32 = 3;
+
+/// tells a lost MSP link from a FC which missed a few answers
+#[derive(Default)]
+struct LinkMonitor {
+    timeouts: u32,
+}
+
+impl LinkMonitor {
+    /// records the `result` of serving a slot, yielding wether the MSP link is lost
+    ///
+    /// A broken connection is lost at once, a silent FC after `LINK_LOSS_TIMEOUTS` timeouts in a
+    /// row. A replay answers unrecorded requests with timeouts, so these never count there, as a
+    /// reconnect would restart the replay.
+    fn lost(&mut self, conf: &Config, result: &io::Result<()>) -> bool {
+        match result {
+            Err(e) if is_link_error(e) => self.timeouts = LINK_LOSS_TIMEOUTS,
+            Err(e) if is_timeout(e) && conf.replay.is_none() && !conf.simulate => {
+                self.timeouts += 1
+            }
+            _ => self.timeouts = 0,
+        }
+        if self.timeouts < LINK_LOSS_TIMEOUTS {
+            return false;
+        }
+        self.timeouts = 0;
+        true
+    }
+}
+
+/// shows `text` in the message console of the GCS
+fn announce(
+    mavconn: &dyn MavConnection<MavMessage>,
+    header: &MavHeader,
+    severity: MavSeverity,
+    text: &str,
+) {
+    for message in statustexts(severity, text) {
+        if let Err(e) = mavconn.send(header, &message) {
+            warn!("unable to send STATUSTEXT: {:?}", e);
+        }
+    }
+}
+
+/// drops a broken MSP connection and reopens it, backing off exponentially until the FC answers
+///
+/// The connection is only locked to swap it, so everyone else fails fast on `Disconnected`
+/// meanwhile instead of waiting for the FC.
+async fn reconnect_msp(
+    conf: &Arc<Config>,
+    mspconn: &SharedMsp,
+    mavconn: &CommonConnection,
+    header: &MavHeader,
+) {
+    *mspconn.lock().await = Box::new(Disconnected);
+    let mut backoff = Duration::from_millis(100);
+    loop {
+        announce(
+            mavconn,
+            header,
+            MavSeverity::MAV_SEVERITY_WARNING,
+            "reconnecting",
+        );
+        Timer::after(backoff).await;
+        let conf = conf.clone();
+        match smol::unblock(move || connect_msp(&conf)).await {
+            Ok(conn) => {
+                *mspconn.lock().await = conn;
+                announce(
+                    mavconn,
+                    header,
+                    MavSeverity::MAV_SEVERITY_NOTICE,
+                    "MSP link restored",
+                );
+                return;
+            }
+            Err(e) => {
+                warn!("unable to reconnect to MSP FC: {:?}", e);
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+        }
+    }
+}
+
+/// the MSP connection shared by the scheduled messages and the handlers of the GCS
+type SharedMsp = Arc<Mutex<Box<dyn MspConnection + Send>>>;
+
+/// runs `f` on the locked MSP connection on the blocking thread pool
+///
+/// An MSP round-trip blocks for up to the read timeout, which must not stall the executor
+/// serving the schedule and the GCS.
+async fn with_msp<F, R>(mspconn: &SharedMsp, f: F) -> R
+where
+    F: FnOnce(&mut dyn MspConnection) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let mut mspconn = mspconn.lock_arc().await;
+    smol::unblock(move || f(&mut **mspconn)).await
+}
+
+/// reads the descriptions of all settings which can be represented as parameter
+///
+/// Yields nothing if the FC does not know MSP2 settings. The connection is locked per setting,
+/// so telemetry keeps flowing meanwhile.
+async fn load_settings(mspconn: &SharedMsp) -> Vec<MspSettingInfo> {
+    let mut settings = Vec::new();
+    for index in 0..=u16::MAX {
+        match with_msp(mspconn, move |mspconn| fetch_setting_info(mspconn, index)).await {
+            Ok(info) if is_param_setting(&info) => settings.push(info),
+            Ok(_) => {}
+            Err(e) => {
+                debug!("settings end at index {}: {:?}", index, e);
+                break;
+            }
+        }
+    }
+    info!("found {} settings", settings.len());
+    settings
+}
+
+/// reads a parameter, by `index` if it is not negative and else by `name`, from the settings
+/// or, if the FC has none, the PID gains and rates
+///
+/// The profile is only known by name, the GCS reads it back after selecting another one.
+fn read_param(
+    mspconn: &mut dyn MspConnection,
+    settings: &mut [MspSettingInfo],
+    index: i16,
+    name: &str,
+) -> io::Result<Option<MavMessage>> {
+    if index < 0 && name == PROFILE_PARAM {
+        return Ok(Some(read_profile(mspconn)?));
+    }
+    if settings.is_empty() {
+        let index = match index {
+            i if i >= 0 => Some(i as usize).filter(|i| *i < PARAM_COUNT),
+            _ => param_index(name),
+        };
+        return match index {
+            Some(index) => Ok(Some(fetch_param(mspconn, index)?)),
+            None => Ok(None),
+        };
+    }
+    match find_setting(settings, index, name) {
+        Some(index) => {
+            settings[index] = fetch_setting_info(&mut *mspconn, settings[index].index)?;
+            Ok(Some(setting_param(&settings[index], index, settings.len())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// text of the `STATUSTEXT` answering a read of a parameter which does not exist
+fn unknown_param(index: i16, name: &str) -> String {
+    match index {
+        i if i >= 0 => format!("unknown parameter index {}", i),
+        _ => format!("unknown parameter {}", name),
+    }
+}
+
+/// writes a parameter to the settings or, if the FC has none, the PID gains and rates
+fn write_param(
+    mspconn: &mut dyn MspConnection,
+    settings: &mut [MspSettingInfo],
+    name: &str,
+    value: f32,
+) -> io::Result<Option<MavMessage>> {
+    if settings.is_empty() {
+        return match param_index(name) {
+            Some(index) => Ok(Some(set_param(mspconn, index, value)?)),
+            None => Ok(None),
+        };
+    }
+    match find_setting(settings, -1, name) {
+        Some(index) => {
+            settings[index] = set_setting(mspconn, &settings[index], value)?;
+            Ok(Some(setting_param(&settings[index], index, settings.len())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// interval of the reminders to keep rotating the drone during the mag calibration
+const MAG_CALIBRATION_REMINDER: Duration = Duration::from_secs(5);
+
+/// reminds the operator to rotate the drone until the mag calibration is over
+async fn mag_calibration_progress(mavconn: &CommonConnection, header: &MavHeader) {
+    let mut left = MAG_CALIBRATION_TIME;
+    while left > Duration::from_secs(0) {
+        let text = format!("mag calibration, keep rotating for {} s", left.as_secs());
+        announce(mavconn, header, MavSeverity::MAV_SEVERITY_INFO, &text);
+        Timer::after(MAG_CALIBRATION_REMINDER).await;
+        left = left
+            .checked_sub(MAG_CALIBRATION_REMINDER)
+            .unwrap_or_default();
+    }
+    announce(
+        mavconn,
+        header,
+        MavSeverity::MAV_SEVERITY_INFO,
+        "mag calibration done",
+    );
+}
+
+/// RC channels overridden by the GCS and when they were last commanded
+///
+/// Only the latest command is kept, `keep_rc_override` sends it to the FC.
+type RcOverride = Option<(Instant, [u16; 16])>;
+
+/// patches the overridden RC channels, or the current channels of the FC if there are none
+///
+/// The channels are not sent right away, so commands arriving faster than the MSP link can
+/// carry them replace each other instead of queueing up.
+fn override_rc(
+    conf: &Config,
+    state: &BridgeState,
+    mspconn: &mut dyn MspConnection,
+    rc_override: &mut RcOverride,
+    patch: impl FnOnce(&mut [u16; 16]),
+) -> io::Result<()> {
+    let mut channels = match rc_override {
+        Some((_, channels)) => *channels,
+        None => fetch_rc(conf, state, mspconn)?,
+    };
+    patch(&mut channels);
+    *rc_override = Some((Instant::now(), channels));
+    Ok(())
+}
+
+/// the failsafe frame applied to the last overridden `channels`, if `--rc-failsafe` is given
+fn failsafe_rc(conf: &Config, mut channels: [u16; 16]) -> Option<[u16; 16]> {
+    let failsafe = conf.rc_failsafe.as_ref()?;
+    failsafe.apply(&mut channels);
+    Some(channels)
+}
+
+/// sends the latest overridden RC channels until the GCS stops commanding them
+///
+/// The channels are sent at the fixed `--rc-override-rate`, also when the GCS does not command
+/// anything new, as the FC considers its MSP receiver lost without updates. Once the GCS went
+/// silent the RC is either released to the receiver of the FC or, given `--rc-failsafe`, held at
+/// the failsafe frame until the GCS commands again.
+async fn keep_rc_override(
+    conf: &Config,
+    rc_override: &Mutex<RcOverride>,
+    mspconn: &SharedMsp,
+    mavconn: &CommonConnection,
+    header: &MavHeader,
+) {
+    let period = Duration::from_secs_f64(1. / conf.rc_override_rate.max(1.));
+    let timeout = Duration::from_millis(conf.rc_override_timeout_ms);
+    let mut failsafe = false;
+    loop {
+        Timer::after(period).await;
+        let mut rc_override = rc_override.lock().await;
+        let channels = match *rc_override {
+            Some((commanded, channels)) if commanded.elapsed() < timeout => {
+                failsafe = false;
+                channels
+            }
+            Some((_, channels)) => match failsafe_rc(conf, channels) {
+                Some(channels) => {
+                    if !failsafe {
+                        failsafe = true;
+                        announce(
+                            mavconn,
+                            header,
+                            MavSeverity::MAV_SEVERITY_WARNING,
+                            "RC override timed out, sending failsafe RC",
+                        );
+                    }
+                    channels
+                }
+                None => {
+                    *rc_override = None;
+                    announce(
+                        mavconn,
+                        header,
+                        MavSeverity::MAV_SEVERITY_WARNING,
+                        "RC override timed out, released RC",
+                    );
+                    continue;
+                }
+            },
+            None => {
+                failsafe = false;
+                continue;
+            }
+        };
+        let result = with_msp(mspconn, move |mspconn| set_raw_rc(mspconn, channels)).await;
+        if let Err(e) = result {
+            warn!("unable to resend RC override: {:?}", e);
+        }
+    }
+}
+
+/// progress of a mission upload from the GCS
+struct MissionUpload {
+    count: u16,
+    next: u16,
+}
+
+/// starts a mission upload of `count` items, yielding the request for the first one
+fn handle_mission_count(
+    mspconn: &mut dyn MspConnection,
+    upload: &mut Option<MissionUpload>,
+    count: u16,
+    sender: &MavHeader,
+) -> MavMessage {
+    *upload = None;
+    if count > MAX_WAYPOINTS.into() {
+        warn!("mission of {} items exceeds the FC capacity", count);
+        return mission_ack(sender, MavMissionResult::MAV_MISSION_ERROR);
+    }
+    if count == 0 {
+        // an empty first waypoint marks the end of the mission
+        let item = MISSION_ITEM_INT_DATA::default();
+        return match set_wp(mspconn, wp_from_mission_item(&item, false)) {
+            Ok(()) => mission_ack(sender, MavMissionResult::MAV_MISSION_ACCEPTED),
+            Err(e) => {
+                warn!("unable to clear mission: {:?}", e);
+                mission_ack(sender, MavMissionResult::MAV_MISSION_ERROR)
+            }
+        };
+    }
+    *upload = Some(MissionUpload { count, next: 0 });
+    mission_request_int(0, sender)
+}
+
+/// writes an uploaded mission item to the FC, yielding the request for the next item or the
+/// final `MISSION_ACK`
+fn handle_mission_item(
+    mspconn: &mut dyn MspConnection,
+    upload: &mut Option<MissionUpload>,
+    item: &MISSION_ITEM_INT_DATA,
+    sender: &MavHeader,
+) -> Option<MavMessage> {
+    let state = upload.as_mut()?;
+    if item.seq != state.next {
+        warn!("expected mission item {}, got {}", state.next, item.seq);
+        *upload = None;
+        return Some(mission_ack(
+            sender,
+            MavMissionResult::MAV_MISSION_INVALID_SEQUENCE,
+        ));
+    }
+
+    let last = item.seq + 1 == state.count;
+    if let Err(e) = set_wp(mspconn, wp_from_mission_item(item, last)) {
+        warn!("unable to write waypoint: {:?}", e);
+        *upload = None;
+        return Some(mission_ack(sender, MavMissionResult::MAV_MISSION_ERROR));
+    }
+
+    if last {
+        *upload = None;
+        Some(mission_ack(sender, MavMissionResult::MAV_MISSION_ACCEPTED))
+    } else {
+        state.next += 1;
+        Some(mission_request_int(state.next, sender))
+    }
+}
+
+/// acknowledges `command` with the outcome of handling it
+///
+/// A lost MSP link or a FC which did not answer temporarily rejects the command, as a retry
+/// may succeed once the link is restored. Any other failure is logged as failing to `action`.
+fn acknowledge(command: MavCmd, outcome: io::Result<MavResult>, action: &str) -> MavMessage {
+    let result = outcome.unwrap_or_else(|e| {
+        warn!("unable to {}: {:?}", action, e);
+        if is_link_error(&e) || is_timeout(&e) {
+            MavResult::MAV_RESULT_TEMPORARILY_REJECTED
+        } else {
+            MavResult::MAV_RESULT_FAILED
+        }
+    });
+    command_ack(command, result)
+}
+
+/// reacts to a `COMMAND_LONG`, yielding the messages to respond with
+///
+/// Every command is acknowledged, unknown ones as unsupported, so the GCS stops repeating it.
+fn handle_command(
+    conf: &Config,
+    state: &BridgeState,
+    mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> Vec<MavMessage> {
+    match msg.command {
+        MavCmd::MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES => {
+            let mut messages = autopilot_version(conf, state, mspconn, None).unwrap_or_else(|e| {
+                warn!("unable to send AUTOPILOT_VERSION: {:?}", e);
+                Vec::new()
+            });
+            let result = if messages.is_empty() {
+                MavResult::MAV_RESULT_FAILED
+            } else {
+                MavResult::MAV_RESULT_ACCEPTED
+            };
+            messages.extend(craft_name_text(state));
+            messages.push(command_ack(msg.command, result));
+            messages
+        }
+        MavCmd::MAV_CMD_COMPONENT_ARM_DISARM => {
+            let arm = msg.param1 == 1.;
+            let outcome = arm_disarm(conf, state, mspconn, arm);
+            vec![acknowledge(msg.command, outcome, "arm/disarm")]
+        }
+        MavCmd::MAV_CMD_PREFLIGHT_CALIBRATION => {
+            match acknowledge(
+                msg.command,
+                preflight_calibration(mspconn, msg),
+                "calibrate",
+            ) {
+                MavMessage::COMMAND_ACK(ack) if ack.result == MavResult::MAV_RESULT_ACCEPTED => {
+                    vec![
+                        command_ack(msg.command, MavResult::MAV_RESULT_IN_PROGRESS),
+                        MavMessage::COMMAND_ACK(ack),
+                    ]
+                }
+                ack => vec![ack],
+            }
+        }
+        MavCmd::MAV_CMD_PREFLIGHT_REBOOT_SHUTDOWN => {
+            vec![acknowledge(msg.command, reboot(mspconn, msg), "reboot")]
+        }
+        MavCmd::MAV_CMD_CONDITION_YAW => {
+            let outcome = condition_yaw(conf, state, mspconn, msg);
+            vec![acknowledge(msg.command, outcome, "set heading")]
+        }
+        MavCmd::MAV_CMD_DO_MOTOR_TEST => {
+            let outcome = motor_test(conf, mspconn, msg);
+            vec![acknowledge(msg.command, outcome, "test motor")]
+        }
+        MavCmd::MAV_CMD_DO_SET_SERVO => {
+            let outcome = set_servo(conf, mspconn, msg);
+            vec![acknowledge(msg.command, outcome, "set servo")]
+        }
+        command => {
+            warn!("received command, don't know what to do: {:?}", command);
+            vec![command_ack(command, MavResult::MAV_RESULT_UNSUPPORTED)]
+        }
+    }
+}
+
+/// messages which are suppressed while the telemetry is stale, so the GCS does not show a frozen
+/// position or attitude
+const STALE_SUPPRESSED: &[u32] = &[24, 30, 31, 32, 33, 242];
+
+/// minimal interval of the warnings about overrun slots
+const OVERRUN_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Skips the next occurrences of a message whose generation took longer than its slot, so an
+/// overloaded MSP link sheds messages rather than delaying the whole schedule
+#[derive(Default)]
+struct Backpressure {
+    /// occurrences left to skip per message id
+    skips: HashMap<u32, u32>,
+    /// overruns since the last warning
+    unreported: u64,
+    warned: Option<Instant>,
+}
+
+impl Backpressure {
+    /// checks whether this occurrence of `id` is skipped
+    fn skip(&mut self, id: u32) -> bool {
+        match self.skips.get_mut(&id) {
+            Some(left) if *left > 0 => {
+                *left -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// notes that generating `id` took `elapsed`, skipping as many of its next occurrences as
+    /// slots of `minor_frame` were overrun
+    fn record(&mut self, id: u32, elapsed: Duration, minor_frame: Duration) {
+        let overrun = (elapsed.as_nanos() / minor_frame.as_nanos().max(1)) as u32;
+        if overrun == 0 {
+            return;
+        }
+        self.skips.insert(id, overrun);
+        MSP_STATS.slot_overruns.fetch_add(1, Ordering::Relaxed);
+        self.unreported += 1;
+        if self
+            .warned
+            .map_or(true, |warned| warned.elapsed() >= OVERRUN_WARNING_INTERVAL)
+        {
+            warn!(
+                "MSP can not keep up, {} slots overrun, message {} took {:?} and skips {} times",
+                self.unreported, id, elapsed, overrun
+            );
+            self.unreported = 0;
+            self.warned = Some(Instant::now());
+        }
+    }
+}
+
+/// generates and sends the messages of slot `id`, remembering each by its own id in `sent` as
+/// context for the next time
+///
+/// A failing generator only costs this one message. The error is returned only if the MSP
+/// link broke or the FC did not answer, so the caller can tell wether the link is lost.
+/// Position and attitude are not sent while the telemetry is stale.
+#[allow(clippy::too_many_arguments)]
+fn serve_slot(
+    conf: &Config,
+    state: &BridgeState,
+    generator: GeneratorFn,
+    mspconn: &mut dyn MspConnection,
+    mavconn: &dyn MavConnection<MavMessage>,
+    header: &MavHeader,
+    sent: &mut HashMap<u32, MavMessage>,
+    id: u32,
+) -> io::Result<()> {
+    if STALE_SUPPRESSED.contains(&id) && state.watchdog.is_stale(conf) {
+        debug!("suppressing message {}, telemetry is stale", id);
+        return Ok(());
+    }
+    match generator(conf, state, mspconn, sent.get(&id)) {
+        Ok(messages) => {
+            for message in messages {
+                match mavconn.send(header, &message) {
+                    Ok(_) => {
+                        sent.insert(message.message_id(), message);
+                    }
+                    Err(e) => warn!("unable to send message {}: {:?}", id, e),
+                }
+            }
+        }
+        Err(e) if is_no_gps_fix(&e) => debug!("skipping message {}: {}", id, e),
+        Err(e) if is_link_error(&e) || is_timeout(&e) => return Err(e),
+        Err(e) => warn!("message {} could not be generated: {:?}", id, e),
+    }
+    Ok(())
+}
+
+/// message ids implied by each `MAV_DATA_STREAM` group of `REQUEST_DATA_STREAM`
+///
+/// | group             | messages                                                          |
+/// |-------------------|-------------------------------------------------------------------|
+/// | `RAW_SENSORS`     | `SCALED_IMU` (26), `RAW_IMU` (27), `SCALED_PRESSURE` (29)         |
+/// | `EXTENDED_STATUS` | `SYS_STATUS` (1), `GPS_RAW_INT` (24), `BATTERY_STATUS` (147),     |
+/// |                   | `EXTENDED_SYS_STATE` (245)                                        |
+/// | `RC_CHANNELS`     | `SERVO_OUTPUT_RAW` (36), `RC_CHANNELS` (65)                       |
+/// | `POSITION`        | `LOCAL_POSITION_NED` (32), `GLOBAL_POSITION_INT` (33)             |
+/// | `EXTRA1`          | `ATTITUDE` (30), `ATTITUDE_QUATERNION` (31)                       |
+/// | `EXTRA2`          | `VFR_HUD` (74)                                                    |
+/// | `EXTRA3`          | `SYSTEM_TIME` (2), `DISTANCE_SENSOR` (132), `HOME_POSITION` (242) |
+///
+/// `ALL` stands for every group, `RAW_CONTROLLER` has no counterpart on a MSP FC.
+const DATA_STREAMS: &[(MavDataStream, &[u32])] = &[
+    (MavDataStream::MAV_DATA_STREAM_RAW_SENSORS, &[26, 27, 29]),
+    (
+        MavDataStream::MAV_DATA_STREAM_EXTENDED_STATUS,
+        &[1, 24, 147, 245],
+    ),
+    (MavDataStream::MAV_DATA_STREAM_RC_CHANNELS, &[36, 65]),
+    (MavDataStream::MAV_DATA_STREAM_POSITION, &[32, 33]),
+    (MavDataStream::MAV_DATA_STREAM_EXTRA1, &[30, 31]),
+    (MavDataStream::MAV_DATA_STREAM_EXTRA2, &[74]),
+    (MavDataStream::MAV_DATA_STREAM_EXTRA3, &[2, 132, 242]),
+];
+
+/// frequencies of the messages sent without request of the GCS, besides the HEARTBEAT
+///
+/// Frequent messages come first, the rare ones then fill the gaps left between them.
+const STARTUP_SCHEDULE: &[(f64, u32)] = &[
+    // inform about attitude on high frequency
+    (30., 30),
+    // lets the GCS align its clock
+    (1., 2),
+    // in air or on ground rarely changes
+    (1., 245),
+    // the environment changes slowly
+    (1., 29),
+    // servo and motor outputs cost two round-trips and matter mostly on the bench
+    (1., 36),
+    // sent once the first 3D fix fixes the origin
+    (1., 49),
+];
+
+/// builds the schedule of `--schedule-slots` holding the HEARTBEAT and the `STARTUP_SCHEDULE`
+fn startup_schedule(conf: &Config) -> io::Result<Schedule<u32>> {
+    if conf.schedule_slots == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--schedule-slots must not be 0",
+        ));
+    }
+    if !(conf.schedule_period.is_finite() && conf.schedule_period > 0.) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--schedule-period must be a positive number of seconds",
+        ));
+    }
+    let schedule = Schedule::with_duration(
+        conf.schedule_slots,
+        Duration::from_secs_f64(conf.schedule_period),
+    );
+    for (freq, id) in [(1., 0)].iter().chain(STARTUP_SCHEDULE) {
+        let slots = schedule.insert(*freq, *id).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unable to schedule message {} at {} Hz in {} slots per {} s: {}",
+                    id, freq, conf.schedule_slots, conf.schedule_period, e
+                ),
+            )
+        })?;
+        debug!("message {} scheduled in slots {:?}", id, slots);
+    }
+    Ok(schedule)
+}
+
+/// frequency requested by `MESSAGE_INTERVAL` for message `id`, `None` leaves the schedule as is
+///
+/// An interval of -1 disables the message and one of 0 restores its rate of the
+/// `STARTUP_SCHEDULE`, which is ignored for a message without such a default.
+fn message_interval_frequency(interval_us: i32, id: u32) -> Option<f64> {
+    match interval_us {
+        i if i > 0 => Some(1_000_000f64 / i as f64),
+        -1 => Some(0.),
+        0 => [(1., 0)]
+            .iter()
+            .chain(STARTUP_SCHEDULE)
+            .find(|(_, default)| *default == id)
+            .map(|(freq, _)| *freq),
+        _ => None,
+    }
+}
+
+/// yields the message ids of a `MAV_DATA_STREAM` group, see [`DATA_STREAMS`]
+fn data_stream_ids(stream_id: u8) -> Vec<u32> {
+    DATA_STREAMS
+        .iter()
+        .filter(|(stream, _)| {
+            stream_id == MavDataStream::MAV_DATA_STREAM_ALL as u8 || stream_id == *stream as u8
+        })
+        .flat_map(|(_, ids)| ids.iter().copied())
+        .collect()
+}
+
+/// the scheduled message ids with their effective rate, e.g. `0: 1 Hz, 30: 10 Hz`
+fn rates_summary(schedule: &Schedule<u32>) -> String {
+    let mut entries = schedule.entries();
+    entries.sort_unstable();
+    let rates: Vec<String> = entries
+        .iter()
+        .map(|(id, _)| format!("{}: {} Hz", id, schedule.frequency_of(id)))
+        .collect();
+    rates.join(", ")
+}
+
+/// logs the effective rate of every scheduled message, and the slots of the schedule in debug
+fn log_rates(schedule: &Schedule<u32>) {
+    info!(
+        "scheduled messages: {}, {:.0}% of the slots used",
+        rates_summary(schedule),
+        schedule.utilization() * 100.
+    );
+    debug!("{}", schedule);
+}
+
+/// minimal interval of the logs of the changed schedule
+const SCHEDULE_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Logs the schedule after the GCS changed it, so a burst of interval requests is logged once
+/// its last change was flushed
+#[derive(Default)]
+struct ScheduleLog {
+    /// whether the schedule changed since it was last logged
+    pending: bool,
+    logged: Option<Instant>,
+}
+
+impl ScheduleLog {
+    /// notes a change of `schedule`, logging it unless it was logged just before
+    fn changed(&mut self, schedule: &Schedule<u32>) -> bool {
+        self.pending = true;
+        self.flush(schedule)
+    }
+
+    /// logs `schedule` if a change is pending and `SCHEDULE_LOG_INTERVAL` passed
+    fn flush(&mut self, schedule: &Schedule<u32>) -> bool {
+        if !self.pending
+            || self
+                .logged
+                .map_or(false, |logged| logged.elapsed() < SCHEDULE_LOG_INTERVAL)
+        {
+            return false;
+        }
+        log_rates(schedule);
+        self.pending = false;
+        self.logged = Some(Instant::now());
+        true
+    }
+}
+
+/// tells the GCS that the bridge goes away and leaves the MSP connection in a clean state
+async fn shutdown(
+    conf: &Config,
+    state: &BridgeState,
+    mspconn: &Mutex<Box<dyn MspConnection + Send>>,
+    mavconn: &dyn MavConnection<MavMessage>,
+    header: &MavHeader,
+) {
+    announce(
+        mavconn,
+        header,
+        MavSeverity::MAV_SEVERITY_NOTICE,
+        "bridge shutting down",
+    );
+    // holding the lock ensures no request is written halfway
+    let mut mspconn = mspconn.lock().await;
+    let mut message = heartbeat(conf, state, &mut *mspconn, None)
+        .ok()
+        .and_then(|mut messages| messages.pop())
+        .unwrap_or_else(|| MavMessage::HEARTBEAT(HEARTBEAT_DATA::default()));
+    if let MavMessage::HEARTBEAT(ref mut data) = message {
+        data.system_status = MavState::MAV_STATE_POWEROFF;
+    }
+    if let Err(e) = mavconn.send(header, &message) {
+        warn!("unable to send final HEARTBEAT: {:?}", e);
+    }
+    if let Err(e) = mspconn.flush() {
+        warn!("unable to flush MSP connection: {:?}", e);
+    }
+}
+
+/// runs the bridge until SIGINT or SIGTERM is received
+pub fn event_loop(conf: &Config) -> io::Result<()> {
+    let (stop_tx, stop_rx) = smol::channel::bounded(1);
+    ctrlc::set_handler(move || {
+        let _ = stop_tx.try_send(());
+    })
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    // initializes the MSP connection, a simulation answers no MSP request at all
+    let mut mspconn: Box<dyn MspConnection + Send> = if conf.simulate {
+        info!("simulating a FC, MSP requests time out");
+        Box::new(ReplayConnection::default())
+    } else {
+        connect_msp_at_startup(conf, &stop_rx)?
+    };
+
+    let mut state = BridgeState::default();
+    if !conf.simulate {
+        match MspMessage::<MspFcVariant>::fetch(&mut mspconn) {
+            Ok(variant) => {
+                let variant = String::from_utf8_lossy(&variant.identifier).into_owned();
+                info!("FC variant is {}", variant);
+                state.fc_variant = Some(variant);
+            }
+            Err(e) => warn!("unable to fetch MspFcVariant: {:?}", e),
+        }
+        match MspMessage::<MspApiVersion>::fetch(&mut mspconn) {
+            Ok(version) => {
+                info!("MSP API version is {}.{}", version.major, version.minor);
+                state.api_version = Some((version.major, version.minor));
+            }
+            Err(e) => warn!("unable to fetch MspApiVersion: {:?}", e),
+        }
+        match MspMessage::fetch(&mut mspconn) {
+            Ok(payload) => {
+                state.craft_name = craft_name(&payload);
+                match &state.craft_name {
+                    Some(name) => info!("craft name is {}", name),
+                    None => info!("the FC has no craft name"),
+                }
+            }
+            Err(e) => warn!("unable to fetch MspName: {:?}", e),
+        }
+    }
+    let state = &state;
+    let mspconn = Arc::new(Mutex::new(mspconn));
+
+    let registry = if conf.simulate {
+        simulator::registry()
+    } else {
+        TranslatorRegistry::default()
+    };
+    info!("supported messages: {:?}", registry.ids());
 
     // initializes MAV connection
     info!("waiting for MAVLink connection");
-    let mavconn = Arc::new(mavlink::connect(&conf.mavlink_listen).unwrap());
+    let mavconn = Arc::new(fanout::connect(&conf.mavlink_listen)?);
 
-    info!("MAVLink connection opened on {}", &conf.mavlink_listen);
+    info!(
+        "MAVLink connection opened on {}",
+        conf.mavlink_listen.join(", ")
+    );
 
-    // initializes scheduler and inserts HEARTBEAT task
-    let schedule = Arc::new(Schedule::new(50));
-    schedule
-        .insert(1, 0)
-        .expect("unable to insert heartbeat in scheduler");
+    let mut header = mavlink::MavHeader::default();
+    header.system_id = conf.mavlink_system_id;
+    for message in craft_name_text(state) {
+        let _ = mavconn.send(&header, &message);
+    }
 
-    // inform about attitude on high frequency
-    schedule.insert(30, 30).unwrap();
+    let schedule = Arc::new(startup_schedule(conf)?);
+    log_rates(&schedule);
 
     // enters eventloop to process scheduled messages and incoming messages
     info!("starting reactor");
 
-    let mut tasks: Vec<_> = Vec::new();
+    let rc_override: Arc<Mutex<RcOverride>> = Arc::new(Mutex::new(None));
 
     smol::block_on(async {
+        if let Some(addr) = conf.metrics_addr {
+            smol::spawn({
+                let schedule = schedule.clone();
+                async move {
+                    if let Err(e) = metrics::serve(addr, schedule).await {
+                        error!("unable to serve metrics on {}: {}", addr, e);
+                    }
+                }
+            })
+            .detach();
+        }
+
         // Satisfie enqued tasks
         smol::spawn({
-            let conf = conf.clone();
+            let conf = Arc::new(conf.clone());
+            let state = Arc::new(state.clone());
             let mavconn = mavconn.clone();
+            let mspconn = mspconn.clone();
             let schedule = schedule.clone();
+            let registry = registry.clone();
             async move {
-                let mut header = mavlink::MavHeader::default();
-                header.system_id = conf.mavlink_system_id;
+                // last message sent per id, handed to the generator as context
+                let mut sent: HashMap<u32, MavMessage> = HashMap::new();
+                let mut backpressure = Backpressure::default();
+                let mut link = LinkMonitor::default();
                 loop {
                     let task = schedule.next().await;
                     let id = task;
-                    if let Some(generator) = generators.get(&id) {
-                        let message = generator(&conf, &mut mspconn, None)
-                            .expect("message could not be generated");
-                        let _ = mavconn.send(&header, &message);
+                    if backpressure.skip(id) {
+                        debug!("skipping message {}, its last generation overran", id);
+                        continue;
+                    }
+                    let started = Instant::now();
+                    if let Some(generator) = registry.get(id) {
+                        let (served, result) = with_msp(&mspconn, {
+                            let conf = conf.clone();
+                            let state = state.clone();
+                            let mavconn = mavconn.clone();
+                            move |mspconn| {
+                                let result = serve_slot(
+                                    &conf, &state, generator, mspconn, &**mavconn, &header,
+                                    &mut sent, id,
+                                );
+                                (sent, result)
+                            }
+                        })
+                        .await;
+                        sent = served;
+                        backpressure.record(id, started.elapsed(), schedule.minor_frame());
+                        if link.lost(&conf, &result) {
+                            warn!("MSP link lost: {:?}", result);
+                            announce(
+                                &**mavconn,
+                                &header,
+                                MavSeverity::MAV_SEVERITY_CRITICAL,
+                                "MSP link lost",
+                            );
+                            reconnect_msp(&conf, &mspconn, &**mavconn, &header).await;
+                        } else if let Err(e) = result {
+                            warn!("message {} could not be generated: {:?}", id, e);
+                        }
                     } else {
                         warn!("cannot process subscription for task {}", id);
                     }
@@ -88,20 +1179,392 @@ pub fn event_loop(conf: &Config) -> ! {
         })
         .detach();
 
+        // keep up RC overrides of the GCS, or release them if it goes silent
+        smol::spawn({
+            let conf = conf.clone();
+            let rc_override = rc_override.clone();
+            let mavconn = mavconn.clone();
+            let mspconn = mspconn.clone();
+            async move {
+                keep_rc_override(&conf, &rc_override, &mspconn, &**mavconn, &header).await;
+            }
+        })
+        .detach();
+
         // reac to incoming MAVLink messages
-        tasks.push(smol::spawn({
+        smol::spawn({
+            let conf = Arc::new(conf.clone());
+            let state = Arc::new(state.clone());
             let mavconn = mavconn.clone();
+            let mspconn = mspconn.clone();
             let schedule = schedule.clone();
+            let rc_override = rc_override.clone();
+            let registry = registry.clone();
             async move {
+                let mut upload = None;
+                let mut schedule_log = ScheduleLog::default();
+                // settings of the FC exposed as parameters, empty if it has none
+                let mut settings = Vec::new();
                 loop {
                     let mavconn_copy = mavconn.clone();
                     match smol::unblock(move || mavconn_copy.recv()).await {
-                        Ok((_header, msg)) => {
+                        Ok((sender, msg)) => {
+                            // the GCS keeps sending heartbeats, which flush the last change
+                            schedule_log.flush(&schedule);
                             match msg {
                                 MavMessage::HEARTBEAT(ref _msg) => {}
+                                MavMessage::GPS_INPUT(msg) => {
+                                    let state = state.clone();
+                                    let result = with_msp(&mspconn, move |mspconn| {
+                                        inject_gps(&state, mspconn, &msg)
+                                    })
+                                    .await;
+                                    if let Err(e) = result {
+                                        warn!("unable to inject GPS_INPUT: {:?}", e);
+                                    }
+                                }
+                                MavMessage::SET_GPS_GLOBAL_ORIGIN(ref msg)
+                                    if msg.target_system == conf.mavlink_system_id =>
+                                {
+                                    // the scheduled GPS_GLOBAL_ORIGIN confirms the new origin
+                                    state.gps_origin.set(GPS_GLOBAL_ORIGIN_DATA {
+                                        latitude: msg.latitude,
+                                        longitude: msg.longitude,
+                                        altitude: msg.altitude,
+                                    });
+                                    info!("GPS origin moved to {:?}", state.gps_origin.get());
+                                }
                                 MavMessage::MESSAGE_INTERVAL(ref msg) => {
-                                    let freq = (1_000_000f64 / msg.interval_us as f64) as u32;
-                                    schedule.insert(freq, msg.message_id.into()).unwrap();
+                                    let id = msg.message_id.into();
+                                    match message_interval_frequency(msg.interval_us, id) {
+                                        Some(freq) => {
+                                            if let Err(e) =
+                                                schedule_message(&schedule, &registry, freq, id)
+                                            {
+                                                warn!("unable to schedule message {}: {}", id, e);
+                                            }
+                                            schedule_log.changed(&schedule);
+                                        }
+                                        None => debug!(
+                                            "ignoring interval {} us of message {}",
+                                            msg.interval_us, id
+                                        ),
+                                    }
+                                }
+                                MavMessage::REQUEST_DATA_STREAM(ref msg) => {
+                                    // stopping a stream deletes its messages from the schedule
+                                    let freq = match msg.start_stop {
+                                        0 => 0.,
+                                        _ => msg.req_message_rate.into(),
+                                    };
+                                    for id in data_stream_ids(msg.req_stream_id) {
+                                        if let Err(e) =
+                                            schedule_message(&schedule, &registry, freq, id)
+                                        {
+                                            warn!("unable to schedule message {}: {}", id, e);
+                                        }
+                                    }
+                                    schedule_log.changed(&schedule);
+                                }
+                                MavMessage::MANUAL_CONTROL(msg) => {
+                                    let rc_override = rc_override.lock_arc().await;
+                                    let (conf, state) = (conf.clone(), state.clone());
+                                    let result = with_msp(&mspconn, move |mspconn| {
+                                        let mut rc_override = rc_override;
+                                        override_rc(&conf, &state, mspconn, &mut rc_override, |c| {
+                                            manual_control(&conf, &msg, c)
+                                        })
+                                    })
+                                    .await;
+                                    if let Err(e) = result {
+                                        warn!("unable to apply MANUAL_CONTROL: {:?}", e);
+                                    }
+                                }
+                                MavMessage::SET_ATTITUDE_TARGET(msg)
+                                    if msg.target_system == conf.mavlink_system_id =>
+                                {
+                                    let rc_override = rc_override.lock_arc().await;
+                                    let offboard = rc_override.is_some();
+                                    let feedback = attitude_target_feedback(&msg);
+                                    let (conf, state) = (conf.clone(), state.clone());
+                                    let result = with_msp(&mspconn, move |mspconn| {
+                                        let mut rc_override = rc_override;
+                                        override_rc(&conf, &state, mspconn, &mut rc_override, |c| {
+                                            attitude_target(&conf, &msg, c)
+                                        })
+                                    })
+                                    .await;
+                                    match result {
+                                        Ok(()) => {
+                                            if !offboard {
+                                                announce(
+                                                    &**mavconn,
+                                                    &header,
+                                                    MavSeverity::MAV_SEVERITY_INFO,
+                                                    "offboard attitude control active",
+                                                );
+                                            }
+                                            let _ = mavconn.send(&header, &feedback);
+                                        }
+                                        Err(e) => {
+                                            warn!("unable to apply SET_ATTITUDE_TARGET: {:?}", e)
+                                        }
+                                    }
+                                }
+                                MavMessage::RC_CHANNELS_OVERRIDE(msg) => {
+                                    let mut rc_override = rc_override.lock_arc().await;
+                                    if releases_all_channels(&msg) {
+                                        *rc_override = None;
+                                        continue;
+                                    }
+                                    let (conf, state) = (conf.clone(), state.clone());
+                                    let result = with_msp(&mspconn, move |mspconn| {
+                                        // the channels of the FC are only needed to release some
+                                        let fc = if releases_any_channel(&msg) {
+                                            fetch_rc(&conf, &state, mspconn)?
+                                        } else {
+                                            [0; 16]
+                                        };
+                                        override_rc(&conf, &state, mspconn, &mut rc_override, |c| {
+                                            rc_channels_override(&msg, &fc, c)
+                                        })
+                                    })
+                                    .await;
+                                    if let Err(e) = result {
+                                        warn!("unable to apply RC_CHANNELS_OVERRIDE: {:?}", e);
+                                    }
+                                }
+                                MavMessage::TIMESYNC(ref msg) => {
+                                    if let Some(message) = timesync(&conf, msg) {
+                                        let _ = mavconn.send(&header, &message);
+                                    }
+                                }
+                                MavMessage::PARAM_REQUEST_LIST(ref _msg) => {
+                                    settings = load_settings(&mspconn).await;
+                                    for (index, info) in settings.iter().enumerate() {
+                                        let message = setting_param(info, index, settings.len());
+                                        let _ = mavconn.send(&header, &message);
+                                    }
+                                    if settings.is_empty() {
+                                        match with_msp(&mspconn, fetch_params).await {
+                                            Ok(messages) => {
+                                                for message in messages {
+                                                    let _ = mavconn.send(&header, &message);
+                                                }
+                                            }
+                                            Err(e) => warn!("unable to fetch parameters: {:?}", e),
+                                        }
+                                    }
+                                }
+                                MavMessage::PARAM_REQUEST_READ(ref msg) => {
+                                    if settings.is_empty() {
+                                        settings = load_settings(&mspconn).await;
+                                    }
+                                    let name = from_param_id(&msg.param_id);
+                                    let index = msg.param_index;
+                                    let (result, read) = with_msp(&mspconn, {
+                                        let mut settings = std::mem::take(&mut settings);
+                                        let name = name.clone();
+                                        move |mspconn| {
+                                            let result =
+                                                read_param(mspconn, &mut settings, index, &name);
+                                            (result, settings)
+                                        }
+                                    })
+                                    .await;
+                                    settings = read;
+                                    let message = match result {
+                                        Ok(Some(message)) => message,
+                                        Ok(None) => statustext(
+                                            MavSeverity::MAV_SEVERITY_WARNING,
+                                            &unknown_param(index, &name),
+                                        ),
+                                        Err(e) => {
+                                            warn!("unable to read {}: {:?}", name, e);
+                                            continue;
+                                        }
+                                    };
+                                    let _ = mavconn.send(&header, &message);
+                                }
+                                MavMessage::PARAM_SET(ref msg)
+                                    if from_param_id(&msg.param_id) == PROFILE_PARAM =>
+                                {
+                                    let value = msg.param_value;
+                                    match with_msp(&mspconn, move |mspconn| {
+                                        select_profile(mspconn, value)
+                                    })
+                                    .await
+                                    {
+                                        Ok(messages) => {
+                                            for message in messages {
+                                                let _ = mavconn.send(&header, &message);
+                                            }
+                                        }
+                                        Err(e) => warn!("unable to select profile: {:?}", e),
+                                    }
+                                }
+                                MavMessage::PARAM_SET(ref msg) => {
+                                    if settings.is_empty() {
+                                        settings = load_settings(&mspconn).await;
+                                    }
+                                    let name = from_param_id(&msg.param_id);
+                                    let value = msg.param_value;
+                                    // the write is saved under the same lock it was made in
+                                    let (result, written) = with_msp(&mspconn, {
+                                        let conf = conf.clone();
+                                        let mut settings = std::mem::take(&mut settings);
+                                        let name = name.clone();
+                                        move |mspconn| {
+                                            let result =
+                                                write_param(mspconn, &mut settings, &name, value)
+                                                    .map(|message| {
+                                                        message.map(|message| {
+                                                            (
+                                                                message,
+                                                                persist_writes(&conf, mspconn),
+                                                            )
+                                                        })
+                                                    });
+                                            (result, settings)
+                                        }
+                                    })
+                                    .await;
+                                    settings = written;
+                                    let message = match result {
+                                        Ok(Some((message, saved))) => {
+                                            match saved {
+                                                Ok(messages) => {
+                                                    for message in messages {
+                                                        let _ = mavconn.send(&header, &message);
+                                                    }
+                                                }
+                                                Err(e) => warn!("unable to save {}: {:?}", name, e),
+                                            }
+                                            message
+                                        }
+                                        Ok(None) => statustext(
+                                            MavSeverity::MAV_SEVERITY_WARNING,
+                                            &format!("unknown parameter {}", name),
+                                        ),
+                                        Err(e) => {
+                                            warn!("unable to set {}: {:?}", name, e);
+                                            continue;
+                                        }
+                                    };
+                                    let _ = mavconn.send(&header, &message);
+                                }
+                                MavMessage::MISSION_REQUEST_LIST(ref _msg) => {
+                                    match with_msp(&mspconn, move |mspconn| {
+                                        mission_count(mspconn, &sender)
+                                    })
+                                    .await
+                                    {
+                                        Ok(message) => {
+                                            let _ = mavconn.send(&header, &message);
+                                        }
+                                        Err(e) => warn!("unable to count waypoints: {:?}", e),
+                                    }
+                                }
+                                MavMessage::MISSION_REQUEST(MISSION_REQUEST_DATA {
+                                    seq, ..
+                                })
+                                | MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                                    seq,
+                                    ..
+                                }) => {
+                                    match with_msp(&mspconn, move |mspconn| {
+                                        mission_item(mspconn, seq, &sender)
+                                    })
+                                    .await
+                                    {
+                                        Ok(message) => {
+                                            let _ = mavconn.send(&header, &message);
+                                        }
+                                        Err(e) => warn!("unable to fetch waypoint: {:?}", e),
+                                    }
+                                }
+                                MavMessage::MISSION_COUNT(ref msg) => {
+                                    let count = msg.count;
+                                    let (message, pending) = with_msp(&mspconn, {
+                                        let mut upload = upload.take();
+                                        move |mspconn| {
+                                            let message = handle_mission_count(
+                                                mspconn,
+                                                &mut upload,
+                                                count,
+                                                &sender,
+                                            );
+                                            (message, upload)
+                                        }
+                                    })
+                                    .await;
+                                    upload = pending;
+                                    let _ = mavconn.send(&header, &message);
+                                }
+                                MavMessage::MISSION_ITEM_INT(msg) => {
+                                    let (message, pending) = with_msp(&mspconn, {
+                                        let mut upload = upload.take();
+                                        move |mspconn| {
+                                            let message = handle_mission_item(
+                                                mspconn,
+                                                &mut upload,
+                                                &msg,
+                                                &sender,
+                                            );
+                                            (message, upload)
+                                        }
+                                    })
+                                    .await;
+                                    upload = pending;
+                                    if let Some(message) = message {
+                                        let _ = mavconn.send(&header, &message);
+                                    }
+                                }
+                                MavMessage::COMMAND_LONG(msg) => {
+                                    // known before a motor test spins the motor, so nothing can
+                                    // fail between starting and stopping it
+                                    let test_duration = motor_test_duration(&msg);
+                                    let messages = with_msp(&mspconn, {
+                                        let (conf, state) = (conf.clone(), state.clone());
+                                        let msg = msg.clone();
+                                        move |mspconn| handle_command(&conf, &state, mspconn, &msg)
+                                    })
+                                    .await;
+                                    let accepted = messages.iter().any(|m| {
+                                        matches!(m, MavMessage::COMMAND_ACK(ack)
+                                            if ack.result == MavResult::MAV_RESULT_ACCEPTED)
+                                    });
+                                    for message in messages {
+                                        let _ = mavconn.send(&header, &message);
+                                    }
+                                    match msg.command {
+                                        MavCmd::MAV_CMD_PREFLIGHT_CALIBRATION
+                                            if accepted && msg.param2 != 0. =>
+                                        {
+                                            let mavconn = mavconn.clone();
+                                            smol::spawn(async move {
+                                                mag_calibration_progress(&**mavconn, &header).await
+                                            })
+                                            .detach();
+                                        }
+                                        MavCmd::MAV_CMD_DO_MOTOR_TEST if accepted => {
+                                            let mspconn = mspconn.clone();
+                                            smol::spawn(async move {
+                                                Timer::after(test_duration).await;
+                                                let result = with_msp(&mspconn, stop_motors).await;
+                                                if let Err(e) = result {
+                                                    warn!("unable to stop motors: {:?}", e);
+                                                }
+                                            })
+                                            .detach();
+                                        }
+                                        // the link drops while the FC reboots, the scheduler
+                                        // reconnects once it finds the connection gone
+                                        MavCmd::MAV_CMD_PREFLIGHT_REBOOT_SHUTDOWN if accepted => {
+                                            *mspconn.lock().await = Box::new(Disconnected);
+                                        }
+                                        _ => {}
+                                    }
                                 }
                                 msg => {
                                     warn!("received MavMessage, don't know what to do: {:?}", msg);
@@ -112,9 +1575,480 @@ pub fn event_loop(conf: &Config) -> ! {
                     }
                 }
             }
-        }));
+        })
+        .detach();
 
-        future::join_all(tasks).await;
-        panic!("event loop broke")
+        let _ = stop_rx.recv().await;
+        info!("shutting down");
+        shutdown(conf, state, &mspconn, &**mavconn, &header).await;
+        Ok(())
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::translator::test::conf;
+    use std::sync::Mutex as StdMutex;
+
+    /// records every sent message
+    struct Recorder(StdMutex<Vec<MavMessage>>);
+
+    impl MavConnection<MavMessage> for Recorder {
+        fn recv(&self) -> Result<(MavHeader, MavMessage), mavlink::error::MessageReadError> {
+            Err(io::Error::from(io::ErrorKind::WouldBlock).into())
+        }
+
+        fn send(&self, _header: &MavHeader, data: &MavMessage) -> io::Result<()> {
+            self.0.lock().unwrap().push(data.clone());
+            Ok(())
+        }
+
+        fn set_protocol_version(&mut self, _version: mavlink::MavlinkVersion) {}
+
+        fn get_protocol_version(&self) -> mavlink::MavlinkVersion {
+            mavlink::MavlinkVersion::V2
+        }
+    }
+
+    fn failing(
+        _conf: &Config,
+        _state: &BridgeState,
+        _mspconn: &mut dyn MspConnection,
+        _context: Option<&MavMessage>,
+    ) -> io::Result<Vec<MavMessage>> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checksum mismatch",
+        ))
+    }
+
+    fn link_lost(
+        _conf: &Config,
+        _state: &BridgeState,
+        _mspconn: &mut dyn MspConnection,
+        _context: Option<&MavMessage>,
+    ) -> io::Result<Vec<MavMessage>> {
+        Err(io::Error::from(io::ErrorKind::TimedOut))
+    }
+
+    fn working(
+        _conf: &Config,
+        _state: &BridgeState,
+        _mspconn: &mut dyn MspConnection,
+        _context: Option<&MavMessage>,
+    ) -> io::Result<Vec<MavMessage>> {
+        Ok(vec![statustext(MavSeverity::MAV_SEVERITY_INFO, "ok")])
+    }
+
+    fn batch(
+        _conf: &Config,
+        _state: &BridgeState,
+        _mspconn: &mut dyn MspConnection,
+        _context: Option<&MavMessage>,
+    ) -> io::Result<Vec<MavMessage>> {
+        Ok(vec![
+            MavMessage::HEARTBEAT(HEARTBEAT_DATA::default()),
+            MavMessage::SYSTEM_TIME(SYSTEM_TIME_DATA::default()),
+        ])
+    }
+
+    /// a FC which never answers, each read blocking for the read timeout
+    struct Silent;
+
+    impl Read for Silent {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(100));
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    impl Write for Silent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MspConnection for Silent {}
+
+    #[test]
+    fn failing_generator_is_skipped() {
+        let conf = conf(&[]);
+        let mut mspconn = io::Cursor::new(Vec::new());
+        let mavconn = Recorder(StdMutex::new(Vec::new()));
+        let header = MavHeader::default();
+        let mut sent = HashMap::new();
+
+        let mut serve = |generator: GeneratorFn, id| {
+            serve_slot(
+                &conf,
+                &BridgeState::default(),
+                generator,
+                &mut mspconn,
+                &mavconn,
+                &header,
+                &mut sent,
+                id,
+            )
+        };
+        assert!(serve(failing, 1).is_ok());
+        assert!(serve(working, 2).is_ok());
+        assert!(serve(failing, 1).is_ok());
+        assert!(serve(link_lost, 3).is_err());
+
+        assert_eq!(mavconn.0.lock().unwrap().len(), 1);
+        assert_eq!(sent.keys().collect::<Vec<_>>(), vec![&253]);
+    }
+
+    #[test]
+    fn stale_attitude_is_suppressed() {
+        let conf = conf(&["--telemetry-timeout-ms", "0"]);
+        std::thread::sleep(Duration::from_millis(1));
+        let mut mspconn = io::Cursor::new(Vec::new());
+        let mavconn = Recorder(StdMutex::new(Vec::new()));
+        let mut sent = HashMap::new();
+
+        for id in &[30, 253] {
+            let result = serve_slot(
+                &conf,
+                &BridgeState::default(),
+                working,
+                &mut mspconn,
+                &mavconn,
+                &MavHeader::default(),
+                &mut sent,
+                *id,
+            );
+            assert!(result.is_ok());
+        }
+        assert_eq!(mavconn.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn batch_is_sent_at_once() {
+        let conf = conf(&[]);
+        let mut mspconn = io::Cursor::new(Vec::new());
+        let mavconn = Recorder(StdMutex::new(Vec::new()));
+        let mut sent = HashMap::new();
+
+        let result = serve_slot(
+            &conf,
+            &BridgeState::default(),
+            batch,
+            &mut mspconn,
+            &mavconn,
+            &MavHeader::default(),
+            &mut sent,
+            0,
+        );
+        assert!(result.is_ok());
+        let ids: Vec<u32> = mavconn
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.message_id())
+            .collect();
+        assert_eq!(ids, vec![0, 2]);
+        assert!(sent.contains_key(&0) && sent.contains_key(&2));
+    }
+
+    #[test]
+    fn unsupported_messages_are_not_scheduled() {
+        let mut registry = TranslatorRegistry::new();
+        registry.register(2, working, &[]).register(1, failing, &[]);
+        assert_eq!(registry.ids(), vec![1, 2]);
+        assert!(registry.supports(2));
+        assert!(!registry.supports(30));
+
+        let schedule = Schedule::new(50);
+        assert!(schedule_message(&schedule, &registry, 10., 2).is_ok());
+        assert!(schedule_message(&schedule, &registry, 10., 30).is_err());
+        assert_eq!(schedule.count(&2), 10);
+        assert_eq!(schedule.count(&30), 0);
+
+        // removing is always fine
+        assert!(schedule_message(&schedule, &registry, 0., 2).is_ok());
+        assert!(schedule_message(&schedule, &registry, 0., 30).is_ok());
+        assert_eq!(schedule.count(&2), 0);
+
+        assert!(TranslatorRegistry::default().supports(30));
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_rate() {
+        let conf = conf(&[]);
+        let registry = TranslatorRegistry::default();
+        let schedule = startup_schedule(&conf).unwrap();
+        assert_eq!(schedule.count(&30), 30);
+
+        // GCSs repeat their requests, which must not add up
+        for _ in 0..3 {
+            schedule_message(&schedule, &registry, 4., 30).unwrap();
+            assert_eq!(schedule.count(&30), 4);
+        }
+        // a rate which does not fit keeps the previous one
+        assert!(schedule_message(&schedule, &registry, 50., 30).is_err());
+        assert_eq!(schedule.count(&30), 4);
+    }
+
+    #[test]
+    fn message_interval_frequencies() {
+        assert_eq!(message_interval_frequency(100_000, 30), Some(10.));
+        assert_eq!(message_interval_frequency(-1, 30), Some(0.));
+        assert_eq!(message_interval_frequency(0, 30), Some(30.));
+        assert_eq!(message_interval_frequency(0, 0), Some(1.));
+        // neither a default rate nor a valid interval
+        assert_eq!(message_interval_frequency(0, 33), None);
+        assert_eq!(message_interval_frequency(-2, 30), None);
+    }
+
+    #[test]
+    fn startup_schedule_fits() {
+        let schedule = startup_schedule(&conf(&[])).unwrap();
+        for (_, id) in STARTUP_SCHEDULE {
+            assert!(TranslatorRegistry::default().supports(*id));
+            assert!(simulator::registry().supports(*id));
+        }
+        assert_eq!(schedule.count(&30), 30);
+        assert_eq!(schedule.count(&0), 1);
+
+        // 30 Hz ATTITUDE does not fit into 20 slots per second
+        let e = startup_schedule(&conf(&["--schedule-slots", "20"]))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(startup_schedule(&conf(&["--schedule-slots", "0"])).is_err());
+        let schedule = startup_schedule(&conf(&["--schedule-slots", "100"])).unwrap();
+        assert_eq!(schedule.count(&30), 30);
+        for period in &["0", "-1", "NaN", "inf"] {
+            let period = format!("--schedule-period={}", period);
+            let e = startup_schedule(&conf(&[&period])).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn registry_lists_names_and_polls() {
+        let registry = TranslatorRegistry::default();
+        for id in registry.ids() {
+            assert!(message_name(id).is_some(), "no name for message {}", id);
+        }
+        assert_eq!(message_name(30).as_deref(), Some("ATTITUDE"));
+        assert_eq!(registry.polls(30), &[("MspAttitude", MspAttitude::ID)]);
+        assert!(registry.polls(2).is_empty());
+        assert!(registry.polls(1000).is_empty());
+    }
+
+    #[test]
+    fn failsafe_rc_frame() {
+        let channels = [1800u16; 16];
+        assert_eq!(failsafe_rc(&conf(&[]), channels), None);
+
+        let conf = conf(&["--rc-failsafe", "1500,1500,1000"]);
+        let failsafe = failsafe_rc(&conf, channels).unwrap();
+        assert_eq!(failsafe[..4], [1500, 1500, 1000, 1800]);
+    }
+
+    #[test]
+    fn param_read_by_index_or_name() {
+        let mut mspconn = ReplayConnection::default();
+        let mut read = |index, name| read_param(&mut mspconn, &mut [], index, name);
+        assert!(read(PARAM_COUNT as i16, "").unwrap().is_none());
+        assert!(read(-1, "NO_SUCH_PARAM").unwrap().is_none());
+        // known parameters are read from the FC, which does not answer
+        assert!(read(0, "NO_SUCH_PARAM").is_err());
+        assert!(read(-1, "ROLL_P").is_err());
+        assert!(read(-1, PROFILE_PARAM).is_err());
+
+        assert_eq!(unknown_param(99, ""), "unknown parameter index 99");
+        assert_eq!(unknown_param(-1, "FOO"), "unknown parameter FOO");
+    }
+
+    #[test]
+    fn rc_overrides_are_coalesced() {
+        let conf = conf(&[]);
+        // the FC does not answer, so the overrides must not reach the MSP link
+        let mut mspconn = ReplayConnection::default();
+        let state = BridgeState::default();
+        let mut rc_override: RcOverride = Some((Instant::now(), [1500; 16]));
+        for value in 1600..1610 {
+            override_rc(&conf, &state, &mut mspconn, &mut rc_override, |c| {
+                c[0] = value
+            })
+            .unwrap();
+        }
+        override_rc(&conf, &state, &mut mspconn, &mut rc_override, |c| {
+            c[1] = 1400
+        })
+        .unwrap();
+        let (_, channels) = rc_override.unwrap();
+        assert_eq!(channels[..3], [1609, 1400, 1500]);
+    }
+
+    #[test]
+    fn every_command_is_acknowledged() {
+        let conf = conf(&[]);
+        let state = BridgeState::default();
+        let mut mspconn = ReplayConnection::default();
+        let mut result_of = |command| {
+            let msg = COMMAND_LONG_DATA {
+                command,
+                ..Default::default()
+            };
+            match handle_command(&conf, &state, &mut mspconn, &msg).last() {
+                Some(MavMessage::COMMAND_ACK(ack)) if ack.command == command => ack.result,
+                other => panic!("{:?} not acknowledged: {:?}", command, other),
+            }
+        };
+        assert_eq!(
+            result_of(MavCmd::MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES),
+            MavResult::MAV_RESULT_ACCEPTED
+        );
+        // the FC does not answer, so it may once the link is back
+        assert_eq!(
+            result_of(MavCmd::MAV_CMD_COMPONENT_ARM_DISARM),
+            MavResult::MAV_RESULT_TEMPORARILY_REJECTED
+        );
+        assert_eq!(
+            result_of(MavCmd::MAV_CMD_NAV_LAND),
+            MavResult::MAV_RESULT_UNSUPPORTED
+        );
+    }
+
+    #[test]
+    fn overrun_skips_next_occurrences() {
+        let slot = Duration::from_millis(20);
+        let mut backpressure = Backpressure::default();
+        backpressure.record(30, Duration::from_millis(15), slot);
+        assert!(!backpressure.skip(30));
+
+        let overruns = MSP_STATS.slot_overruns.load(Ordering::Relaxed);
+        backpressure.record(30, Duration::from_millis(45), slot);
+        assert!(MSP_STATS.slot_overruns.load(Ordering::Relaxed) > overruns);
+        assert!(!backpressure.skip(33));
+        assert!(backpressure.skip(30));
+        assert!(backpressure.skip(30));
+        assert!(!backpressure.skip(30));
+    }
+
+    #[test]
+    fn schedule_log_is_rate_limited() {
+        let schedule = Schedule::new(50);
+        schedule.insert(1., 0).unwrap();
+        schedule.insert(10., 30).unwrap();
+        assert_eq!(rates_summary(&schedule), "0: 1 Hz, 30: 10 Hz");
+
+        let mut log = ScheduleLog::default();
+        assert!(log.changed(&schedule));
+        assert!(!log.changed(&schedule));
+        assert!(!log.flush(&schedule));
+        log.logged = Some(Instant::now() - SCHEDULE_LOG_INTERVAL);
+        assert!(log.flush(&schedule));
+        assert!(!log.flush(&schedule));
+    }
+
+    #[test]
+    fn data_stream_groups() {
+        let position = MavDataStream::MAV_DATA_STREAM_POSITION as u8;
+        assert_eq!(data_stream_ids(position), vec![32, 33]);
+        let extra1 = MavDataStream::MAV_DATA_STREAM_EXTRA1 as u8;
+        assert_eq!(data_stream_ids(extra1), vec![30, 31]);
+        let raw_controller = MavDataStream::MAV_DATA_STREAM_RAW_CONTROLLER as u8;
+        assert!(data_stream_ids(raw_controller).is_empty());
+
+        let all = data_stream_ids(MavDataStream::MAV_DATA_STREAM_ALL as u8);
+        for (stream, ids) in DATA_STREAMS {
+            assert_eq!(&data_stream_ids(*stream as u8)[..], *ids);
+            assert!(ids.iter().all(|id| all.contains(id)));
+        }
+    }
+
+    #[test]
+    fn silent_fc_does_not_delay_the_gcs() {
+        let mspconn: SharedMsp = Arc::new(Mutex::new(Box::new(Silent)));
+        let schedule = Arc::new(Schedule::new(50));
+        schedule.insert(50., 0).unwrap();
+        let (tx, rx) = smol::channel::unbounded();
+        thread::spawn(move || {
+            for _ in 0..20 {
+                thread::sleep(Duration::from_millis(10));
+                let _ = tx.try_send(Instant::now());
+            }
+        });
+
+        let latency = smol::block_on(async {
+            let scheduler = smol::spawn({
+                let mspconn = mspconn.clone();
+                let schedule = schedule.clone();
+                async move {
+                    for _ in 0..5 {
+                        schedule.next().await;
+                        let _ = with_msp(&mspconn, |mut mspconn| {
+                            MspMessage::<MspAttitude>::fetch(&mut mspconn)
+                        })
+                        .await;
+                    }
+                }
+            });
+            // handled on the executor of the schedule, like messages of the GCS
+            let latency = smol::spawn(async move {
+                let mut latency = Duration::default();
+                while let Ok(sent) = rx.recv().await {
+                    latency = latency.max(sent.elapsed());
+                }
+                latency
+            })
+            .await;
+            scheduler.cancel().await;
+            latency
+        });
+        // each round-trip takes five slots, yet incoming messages are handled within one
+        assert!(latency < schedule.minor_frame(), "{:?}", latency);
+    }
+
+    #[test]
+    fn link_loss() {
+        let timeout = || Err(io::Error::from(io::ErrorKind::TimedOut));
+        let conf = conf(&[]);
+        let mut link = LinkMonitor::default();
+        assert!(link.lost(&conf, &Err(io::ErrorKind::BrokenPipe.into())));
+        // a FC missing a few answers is not lost
+        assert!(!link.lost(&conf, &timeout()));
+        assert!(!link.lost(&conf, &timeout()));
+        assert!(!link.lost(&conf, &Ok(())));
+        assert!(!link.lost(&conf, &timeout()));
+        assert!(!link.lost(&conf, &timeout()));
+        assert!(link.lost(&conf, &timeout()));
+        assert!(!link.lost(&conf, &timeout()));
+
+        let mut replaying = conf.clone();
+        replaying.msp_connection = None;
+        replaying.replay = Some("recording.log".to_string());
+        let mut link = LinkMonitor::default();
+        for _ in 0..LINK_LOSS_TIMEOUTS {
+            assert!(!link.lost(&replaying, &timeout()));
+        }
+    }
+
+    #[test]
+    fn rebooting_fc_is_lost() {
+        // a reboot leaves the connection disconnected for the scheduler to reconnect
+        let conf = conf(&[]);
+        let result = serve_slot(
+            &conf,
+            &BridgeState::default(),
+            heartbeat,
+            &mut Disconnected,
+            &Recorder(StdMutex::new(Vec::new())),
+            &MavHeader::default(),
+            &mut HashMap::new(),
+            0,
+        );
+        assert!(LinkMonitor::default().lost(&conf, &result));
+    }
+}
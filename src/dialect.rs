@@ -0,0 +1,92 @@
+//! The MAVLink dialect spoken on the MAVLink connection
+//!
+//! The translators build and handle the messages of the `common` dialect. Built with the
+//! `ardupilotmega` feature, the bridge speaks the `ardupilotmega` dialect instead, which wraps
+//! the common messages in its own `MavMessage`. `connect` hides the difference, so everything
+//! else keeps working on common messages.
+
+use std::io;
+
+use mavlink::common;
+use mavlink::MavConnection;
+
+/// message type of the dialect selected at build time
+#[cfg(not(feature = "ardupilotmega"))]
+pub use mavlink::common::MavMessage;
+
+/// message type of the dialect selected at build time
+#[cfg(feature = "ardupilotmega")]
+pub use mavlink::ardupilotmega::MavMessage;
+
+/// a MAVLink connection carrying the common messages
+pub type CommonConnection = dyn MavConnection<common::MavMessage> + Sync + Send;
+
+/// opens a MAVLink connection speaking the dialect selected at build time, see
+/// `mavlink::connect` for the address format
+#[cfg(not(feature = "ardupilotmega"))]
+pub fn connect(address: &str) -> io::Result<Box<CommonConnection>> {
+    mavlink::connect::<MavMessage>(address)
+}
+
+/// opens a MAVLink connection speaking the dialect selected at build time, see
+/// `mavlink::connect` for the address format
+#[cfg(feature = "ardupilotmega")]
+pub fn connect(address: &str) -> io::Result<Box<CommonConnection>> {
+    let inner = mavlink::connect::<MavMessage>(address)?;
+    Ok(Box::new(DialectConnection { inner }))
+}
+
+/// wraps a common message into a message of the dialect
+#[cfg(feature = "ardupilotmega")]
+pub fn from_common(msg: common::MavMessage) -> MavMessage {
+    MavMessage::common(msg)
+}
+
+/// Speaks the dialect on `inner` while carrying common messages
+///
+/// Messages specific to the dialect are received but ignored.
+#[cfg(feature = "ardupilotmega")]
+pub struct DialectConnection {
+    inner: Box<dyn MavConnection<MavMessage> + Sync + Send>,
+}
+
+#[cfg(feature = "ardupilotmega")]
+impl MavConnection<common::MavMessage> for DialectConnection {
+    fn recv(
+        &self,
+    ) -> Result<(mavlink::MavHeader, common::MavMessage), mavlink::error::MessageReadError> {
+        loop {
+            let (header, msg) = self.inner.recv()?;
+            match msg {
+                MavMessage::common(msg) => return Ok((header, msg)),
+                msg => debug!("ignoring {:?}", msg),
+            }
+        }
+    }
+
+    fn send(&self, header: &mavlink::MavHeader, data: &common::MavMessage) -> io::Result<()> {
+        self.inner.send(header, &from_common(data.clone()))
+    }
+
+    fn set_protocol_version(&mut self, version: mavlink::MavlinkVersion) {
+        self.inner.set_protocol_version(version)
+    }
+
+    fn get_protocol_version(&self) -> mavlink::MavlinkVersion {
+        self.inner.get_protocol_version()
+    }
+}
+
+#[cfg(all(test, feature = "ardupilotmega"))]
+mod test {
+    use super::*;
+    use mavlink::Message;
+
+    #[test]
+    fn common_messages_are_wrapped() {
+        let heartbeat = common::MavMessage::HEARTBEAT(common::HEARTBEAT_DATA::default());
+        let wrapped = from_common(heartbeat.clone());
+        assert_eq!(wrapped.message_id(), heartbeat.message_id());
+        assert_eq!(wrapped, MavMessage::common(heartbeat));
+    }
+}
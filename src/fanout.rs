@@ -0,0 +1,211 @@
+//! Several MAVLink endpoints behaving as one connection, e.g. a GCS and a logger
+//!
+//! Every message is sent to all endpoints, and the messages received on any of them are merged
+//! into one stream. An endpoint which fails is skipped until it works again, the others are not
+//! affected.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use mavlink::common::MavMessage;
+use mavlink::error::MessageReadError;
+use mavlink::{MavConnection, MavHeader, MavlinkVersion};
+
+use crate::dialect::{self, CommonConnection};
+
+/// pause after a failed receive, so a dead endpoint does not spin
+const RECV_BACKOFF: Duration = Duration::from_secs(1);
+
+/// One of the endpoints of a `FanOut`
+struct Endpoint {
+    address: String,
+    conn: Arc<CommonConnection>,
+    /// whether the last send failed, to warn once per failure rather than per message
+    failing: AtomicBool,
+}
+
+/// Sends to and receives from several MAVLink endpoints
+pub struct FanOut {
+    endpoints: Vec<Endpoint>,
+    incoming: Mutex<Receiver<(MavHeader, MavMessage)>>,
+}
+
+/// receives the messages of one endpoint until the `FanOut` is dropped
+fn forward(address: String, conn: Arc<CommonConnection>, tx: Sender<(MavHeader, MavMessage)>) {
+    loop {
+        match conn.recv() {
+            Ok(message) => {
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+            Err(MessageReadError::Io(e)) => {
+                warn!("unable to receive from {}: {}", address, e);
+                thread::sleep(RECV_BACKOFF);
+            }
+            Err(e) => debug!("ignoring invalid message from {}: {:?}", address, e),
+        }
+    }
+}
+
+impl FanOut {
+    /// merges the already opened `endpoints`, given with their address
+    pub fn new(endpoints: Vec<(String, Box<CommonConnection>)>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(address, conn)| {
+                let conn: Arc<CommonConnection> = conn.into();
+                thread::spawn({
+                    let (address, conn, tx) = (address.clone(), conn.clone(), tx.clone());
+                    move || forward(address, conn, tx)
+                });
+                Endpoint {
+                    address,
+                    conn,
+                    failing: AtomicBool::new(false),
+                }
+            })
+            .collect();
+        FanOut {
+            endpoints,
+            incoming: Mutex::new(rx),
+        }
+    }
+}
+
+impl MavConnection<MavMessage> for FanOut {
+    fn recv(&self) -> Result<(MavHeader, MavMessage), MessageReadError> {
+        let incoming = self
+            .incoming
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "poisoned lock"))?;
+        incoming
+            .recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe).into())
+    }
+
+    /// succeeds if at least one endpoint took the message
+    fn send(&self, header: &MavHeader, data: &MavMessage) -> io::Result<()> {
+        let mut result = Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no MAVLink endpoint",
+        ));
+        for endpoint in &self.endpoints {
+            match endpoint.conn.send(header, data) {
+                Ok(()) => {
+                    if endpoint.failing.swap(false, Ordering::Relaxed) {
+                        info!("sending to {} again", endpoint.address);
+                    }
+                    result = Ok(());
+                }
+                Err(e) => {
+                    if !endpoint.failing.swap(true, Ordering::Relaxed) {
+                        warn!("unable to send to {}: {}", endpoint.address, e);
+                    }
+                    if result.is_err() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// the endpoints are shared with their receiving threads, so they keep the version they
+    /// were opened with
+    fn set_protocol_version(&mut self, _version: MavlinkVersion) {
+        warn!("unable to change the MAVLink version of multiple endpoints");
+    }
+
+    fn get_protocol_version(&self) -> MavlinkVersion {
+        self.endpoints
+            .first()
+            .map_or(MavlinkVersion::V2, |e| e.conn.get_protocol_version())
+    }
+}
+
+/// opens all `addresses`, as one connection if there are several
+pub fn connect(addresses: &[String]) -> io::Result<Box<CommonConnection>> {
+    match addresses {
+        [address] => dialect::connect(address),
+        _ => {
+            let endpoints = addresses
+                .iter()
+                .map(|address| Ok((address.clone(), dialect::connect(address)?)))
+                .collect::<io::Result<_>>()?;
+            Ok(Box::new(FanOut::new(endpoints)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mavlink::common::{HEARTBEAT_DATA, PING_DATA};
+
+    /// an endpoint which records what is sent and receives a single message
+    struct Loopback {
+        sent: Arc<Mutex<Vec<MavMessage>>>,
+        incoming: Mutex<Option<MavMessage>>,
+        dead: bool,
+    }
+
+    impl MavConnection<MavMessage> for Loopback {
+        fn recv(&self) -> Result<(MavHeader, MavMessage), MessageReadError> {
+            match self.incoming.lock().unwrap().take() {
+                Some(message) => Ok((MavHeader::default(), message)),
+                None => loop {
+                    thread::park();
+                },
+            }
+        }
+
+        fn send(&self, _header: &MavHeader, data: &MavMessage) -> io::Result<()> {
+            if self.dead {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.sent.lock().unwrap().push(data.clone());
+            Ok(())
+        }
+
+        fn set_protocol_version(&mut self, _version: MavlinkVersion) {}
+
+        fn get_protocol_version(&self) -> MavlinkVersion {
+            MavlinkVersion::V2
+        }
+    }
+
+    #[test]
+    fn fan_out_to_all_endpoints() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let endpoint = |message: Option<MavMessage>, dead: bool| {
+            let conn: Box<CommonConnection> = Box::new(Loopback {
+                sent: sent.clone(),
+                incoming: Mutex::new(message),
+                dead,
+            });
+            ("loopback".to_string(), conn)
+        };
+        let ping = MavMessage::PING(PING_DATA::default());
+        let fanout = FanOut::new(vec![
+            endpoint(Some(ping.clone()), false),
+            endpoint(None, true),
+            endpoint(None, false),
+        ]);
+
+        let heartbeat = MavMessage::HEARTBEAT(HEARTBEAT_DATA::default());
+        assert!(fanout.send(&MavHeader::default(), &heartbeat).is_ok());
+        assert_eq!(*sent.lock().unwrap(), vec![heartbeat.clone(), heartbeat]);
+
+        let (_, received) = fanout.recv().unwrap();
+        assert_eq!(received, ping);
+
+        let dead = FanOut::new(vec![endpoint(None, true)]);
+        assert!(dead.send(&MavHeader::default(), &ping).is_err());
+    }
+}
@@ -7,55 +7,285 @@
 extern crate log;
 
 use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
 use std::time::Instant;
 
-use clap::Clap;
+use clap::{AppSettings, Clap};
 
+mod config_file;
 mod core;
+mod dialect;
+mod fanout;
+mod metrics;
 mod msp;
+mod replay;
 mod scheduler;
+mod simulator;
 mod translator;
+mod transport;
 
 /// A communication bridge to masquerade a MSP capable drone as MAVLink drone.
 ///
 /// The translation is described as a map of functions. A MAVLink message ID maps to a generator
 /// functions. Said generator functions receive a reference to the static Config, a reference to
-/// the MSP stub to poll information from the actual drone and an optional context, consisting of
-/// the logically previous MAVLink message.
+/// the state learned while running, a reference to the MSP stub to poll information from the
+/// actual drone and an optional context, consisting of the logically previous MAVLink message.
 #[derive(Clone, Debug, Clap)]
-#[clap(version, author, about)]
+#[clap(
+    version,
+    author,
+    about,
+    setting = AppSettings::AllArgsOverrideSelf,
+    setting = AppSettings::SubcommandsNegateReqs
+)]
 pub struct Config {
+    /// TOML file providing defaults for the MAVLink and MSP connection flags, which are
+    /// overridden by the flags given on the command line
+    #[clap(long)]
+    config: Option<String>,
+
     /// MAVLink listen address. Can be TCP/UDP/Serialport/File. For further information, see
-    /// https://docs.rs/mavlink/0/mavlink/fn.connect.html
-    #[clap(short = "l", long, default_value = "udpbcast:0.0.0.0:14550")]
-    mavlink_listen: String,
+    /// https://docs.rs/mavlink/0/mavlink/fn.connect.html. Given repeatedly, every message is
+    /// sent to all of them and commands are accepted from any of them
+    #[clap(
+        short = "l",
+        long,
+        default_value = "udpbcast:0.0.0.0:14550",
+        number_of_values = 1
+    )]
+    mavlink_listen: Vec<String>,
+
+    /// airframe reported to the GCS, one of generic, fixed-wing, quadrotor, coaxial, helicopter,
+    /// airship, ground-rover, surface-boat, submarine, hexarotor, octorotor, tricopter,
+    /// flapping-wing, vtol-duorotor, vtol-quadrotor or vtol-tiltrotor, ignoring case
+    #[clap(long, default_value = "generic")]
+    mav_type: translator::VehicleType,
+
+    /// autopilot reported to the GCS, one of generic, ardupilotmega, px4 or invalid, ignoring
+    /// case. Defaults to ardupilotmega for INAV and generic otherwise
+    #[clap(long)]
+    autopilot: Option<translator::Autopilot>,
 
     /// MAVLink system id of masked drone.
     #[clap(short = "i", long, default_value = "1")]
     mavlink_system_id: u8,
 
-    /// serialport to MSP FC
-    #[clap(short = "s", long = "serial")]
-    msp_serialport: String,
+    /// connection to MSP FC. Can be a serialport (`serial:/dev/ttyUSB0:115200` or just the
+    /// path), `tcp:192.168.1.1:5761` or `udp:192.168.1.1:5761`
+    #[clap(
+        short = "s",
+        long = "serial",
+        required_unless_one = &["replay", "simulate"]
+    )]
+    msp_connection: Option<transport::MspTransport>,
+
+    /// answer MSP requests with the responses recorded in this file instead of connecting to a FC
+    #[clap(long, conflicts_with = "msp-connection")]
+    replay: Option<String>,
+
+    /// send simulated telemetry of a drone circling its home instead of connecting to a FC, to
+    /// try a GCS and the schedule without hardware
+    #[clap(long, conflicts_with_all = &["msp-connection", "replay"])]
+    simulate: bool,
+
+    /// record the MSP traffic to this file, for replay with `--replay`
+    #[clap(long)]
+    record_msp: Option<String>,
 
-    /// baudrate for given serialport
+    /// baudrate for given serialport, unless specified in the connection
     #[clap(short = "b", long = "baud", default_value = "115200")]
     msp_baud: u32,
 
+    /// the FC only speaks MSPv1, so MSPv2 requests are tunneled through `MSP_V2_FRAME`
+    #[clap(long)]
+    msp_v1_only: bool,
+
+    /// discard the MSP input left over by a failed request, e.g. one which timed out or got a
+    /// corrupt response, before the next request, so a late response does not delay the next one
+    #[clap(long)]
+    msp_drain_after_error: bool,
+
+    /// time in milliseconds to wait for a MSP response, must not be 0. Each attempt of a fetch
+    /// holds up the schedule for up to this long, so values above one minor frame
+    /// (`--schedule-period` / `--schedule-slots`) delay the messages scheduled after it
+    #[clap(long, default_value = "100")]
+    msp_timeout_ms: NonZeroU64,
+
+    /// time in milliseconds to keep retrying the MSP connection at startup, e.g. while the FC
+    /// still boots, 0 gives up after the first attempt
+    #[clap(long, default_value = "30000")]
+    startup_timeout_ms: u64,
+
+    /// number of times a failed MSP request is repeated
+    #[clap(long, default_value = "1")]
+    msp_retries: usize,
+
+    /// time in milliseconds a fetched MSP payload is reused by other messages, which defaults to
+    /// one minor frame of the default schedule, 0 disables the reuse
+    #[clap(long, default_value = "20")]
+    fetch_ttl_ms: u64,
+
+    /// time in milliseconds without a successful MSP fetch after which the telemetry is stale,
+    /// HEARTBEAT then reports a critical state and position and attitude are no longer sent
+    #[clap(long, default_value = "3000")]
+    telemetry_timeout_ms: u64,
+
+    /// duration of one major frame of the schedule in seconds, the lowest possible message
+    /// frequency is one per major frame
+    #[clap(long, default_value = "1")]
+    schedule_period: f64,
+
+    /// number of slots a major frame of the schedule is divided into, each sends one message.
+    /// Every message gets whole slots per major frame, so the highest possible frequency is
+    /// `--schedule-slots` / `--schedule-period`, e.g. 50 Hz with the defaults
+    #[clap(long, default_value = "50")]
+    schedule_slots: usize,
+
+    /// save parameters written by the GCS to the EEPROM of the FC, which wears the flash when
+    /// tuning live
+    #[clap(long)]
+    persist_writes: bool,
+
+    /// RC channel of the arm switch, counting from 1
+    #[clap(long, default_value = "5")]
+    arm_channel: usize,
+
+    /// value of the arm switch channel to arm, in microseconds
+    #[clap(long, default_value = "2000")]
+    arm_high_us: u16,
+
+    /// value of the arm switch channel to disarm, in microseconds
+    #[clap(long, default_value = "1000")]
+    arm_low_us: u16,
+
+    /// highest throttle in percent allowed for MAV_CMD_DO_MOTOR_TEST
+    #[clap(long, default_value = "30")]
+    motor_test_max_percent: f32,
+
+    /// RC channels driven by the x (pitch), y (roll), z (thrust) and r (yaw) axes of
+    /// MANUAL_CONTROL, counting from 1
+    #[clap(long, default_value = "2,1,3,4")]
+    manual_control_axes: translator::AxisChannels,
+
+    /// first RC channel driven by the buttons of MANUAL_CONTROL, one channel per button
+    #[clap(long)]
+    manual_control_buttons: Option<usize>,
+
+    /// roll and pitch in degrees of SET_ATTITUDE_TARGET at full stick deflection, the angle
+    /// limit of the FC in angle mode
+    #[clap(long, default_value = "60")]
+    attitude_target_max_angle: f32,
+
+    /// yaw rate in degrees per second of SET_ATTITUDE_TARGET at full stick deflection
+    #[clap(long, default_value = "200")]
+    attitude_target_max_yaw_rate: f32,
+
+    /// time in milliseconds after the last RC command from the GCS until the bridge stops
+    /// overriding the RC channels of the FC
+    #[clap(long, default_value = "500")]
+    rc_override_timeout_ms: u64,
+
+    /// rate in Hz at which the latest RC override is sent to the FC, at least 1. Overrides
+    /// commanded faster replace each other rather than queue up on a slow MSP link
+    #[clap(long, default_value = "10")]
+    rc_override_rate: f64,
+
+    /// RC channel values in microseconds, starting at channel 1, sent instead of releasing the
+    /// RC when the GCS stops commanding it, e.g. `1500,1500,1000,1500`
+    #[clap(long)]
+    rc_failsafe: Option<translator::RcFrame>,
+
+    /// raw RSSI the FC reports at full signal strength, 1023 for most builds and 255 for some,
+    /// scaled to the MAVLink range of 0 to 254
+    #[clap(long, default_value = "1023")]
+    rssi_max: u16,
+
+    /// RC channel carrying the RSSI, counting from 1, from 1000 us for no signal to 2000 us for
+    /// full signal, instead of the RSSI reported by the FC
+    #[clap(long)]
+    rssi_channel: Option<usize>,
+
+    /// magnetic declination in degrees, east positive, added to the compass heading of the FC
+    #[clap(long, default_value = "0", allow_hyphen_values = true)]
+    declination_deg: f32,
+
+    /// share of the previous heading kept by the low-pass filter of the ATTITUDE yaw, from 0
+    /// (unfiltered) to 1
+    #[clap(long, default_value = "0.5")]
+    heading_smoothing: f32,
+
+    /// invert the roll axis of ATTITUDE and SCALED_IMU, for a flight controller mounted rotated
+    #[clap(long)]
+    invert_roll: bool,
+
+    /// invert the pitch axis of ATTITUDE and SCALED_IMU, for a flight controller mounted rotated
+    #[clap(long)]
+    invert_pitch: bool,
+
+    /// invert the yaw axis of ATTITUDE and SCALED_IMU, for a flight controller mounted rotated
+    #[clap(long)]
+    invert_yaw: bool,
+
+    /// accelerometer scale of SCALED_IMU in mg per MSP unit, INAV reports 512 units per g
+    #[clap(long, default_value = "1.953125")]
+    acc_scale: f64,
+
+    /// gyroscope scale of SCALED_IMU in mrad/s per MSP unit, INAV reports deg/s
+    #[clap(long, default_value = "17.453293")]
+    gyro_scale: f64,
+
+    /// magnetometer scale of SCALED_IMU in mgauss per MSP unit
+    #[clap(long, default_value = "1")]
+    mag_scale: f64,
+
+    /// serve health metrics of the bridge for Prometheus on this address, e.g.
+    /// `127.0.0.1:9100`
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// time zero
     #[clap(skip = Instant::now())]
     t0: Instant,
 }
 
+/// Actions other than running the bridge
+#[derive(Clone, Debug, Clap)]
+enum Command {
+    /// print the supported MAVLink messages and the MSP functions they poll, then exit
+    ListMessages,
+}
+
 fn main() {
     env::set_var(
         "RUST_LOG",
         env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
     );
 
-    let conf = Config::parse();
+    let args = match config_file::args(env::args_os()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("unable to load config file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let conf = Config::parse_from(args);
+    if let Some(Command::ListMessages) = conf.command {
+        core::list_messages(&core::TranslatorRegistry::default());
+        return;
+    }
 
     info!("started");
+    if let Some(path) = &conf.config {
+        info!("loaded config file {}", path);
+    }
     debug!("{:?}", &conf);
-    core::event_loop(&conf);
+    if let Err(e) = core::event_loop(&conf) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    info!("stopped");
 }
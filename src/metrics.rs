@@ -0,0 +1,149 @@
+//! Health metrics of the bridge in the Prometheus text format, served by `--metrics-addr`
+//!
+//! The endpoint speaks just enough HTTP for a scraper: every request to `/metrics` is answered
+//! with the current metrics, anything else with 404.
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::net::{TcpListener, TcpStream};
+
+use crate::msp::{MspStats, LATENCY_BUCKETS_MS, MSP_STATS};
+use crate::scheduler::Schedule;
+
+/// renders the metrics of the MSP traffic and the schedule
+pub fn render(stats: &MspStats, schedule: &Schedule<u32>) -> String {
+    let mut out = String::new();
+    let counters = [
+        ("msp_requests_total", "MSP requests sent", &stats.requests),
+        (
+            "msp_responses_total",
+            "MSP responses received",
+            &stats.responses,
+        ),
+        (
+            "msp_checksum_errors_total",
+            "MSP responses with a wrong checksum",
+            &stats.checksum_errors,
+        ),
+        (
+            "msp_timeouts_total",
+            "MSP requests without response",
+            &stats.timeouts,
+        ),
+        (
+            "schedule_slot_overruns_total",
+            "schedule slots whose MSP round-trips took longer than the slot",
+            &stats.slot_overruns,
+        ),
+    ];
+    for (name, help, counter) in &counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+
+    let name = "msp_round_trip_seconds";
+    let _ = writeln!(out, "# HELP {} MSP request to response time", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    let mut count = 0;
+    for (i, bucket) in stats.latency_buckets.iter().enumerate() {
+        count += bucket.load(Ordering::Relaxed);
+        let le = match LATENCY_BUCKETS_MS.get(i) {
+            Some(ms) => (*ms as f64 / 1000.).to_string(),
+            None => "+Inf".to_string(),
+        };
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+    }
+    let sum = stats.latency_sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+    let _ = writeln!(out, "{}_sum {}", name, sum);
+    let _ = writeln!(out, "{}_count {}", name, count);
+
+    let name = "msp_round_trip_average_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {} rolling average of recent MSP round-trips",
+        name
+    );
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, stats.average_latency().as_secs_f64());
+
+    let name = "schedule_slot_utilization";
+    let _ = writeln!(out, "# HELP {} share of the schedule slots in use", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, schedule.utilization());
+    out
+}
+
+/// answers one HTTP request
+async fn respond(mut stream: TcpStream, schedule: &Schedule<u32>) -> io::Result<()> {
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let response = match path {
+        "/metrics" => {
+            let body = render(&MSP_STATS, schedule);
+            format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// serves the metrics on `addr` until the bridge stops
+pub async fn serve(addr: SocketAddr, schedule: Arc<Schedule<u32>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("serving metrics on http://{}/metrics", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let schedule = schedule.clone();
+        smol::spawn(async move {
+            if let Err(e) = respond(stream, &schedule).await {
+                debug!("unable to serve metrics to {}: {}", peer, e);
+            }
+        })
+        .detach();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_prometheus_text() {
+        let stats = MspStats::new();
+        stats.requests.store(3, Ordering::Relaxed);
+        stats.responses.store(2, Ordering::Relaxed);
+        stats.slot_overruns.store(4, Ordering::Relaxed);
+        stats.latency_buckets[1].store(1, Ordering::Relaxed);
+        stats.latency_buckets[LATENCY_BUCKETS_MS.len()].store(1, Ordering::Relaxed);
+        stats.latency_sum_us.store(501_500, Ordering::Relaxed);
+        stats.latency_average_us.store(250_750, Ordering::Relaxed);
+        let schedule = Schedule::new(50);
+        schedule.insert(10., 30).unwrap();
+
+        let text = render(&stats, &schedule);
+        assert!(text.contains("msp_requests_total 3\n"));
+        assert!(text.contains("msp_responses_total 2\n"));
+        assert!(text.contains("msp_round_trip_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("msp_round_trip_seconds_bucket{le=\"0.002\"} 1\n"));
+        assert!(text.contains("msp_round_trip_seconds_bucket{le=\"0.2\"} 1\n"));
+        assert!(text.contains("msp_round_trip_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("msp_round_trip_seconds_sum 0.5015\n"));
+        assert!(text.contains("msp_round_trip_seconds_count 2\n"));
+        assert!(text.contains("msp_round_trip_average_seconds 0.25075\n"));
+        assert!(text.contains("schedule_slot_utilization 0.2\n"));
+        assert!(text.contains("schedule_slot_overruns_total 4\n"));
+    }
+}
@@ -1,24 +1,58 @@
 use std::clone::Clone;
+use std::collections::VecDeque;
 use std::convert::{TryFrom, TryInto};
-use std::fmt::{Debug, Display};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
 use std::io::{self, Read, Write};
 use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use crc_any::CRC;
 
 macro_rules! msp_codec {
-    ( $name:ident $id:expr ) => {
+    ( $name:ident $id:expr $(,)? ) => {
+        #[derive(Debug, Clone, PartialEq)]
         pub struct $name {}
+
         impl MspPayload for $name {
-            const SIZE: usize = 0 ;
+            const SIZE: usize = 0;
             const ID: IdType = $id;
 
-            fn decode<R: Read>(r:&mut R)->io::Result<Self>{
-                Ok($name{})
+            fn decode<R: Read>(_r: &mut R, _len: usize) -> io::Result<Self> {
+                Ok($name {})
             }
 
-            fn encode<W: Write>(&self, w: &mut W)->io::Result<()>{
-                Ok()
+            fn encode<W: Write>(&self, _w: &mut W) -> io::Result<()> {
+                Ok(())
+            }
+        }
+    };
+
+    ( $name:ident $id:expr, $field_name:ident : String ) => {
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name {
+            pub $field_name: String,
+        }
+
+        impl MspPayload for $name {
+            const SIZE: usize = 0;
+            const ID: IdType = $id;
+
+            fn decode<R: Read>(r: &mut R, len: usize) -> io::Result<Self> {
+                let mut buf = vec![0u8; len];
+                read_full(r, &mut buf[..])?;
+                let $field_name = String::from_utf8(buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok($name { $field_name })
+            }
+
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                w.write_all(self.$field_name.as_bytes())
+            }
+
+            fn size(&self) -> usize {
+                self.$field_name.len()
             }
         }
     };
@@ -33,9 +67,9 @@ macro_rules! msp_codec {
             const SIZE: usize = 0 $( + size_of::<$field_type>() )+;
             const ID: IdType = $id;
 
-            fn decode<R: Read>(r:&mut R)->io::Result<Self>{
+            fn decode<R: Read>(r:&mut R, _len: usize)->io::Result<Self>{
                 let mut buf = [0u8; Self::SIZE];
-                r.read_exact(&mut buf[..])?;
+                read_full(r, &mut buf[..])?;
                 let mut i = 0;
 
                 #[allow(clippy::eval_order_dependence)]
@@ -65,15 +99,50 @@ macro_rules! msp_codec {
 
     ($name:ident $id:expr, [$type:ty; $size:expr]) => {
         #[derive(Clone, Debug, PartialEq)]
-        struct $name( [$type; $size] );
+        pub struct $name( [$type; $size] );
+
+        impl $name {
+            /// the contained values
+            #[allow(dead_code)]
+            pub fn as_slice(&self) -> &[$type] {
+                &self.0[..]
+            }
+        }
+
+        impl std::ops::Index<usize> for $name {
+            type Output = $type;
+
+            fn index(&self, index: usize) -> &$type {
+                &self.0[index]
+            }
+        }
+
+        /// the values, separated by commas
+        impl Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                for (i, value) in self.0.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl From<[$type; $size]> for $name {
+            fn from(values: [$type; $size]) -> Self {
+                $name(values)
+            }
+        }
 
         impl MspPayload for $name {
             const SIZE: usize = $size * size_of::<$type>();
             const ID: IdType = $id;
 
-            fn decode<R: Read>(r: &mut R) -> io::Result<$name> {
+            fn decode<R: Read>(r: &mut R, _len: usize) -> io::Result<$name> {
                 let mut buf = [0u8; Self::SIZE];
-                r.read_exact(&mut buf[..])?;
+                read_full(r, &mut buf[..])?;
 
                 let mut payload = [0 as $type; $size];
                 let mut i = 0;
@@ -102,10 +171,14 @@ macro_rules! msp_codec {
 
 #[cfg(test)]
 macro_rules! msp_test {
-    ( $name:ident $id:expr ) => {
+    ( $name:ident $id:expr $(,)? ) => {
+        #[allow(non_snake_case)]
         mod $name {
+            use rand::random;
+
             use super::super::*;
 
+            // nothing to transmit, so the payload is decoded as `None`
             #[test]
             fn message_encode_decode_test(){
                 let mut buf: Vec<u8> = Vec::new();
@@ -117,6 +190,52 @@ macro_rules! msp_test {
                     payload: Some( $name{}),
                 };
                 message.encode(&mut buf).expect("unable to encode");
+                let new_message: MspMessage<$name> =
+                    MspMessage::decode(&mut &buf[..]).expect("unable to decode");
+                let mut new_buf: Vec<u8> = Vec::new();
+                new_message.encode(&mut new_buf).expect("unable to encode");
+                assert_eq!(new_message.function, message.function);
+                assert_eq!(new_message.payload, None);
+                assert_eq!(buf, new_buf);
+            }
+        }
+    };
+
+    ( $name:ident $id:expr, $field_name:ident : String ) => {
+        #[allow(non_snake_case)]
+        mod $name {
+            use rand::random;
+
+            use super::super::*;
+
+            // never empty, an empty payload is decoded as `None`
+            fn random_string() -> String {
+                (0..=random::<u8>() % 32).map(|_| random::<char>()).collect()
+            }
+
+            #[test]
+            fn encode_decode_test(){
+                let mut buf: Vec<u8> = Vec::new();
+                let payload = $name { $field_name : random_string() };
+                payload.encode(&mut buf).expect("unable to encode");
+                let new_payload = $name::decode(&mut &buf[..], buf.len()).expect("unable to decode");
+                let mut new_buf: Vec<u8> = Vec::new();
+                new_payload.encode(&mut new_buf).expect("unable to encode");
+                assert_eq!(payload, new_payload);
+                assert_eq!(buf, new_buf);
+            }
+
+            #[test]
+            fn message_encode_decode_test(){
+                let mut buf: Vec<u8> = Vec::new();
+                let message = MspMessage {
+                    version: MspVersion::V2,
+                    direction: MspDirection::Response,
+                    flag: Some(random()),
+                    function: $id,
+                    payload: Some( $name { $field_name : random_string() }),
+                };
+                message.encode(&mut buf).expect("unable to encode");
                 let new_message = MspMessage::decode(&mut &buf[..]).expect("unable to decode");
                 let mut new_buf: Vec<u8> = Vec::new();
                 new_message.encode(&mut new_buf).expect("unable to encode");
@@ -134,12 +253,22 @@ macro_rules! msp_test {
 
             use super::super::*;
 
+            #[test]
+            fn size_test(){
+                let payload = $name { $( $field_name : random(), )+ };
+                let mut buf: Vec<u8> = Vec::new();
+                payload.encode(&mut buf).expect("unable to encode");
+                assert_eq!($name::SIZE, 0 $( + std::mem::size_of_val(&payload.$field_name) )+);
+                assert_eq!($name::SIZE, buf.len());
+                assert_eq!(payload.size(), buf.len());
+            }
+
             #[test]
             fn encode_decode_test(){
                 let mut buf: Vec<u8> = Vec::new();
                 let payload = $name { $( $field_name : random(), )+ };
                 payload.encode(&mut buf).expect("unable to encode");
-                let new_payload = $name::decode(&mut &buf[..]).expect("unable to decode");
+                let new_payload = $name::decode(&mut &buf[..], buf.len()).expect("unable to decode");
                 let mut new_buf: Vec<u8> = Vec::new();
                 new_payload.encode(&mut new_buf).expect("unable to encode");
                 assert_eq!(payload, new_payload);
@@ -172,6 +301,16 @@ macro_rules! msp_test {
 
             use super::super::*;
 
+            #[test]
+            fn size_test(){
+                let payload = $name([0 as $type; $size]);
+                let mut buf: Vec<u8> = Vec::new();
+                payload.encode(&mut buf).expect("unable to encode");
+                assert_eq!($name::SIZE, $size * std::mem::size_of::<$type>());
+                assert_eq!($name::SIZE, buf.len());
+                assert_eq!(payload.size(), buf.len());
+            }
+
             #[test]
             fn encode_decode_test(){
                 let mut buf: Vec<u8> = Vec::new();
@@ -180,7 +319,7 @@ macro_rules! msp_test {
                     *e = &mut random();
                 }
                 payload.encode(&mut buf).expect("unable to encode");
-                let new_payload = $name::decode(&mut &buf[..]).expect("unable to decode");
+                let new_payload = $name::decode(&mut &buf[..], buf.len()).expect("unable to decode");
                 let mut new_buf: Vec<u8> = Vec::new();
                 new_payload.encode(&mut new_buf).expect("unable to encode");
                 assert_eq!(payload, new_payload);
@@ -230,11 +369,288 @@ macro_rules! msp_payload {
 macro_rules! get {
     ( $src: expr, $type:ty ) => {{
         let mut buf = [0u8; size_of::<$type>()];
-        $src.read_exact(&mut buf[..])?;
+        read_full($src, &mut buf[..])?;
         <$type>::from_le_bytes(buf[..].try_into().unwrap())
     }};
 }
 
+/// fills `buf` completely, even if the reader hands out single bytes
+fn read_full<R: Read + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Byte arrays have no endianness, this allows them to be used as fields in `msp_codec!`
+trait ByteArray {
+    fn from_le_bytes(bytes: Self) -> Self;
+    fn to_le_bytes(self) -> Self;
+}
+
+impl<const N: usize> ByteArray for [u8; N] {
+    fn from_le_bytes(bytes: Self) -> Self {
+        bytes
+    }
+
+    fn to_le_bytes(self) -> Self {
+        self
+    }
+}
+
+/// Everything that can go wrong while talking MSP
+#[derive(Debug)]
+pub enum MspError {
+    /// the checksum of a received frame does not match its content
+    ChecksumMismatch {
+        expected: u8,
+        got: u8,
+    },
+    UnknownDirection(u8),
+    UnknownVersion(u8),
+    /// the payload does not fit the length field of the frame
+    PayloadTooBig,
+    /// the response carried no payload, although one was expected
+    EmptyPayload,
+    /// the connection ended in the middle of a frame
+    UnexpectedEof,
+    /// the length of a fixed size payload differs from the length announced by the frame
+    LengthMismatch {
+        expected: usize,
+        got: usize,
+    },
+    /// the response belongs to another function than the request
+    UnexpectedFunction {
+        expected: u16,
+        got: u16,
+    },
+    /// the FC answered with an error frame, e.g. for a function it does not know
+    FcRejected {
+        cmd: u16,
+    },
+    /// no complete frame arrived before the deadline
+    Timeout,
+    Io(io::Error),
+}
+
+impl Display for MspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MspError::ChecksumMismatch { expected, got } => write!(
+                f,
+                "wrong Msp checksum, expected {:#04x} got {:#04x}",
+                expected, got
+            ),
+            MspError::UnknownDirection(byte) => write!(f, "unknown Msp direction {:#04x}", byte),
+            MspError::UnknownVersion(byte) => write!(f, "unknown Msp version {:#04x}", byte),
+            MspError::PayloadTooBig => write!(f, "Msp payload too big"),
+            MspError::EmptyPayload => write!(f, "received empty Msp payload"),
+            MspError::UnexpectedEof => write!(f, "unexpected end of Msp frame"),
+            MspError::LengthMismatch { expected, got } => write!(
+                f,
+                "wrong Msp payload length, expected {} got {}",
+                expected, got
+            ),
+            MspError::UnexpectedFunction { expected, got } => write!(
+                f,
+                "unexpected Msp function, expected {} got {}",
+                expected, got
+            ),
+            MspError::FcRejected { cmd } => write!(f, "FC rejected Msp function {}", cmd),
+            MspError::Timeout => write!(f, "no Msp frame before the deadline"),
+            MspError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for MspError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MspError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl MspError {
+    /// errors a repeated request may not run into again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MspError::ChecksumMismatch { .. } | MspError::Timeout => true,
+            MspError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// upper bounds in ms of the buckets of the MSP round-trip histogram
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [1, 2, 5, 10, 20, 50, 100, 200];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the MSP requests of all connections
+pub struct MspStats {
+    pub requests: AtomicU64,
+    pub responses: AtomicU64,
+    pub checksum_errors: AtomicU64,
+    pub timeouts: AtomicU64,
+    /// schedule slots whose MSP round-trips took longer than the slot
+    pub slot_overruns: AtomicU64,
+    /// round-trips per bucket of `LATENCY_BUCKETS_MS`, the last one counts all slower ones
+    pub latency_buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    /// sum of the round-trips in microseconds
+    pub latency_sum_us: AtomicU64,
+    /// rolling average of the recent round-trips in microseconds
+    pub latency_average_us: AtomicU64,
+}
+
+/// weight of the previous average against a new round-trip in `MspStats.latency_average_us`
+const LATENCY_AVERAGE_WEIGHT: u64 = 7;
+
+/// statistics of every MSP request sent by the bridge
+pub static MSP_STATS: MspStats = MspStats::new();
+
+impl MspStats {
+    pub const fn new() -> Self {
+        MspStats {
+            requests: ZERO,
+            responses: ZERO,
+            checksum_errors: ZERO,
+            timeouts: ZERO,
+            slot_overruns: ZERO,
+            latency_buckets: [ZERO; LATENCY_BUCKETS_MS.len() + 1],
+            latency_sum_us: ZERO,
+            latency_average_us: ZERO,
+        }
+    }
+
+    /// rolling average of the recent round-trips
+    pub fn average_latency(&self) -> Duration {
+        Duration::from_micros(self.latency_average_us.load(Ordering::Relaxed))
+    }
+
+    /// counts a request and its outcome, which took `round_trip`
+    fn record<P>(&self, result: &Result<P, MspError>, round_trip: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                self.responses.fetch_add(1, Ordering::Relaxed);
+                let ms = round_trip.as_millis() as u64;
+                let bucket = LATENCY_BUCKETS_MS
+                    .iter()
+                    .position(|bound| ms < *bound)
+                    .unwrap_or(LATENCY_BUCKETS_MS.len());
+                self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+                let us = round_trip.as_micros() as u64;
+                self.latency_sum_us.fetch_add(us, Ordering::Relaxed);
+                // the first round-trip starts the average
+                let _ = self.latency_average_us.fetch_update(
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                    |average| match average {
+                        0 => Some(us),
+                        _ => Some(
+                            (average * LATENCY_AVERAGE_WEIGHT + us) / (LATENCY_AVERAGE_WEIGHT + 1),
+                        ),
+                    },
+                );
+            }
+            Err(MspError::ChecksumMismatch { .. }) => {
+                self.checksum_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            // the other transient errors are timeouts
+            Err(e) if e.is_transient() => {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl From<io::Error> for MspError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => MspError::UnexpectedEof,
+            _ => MspError::Io(e),
+        }
+    }
+}
+
+impl From<MspError> for io::Error {
+    fn from(e: MspError) -> Self {
+        let kind = match e {
+            MspError::Io(e) => return e,
+            MspError::ChecksumMismatch { .. }
+            | MspError::EmptyPayload
+            | MspError::LengthMismatch { .. }
+            | MspError::UnexpectedFunction { .. } => io::ErrorKind::InvalidData,
+            MspError::UnknownDirection(_)
+            | MspError::UnknownVersion(_)
+            | MspError::PayloadTooBig
+            | MspError::FcRejected { .. } => io::ErrorKind::InvalidInput,
+            MspError::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            MspError::Timeout => io::ErrorKind::TimedOut,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Bytes skipped while searching the start of a frame before giving up
+const MAX_SCAN: usize = 1024;
+
+/// Remembers the bytes of the current frame, so they can be scanned again if it is corrupt
+///
+/// No read is started after `deadline`, which sets `expired` instead.
+struct Resync<'a, R> {
+    inner: &'a mut R,
+    replay: VecDeque<u8>,
+    frame: Vec<u8>,
+    deadline: Option<Instant>,
+    expired: bool,
+}
+
+impl<R> Resync<'_, R> {
+    /// reads the current frame again, starting after its `$`
+    fn rewind(&mut self) {
+        for byte in self.frame.drain(..).skip(1).rev() {
+            self.replay.push_front(byte);
+        }
+    }
+}
+
+impl<R: Read> Read for Resync<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.replay.len() {
+            0 => {
+                if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                    self.expired = true;
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                self.inner.read(buf)?
+            }
+            replay => {
+                let len = buf.len().min(replay);
+                for (b, byte) in buf.iter_mut().zip(self.replay.drain(..len)) {
+                    *b = byte;
+                }
+                len
+            }
+        };
+        self.frame.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
 /// Type for MSP Id
 type IdType = u16;
 
@@ -262,47 +678,46 @@ impl From<&MspDirection> for u8 {
 }
 
 impl TryFrom<u8> for MspDirection {
-    type Error = io::Error;
+    type Error = MspError;
     fn try_from(byte: u8) -> Result<Self, <Self as TryFrom<u8>>::Error> {
         match byte as char {
             '<' => Ok(MspDirection::Request),
             '>' => Ok(MspDirection::Response),
             '!' => Ok(MspDirection::Error),
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "unknown Msp direction",
-            )),
+            _ => Err(MspError::UnknownDirection(byte)),
         }
     }
 }
 
 /// V1: (`M`)
 /// V2: (`X`)
+/// V2OverV1: (`M`) a V2 frame tunneled in the payload of the V1 function `MSP_V2_FRAME`
 #[derive(Clone, Debug, PartialEq)]
 pub enum MspVersion {
     V1,
     V2,
+    V2OverV1,
 }
 
 impl From<&MspVersion> for u8 {
     fn from(d: &MspVersion) -> Self {
         match d {
-            MspVersion::V1 => b'M',
+            MspVersion::V1 | MspVersion::V2OverV1 => b'M',
             MspVersion::V2 => b'X',
         }
     }
 }
 
+/// V1 function carrying a tunneled V2 frame
+const MSP_V2_FRAME: IdType = 255;
+
 impl TryFrom<u8> for MspVersion {
-    type Error = io::Error;
+    type Error = MspError;
     fn try_from(byte: u8) -> Result<Self, <Self as TryFrom<u8>>::Error> {
         match byte as char {
             'M' => Ok(MspVersion::V1),
             'X' => Ok(MspVersion::V2),
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "unknown msp version",
-            )),
+            _ => Err(MspError::UnknownVersion(byte)),
         }
     }
 }
@@ -311,14 +726,26 @@ pub trait MspPayload {
     const ID: IdType;
     const SIZE: usize;
 
-    fn decode<R: Read>(r: &mut R) -> io::Result<Self>
+    /// `len` is the payload length announced by the message header
+    fn decode<R: Read>(r: &mut R, len: usize) -> io::Result<Self>
     where
         Self: std::marker::Sized;
     fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+
+    /// actual size of the encoded payload, only differs from `SIZE` for variable length payloads
+    fn size(&self) -> usize {
+        Self::SIZE
+    }
 }
 
 //msp_payload!{MspIdent 100, version: u8, multitype: u8, msp_version: u8, capability: u32}
 msp_payload! {
+    { MspApiVersion 1, protocol: u8, major: u8, minor: u8},
+    { MspFcVariant 2, identifier: [u8; 4]},
+    { MspFcVersion 3, major: u8, minor: u8, patch: u8},
+    { MspName 10, name: String},
+    { MspSonarAltitude 58, distance: i32},
+    { MspReboot 68, },
     { MspIdent 100, version: u8, multitype: u8, msp_version: u8, capability: u32},
     { MspStatus 101, cycle_time: u16, i2c_errors_count: u16, sensor: u16,  flag: u32,  global_conf_current_set: u8 },
     { MspRawImu 102, accx: i16, accy: i16, accz: i16, gyrx: i16, gyry: i16, gyrz: i16, magx: i16, magy: i16, magz: i16 },
@@ -335,31 +762,215 @@ msp_payload! {
     { MspAnalog 110, vbat: u8, int_power_meter_sum: u16, rssi: u16, amperage: u16},
     { MspRcTuning 111, rc_rate:u8, rc_expo: u8, roll_pitch_rate: u8, yaw_rate: u8, dyn_thr_pid:u8, throttle_mid: u8, throttle_expo: u8},
     { MspSetRcTuning 204, rc_rate:u8, rc_expo: u8, roll_pitch_rate: u8, yaw_rate: u8, dyn_thr_pid:u8, throttle_mid: u8, throttle_expo: u8},
-    //{Msp_PID  112},
-    //{Msp_SET_PID  202},
+    { MspPid 112, roll: [u8; 3], pitch: [u8; 3], yaw: [u8; 3], alt: [u8; 3], pos: [u8; 3], posr: [u8; 3], navr: [u8; 3], level: [u8; 3], mag: [u8; 3], vel: [u8; 3]},
+    { MspSetPid 202, roll: [u8; 3], pitch: [u8; 3], yaw: [u8; 3], alt: [u8; 3], pos: [u8; 3], posr: [u8; 3], navr: [u8; 3], level: [u8; 3], mag: [u8; 3], vel: [u8; 3]},
     //{Msp_BOX  113},
     //{Msp_SET_BOX  203},
     //{Msp_MISC  114},
     //{Msp_SET_MISC  207},
     { MspMotorPins 115, [u8;8]},
-    //{Msp_BOXNAMES  116},
-    //{Msp_PIDNAMES  117},
+    { MspBoxNames 116, names: String},
+    { MspWpRequest 118, wp_no: u8},
     { MspWp 118, wp_no: u8, lat:i32, lon: i32, alt_hold: u32, heading: i16, time_to_stay:u16, nav_flag: u8},
     { MspSetWp 209, wp_no: u8, lat:i32, lon: i32, alt_hold: u32, heading: i16, time_to_stay:u16, nav_flag: u8},
     //{Msp_SET_WP  209},
     //{Msp_BOXIDS  119},
     //{Msp_SERVO_CONF  120},
-    //{Msp_SET_SERVO_CONF  212},
-    //{Msp_ACC_CALIBRATION  205},
-    //{Msp_MAG_CALIBRATION  206},
+    { MspSetServoConf 212, index: u8, min: u16, max: u16, middle: u16, rate: i8, forward_from_channel: u8, reversed_sources: u32},
+    { MspAccCalibration 205, },
+    { MspMagCalibration 206, },
     //{Msp_RESET_CONF  208},
-    //{Msp_SELECT_SETTING  210},
-    { MspSetHead 211, mag_hold: i16}
+    { MspSelectSetting 210, profile: u8},
+    { MspSetHead 211, mag_hold: i16},
+    { MspBatteryState 130, cell_count: u8, capacity: u16, voltage: u8, mah_drawn: u16, amperage: u16, state: u8, voltage_precise: u16},
     //{Msp_BIND  240},
-    //{ Msp_EEPROM_WRITE 250}
+    { MspEepromWrite 250, }
+}
+
+/// Selects a setting by its index, as parameter of the MSP2 setting requests
+///
+/// MSP2 also allows to select a setting by name, the leading zero byte marks an index.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MspSettingIndex(pub u16);
+
+impl MspPayload for MspSettingIndex {
+    const SIZE: usize = 3;
+    const ID: IdType = 0x1003;
+
+    fn decode<R: Read>(r: &mut R, _len: usize) -> io::Result<Self> {
+        let mut buf = [0u8; Self::SIZE];
+        read_full(r, &mut buf[..])?;
+        Ok(MspSettingIndex(u16::from_le_bytes([buf[1], buf[2]])))
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[0])?;
+        w.write_all(&self.0.to_le_bytes())
+    }
+}
+
+/// MSP2_COMMON_SET_SETTING, writes the raw little endian value of the setting `index`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MspSetSetting {
+    pub index: u16,
+    pub value: Vec<u8>,
+}
+
+impl MspPayload for MspSetSetting {
+    const SIZE: usize = 0;
+    const ID: IdType = 0x1004;
+
+    fn decode<R: Read>(r: &mut R, len: usize) -> io::Result<Self> {
+        let index = MspSettingIndex::decode(r, MspSettingIndex::SIZE)?.0;
+        let mut value = vec![0u8; len.saturating_sub(MspSettingIndex::SIZE)];
+        read_full(r, &mut value[..])?;
+        Ok(MspSetSetting { index, value })
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        MspSettingIndex(self.index).encode(w)?;
+        w.write_all(&self.value)
+    }
+
+    fn size(&self) -> usize {
+        MspSettingIndex::SIZE + self.value.len()
+    }
+}
+
+/// MSP2_COMMON_SETTING_INFO, describes a setting and carries its current value
+#[derive(Debug, Clone, PartialEq)]
+pub struct MspSettingInfo {
+    pub name: String,
+    pub pgn: u16,
+    /// 0: u8, 1: i8, 2: u16, 3: i16, 4: u32, 5: f32, 6: string
+    pub setting_type: u8,
+    pub section: u8,
+    /// 0: direct value, 1: index into `lookup`
+    pub mode: u8,
+    pub min: i32,
+    pub max: u32,
+    pub index: u16,
+    pub profile: u8,
+    pub profile_count: u8,
+    /// names of the values of a lookup setting
+    pub lookup: Vec<String>,
+    pub value: Vec<u8>,
+}
+
+/// reads a null terminated string
+fn read_cstr<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut buf = Vec::new();
+    loop {
+        match get!(r, u8) {
+            0 => break,
+            byte => buf.push(byte),
+        }
+    }
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl MspPayload for MspSettingInfo {
+    const SIZE: usize = 0;
+    const ID: IdType = 0x1007;
+
+    fn decode<R: Read>(r: &mut R, len: usize) -> io::Result<Self> {
+        let mut buf = vec![0u8; len];
+        read_full(r, &mut buf[..])?;
+        let r = &mut &buf[..];
+
+        let name = read_cstr(r)?;
+        let pgn = get!(r, u16);
+        let setting_type = get!(r, u8);
+        let section = get!(r, u8);
+        let mode = get!(r, u8);
+        let min = get!(r, i32);
+        let max = get!(r, u32);
+        let index = get!(r, u16);
+        let profile = get!(r, u8);
+        let profile_count = get!(r, u8);
+        let mut lookup = Vec::new();
+        if mode == 1 {
+            for _ in min..=max as i32 {
+                lookup.push(read_cstr(r)?);
+            }
+        }
+        Ok(MspSettingInfo {
+            name,
+            pgn,
+            setting_type,
+            section,
+            mode,
+            min,
+            max,
+            index,
+            profile,
+            profile_count,
+            lookup,
+            value: r.to_vec(),
+        })
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(self.name.as_bytes())?;
+        w.write_all(&[0])?;
+        w.write_all(&self.pgn.to_le_bytes())?;
+        w.write_all(&[self.setting_type, self.section, self.mode])?;
+        w.write_all(&self.min.to_le_bytes())?;
+        w.write_all(&self.max.to_le_bytes())?;
+        w.write_all(&self.index.to_le_bytes())?;
+        w.write_all(&[self.profile, self.profile_count])?;
+        for name in &self.lookup {
+            w.write_all(name.as_bytes())?;
+            w.write_all(&[0])?;
+        }
+        w.write_all(&self.value)
+    }
+
+    fn size(&self) -> usize {
+        self.name.len()
+            + 1
+            + 17
+            + self.lookup.iter().map(|name| name.len() + 1).sum::<usize>()
+            + self.value.len()
+    }
+}
+
+/// Payload of any function as raw bytes, the function is only known from the frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct MspRaw(pub Vec<u8>);
+
+impl MspPayload for MspRaw {
+    const SIZE: usize = 0;
+    const ID: IdType = 0;
+
+    fn decode<R: Read>(r: &mut R, len: usize) -> io::Result<Self> {
+        let mut buf = vec![0u8; len];
+        read_full(r, &mut buf[..])?;
+        Ok(MspRaw(buf))
+    }
+
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.0)
+    }
+
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// calculates the checksum of the frame `bytes` following the direction, for `version`
+fn frame_checksum(version: &MspVersion, bytes: &[u8]) -> u8 {
+    match version {
+        MspVersion::V1 | MspVersion::V2OverV1 => bytes.iter().fold(0, |xor, byte| xor ^ byte),
+        MspVersion::V2 => {
+            let mut crc = CRC::create_crc(0xd5, 8, 0x0, 0x0, false);
+            crc.digest(bytes);
+            crc.get_crc().try_into().unwrap()
+        }
+    }
 }
 
-/// A flag may only be `Some(_)` if `version == MspVersion::V2`
+/// A flag may only be `Some(_)` if `version` is `MspVersion::V2` or `MspVersion::V2OverV1`
 #[derive(Clone, Debug, PartialEq)]
 pub struct MspMessage<P: MspPayload> {
     pub version: MspVersion,
@@ -390,14 +1001,30 @@ where
     P: MspPayload + Clone + Debug,
 {
     /// serializes message omiting the checksum
-    fn ser(&self) -> io::Result<Vec<u8>> {
+    fn ser(&self) -> Result<Vec<u8>, MspError> {
         match self.version {
             MspVersion::V1 => {
-                //const LEN_OFFSET: usize = 4 + size_of::<IdType>();
-                //let buf =
-                //    vec![0u8; 4 * size_of::<u8>() + size_of::<IdType>() + size_of::<LenType>()];
-                //Ok(buf)
-                panic!("not implemented");
+                let function: u8 = self.function.try_into().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "function id too big for Msp V1",
+                    )
+                })?;
+                let len: LenType = match &self.payload {
+                    Some(payload) => payload
+                        .size()
+                        .try_into()
+                        .map_err(|_| MspError::PayloadTooBig)?,
+                    _ => 0,
+                };
+
+                let mut buf = self.ser_v1_header(function, len);
+                if let Some(payload) = &self.payload {
+                    let mut payload_buf = vec![0u8; payload.size()];
+                    payload.encode(&mut &mut payload_buf[..])?;
+                    buf.append(&mut payload_buf);
+                }
+                Ok(buf)
             }
             MspVersion::V2 => {
                 const LEN_OFFSET: usize = 4 + size_of::<IdType>();
@@ -409,51 +1036,116 @@ where
                 buf[2] = u8::from(&self.direction);
                 buf[3] = self.flag.unwrap_or(0);
                 buf[4..LEN_OFFSET].copy_from_slice(&self.function.to_le_bytes()[..]);
-                let len: u16 = match self.payload {
-                    Some(_) => P::SIZE.try_into().expect("payload too big"),
+                let len: LenType = match &self.payload {
+                    Some(payload) => payload
+                        .size()
+                        .try_into()
+                        .map_err(|_| MspError::PayloadTooBig)?,
                     _ => 0,
                 };
                 buf[LEN_OFFSET..].copy_from_slice(&len.to_le_bytes()[..]);
 
                 if let Some(payload) = &self.payload {
-                    let mut payload_buf = vec![0u8; P::SIZE];
+                    let mut payload_buf = vec![0u8; payload.size()];
                     payload.encode(&mut &mut payload_buf[..])?;
                     buf.append(&mut payload_buf);
                 }
                 Ok(buf)
             }
+            MspVersion::V2OverV1 => {
+                // the tunneled frame is a V2 frame without its `$X<` preamble
+                let inner = self.tunneled();
+                let mut frame = inner.ser()?;
+                frame.push(inner.checksum());
+                let len: LenType = (frame.len() - 3)
+                    .try_into()
+                    .map_err(|_| MspError::PayloadTooBig)?;
+
+                let mut buf = self.ser_v1_header(MSP_V2_FRAME as u8, len);
+                buf.extend_from_slice(&frame[3..]);
+                Ok(buf)
+            }
+        }
+    }
+
+    /// serializes the V1 header, switching to a jumbo frame for payloads of 255 bytes or more
+    fn ser_v1_header(&self, function: u8, len: LenType) -> Vec<u8> {
+        let mut buf = vec![b'$', u8::from(&self.version), u8::from(&self.direction)];
+        if len < 255 {
+            buf.push(len as u8);
+            buf.push(function);
+        } else {
+            buf.push(255);
+            buf.push(function);
+            buf.extend_from_slice(&len.to_le_bytes()[..]);
+        }
+        buf
+    }
+
+    /// the V2 frame tunneled by a `MspVersion::V2OverV1` message
+    fn tunneled(&self) -> Self {
+        MspMessage {
+            version: MspVersion::V2,
+            ..self.clone()
         }
     }
 
     /// calculates the checksum for the given message
     pub fn checksum(&self) -> u8 {
-        match self.version {
-            MspVersion::V1 => {
-                let mut xor = 0;
-                let buf = &self.ser().unwrap();
-                for byte in &buf[2..] {
-                    xor ^= byte;
-                }
-                xor
-            }
-            MspVersion::V2 => {
-                let mut crc = CRC::create_crc(0xd5, 8, 0x0, 0x0, false);
-                let buf = &self.ser().unwrap();
-                crc.digest(&buf[3..]);
-                crc.get_crc().try_into().unwrap()
-            }
-        }
+        frame_checksum(&self.version, &self.ser().unwrap()[3..])
     }
 
     /// encodes the message to something which can be written to
-    pub fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+    pub fn encode<W: Write>(&self, w: &mut W) -> Result<(), MspError> {
         let buf = &self.ser()?;
         w.write_all(&buf)?;
-        w.write_all(&self.checksum().to_le_bytes())
+        w.write_all(&self.checksum().to_le_bytes())?;
+        Ok(())
     }
 
     /// decodes a message from something which can be read from
-    pub fn decode<R: Read>(r: &mut R) -> io::Result<Self> {
+    ///
+    /// A corrupt frame is dropped and the bytes after its `$` are scanned again for the next
+    /// frame. Bytes of a dropped frame which remain after the next frame are discarded.
+    pub fn decode<R: Read>(r: &mut R) -> Result<Self, MspError> {
+        Self::decode_until(r, None)
+    }
+
+    /// like `decode`, but gives up with `MspError::Timeout` once `deadline` passed
+    ///
+    /// The deadline is checked before every read, so neither a frame dribbling in nor endless
+    /// garbage outlast it by more than one read timeout of `r`, whatever the length of the frame.
+    fn decode_until<R: Read>(r: &mut R, deadline: Option<Instant>) -> Result<Self, MspError> {
+        let mut r = Resync {
+            inner: r,
+            replay: VecDeque::new(),
+            frame: Vec::new(),
+            deadline,
+            expired: false,
+        };
+        let mut scanned = 0;
+        let mut corrupt = None;
+        loop {
+            match Self::decode_frame(&mut r, &mut scanned) {
+                Err(_) if r.expired => return Err(MspError::Timeout),
+                Err(e @ MspError::ChecksumMismatch { .. })
+                | Err(e @ MspError::UnknownVersion(_))
+                | Err(e @ MspError::UnknownDirection(_)) => {
+                    warn!("dropping corrupt Msp frame: {}", e);
+                    r.rewind();
+                    corrupt = Some(e);
+                }
+                // report why the last frame was dropped rather than the end of the stream
+                Err(MspError::UnexpectedEof) => {
+                    return Err(corrupt.unwrap_or(MspError::UnexpectedEof))
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// decodes the next frame, counting the skipped bytes before it in `scanned`
+    fn decode_frame<R: Read>(r: &mut Resync<'_, R>, scanned: &mut usize) -> Result<Self, MspError> {
         #[derive(Debug)]
         enum State {
             Header,
@@ -479,16 +1171,22 @@ where
                     message.version = MspVersion::try_from(get!(r, u8))?;
                     message.direction = MspDirection::try_from(get!(r, u8))?;
                     state = Some(match message.version {
-                        MspVersion::V1 => State::V1Fields,
+                        // a tunneled frame only reveals itself by its function
+                        MspVersion::V1 | MspVersion::V2OverV1 => State::V1Fields,
                         MspVersion::V2 => State::V2Fields,
                     });
                 }
                 Some(State::V1Fields) => {
                     message.flag = None;
                     let payload_size = get!(r, u8) as usize;
-                    message.function = get!(r, u16);
-                    state = Some(match payload_size {
-                        255 => State::Jumbo,
+                    message.function = get!(r, u8).into();
+                    if message.function == MSP_V2_FRAME {
+                        message.version = MspVersion::V2OverV1;
+                    }
+                    state = Some(match (payload_size, &message.version) {
+                        (255, _) => State::Jumbo,
+                        // the tunneled frame announces its own payload size
+                        (_, MspVersion::V2OverV1) => State::V2Fields,
                         _ => State::Payload(payload_size),
                     });
                 }
@@ -496,33 +1194,83 @@ where
                     message.flag = Some(get!(r, u8));
                     message.function = get!(r, u16);
                     let payload_size = get!(r, u16) as usize;
-                    state = Some(match payload_size {
-                        0 => State::Checksum,
-                        _ => State::Payload(payload_size),
-                    });
+                    state = Some(State::Payload(payload_size));
                 }
                 Some(State::Jumbo) => {
                     let payload_size = get!(r, u16) as usize;
-                    state = Some(State::Payload(payload_size));
+                    state = Some(match message.version {
+                        MspVersion::V2OverV1 => State::V2Fields,
+                        _ => State::Payload(payload_size),
+                    });
+                }
+                // an error frame echoes the rejected function, its payload is no `P`
+                Some(State::Payload(payload_size)) if message.direction == MspDirection::Error => {
+                    let mut payload = vec![0u8; payload_size];
+                    read_full(r, &mut payload[..])?;
+                    if message.version == MspVersion::V2OverV1 {
+                        let _ = get!(r, u8);
+                    }
+                    let expected = frame_checksum(&message.version, &r.frame[3..]);
+                    let got = get!(r, u8);
+                    if expected != got {
+                        return Err(MspError::ChecksumMismatch { expected, got });
+                    }
+                    return Err(MspError::FcRejected {
+                        cmd: message.function,
+                    });
+                }
+                // fixed size payloads would be mis-parsed from any other length, no payload at
+                // all is fine though. The frame is skipped, unless its length is corrupt and the
+                // next frame has to be searched within it.
+                Some(State::Payload(payload_size))
+                    if P::SIZE > 0 && payload_size > 0 && payload_size != P::SIZE =>
+                {
+                    let mut payload = vec![0u8; payload_size];
+                    read_full(r, &mut payload[..])?;
+                    // the outer checksum of a tunneled frame covers the tunneled one
+                    if message.version == MspVersion::V2OverV1 {
+                        let _ = get!(r, u8);
+                    }
+                    let expected = frame_checksum(&message.version, &r.frame[3..]);
+                    let got = get!(r, u8);
+                    if expected != got {
+                        return Err(MspError::ChecksumMismatch { expected, got });
+                    }
+                    return Err(MspError::LengthMismatch {
+                        expected: P::SIZE,
+                        got: payload_size,
+                    });
                 }
                 Some(State::Payload(payload_size)) if payload_size > 0 => {
-                    message.payload = Some(P::decode(r)?);
+                    message.payload = Some(P::decode(r, payload_size)?);
                     state = Some(State::Checksum);
                 }
                 Some(State::Payload(_)) => state = Some(State::Checksum),
                 Some(State::Checksum) => {
-                    if message.checksum() == get!(r, u8) {
+                    if message.version == MspVersion::V2OverV1 {
+                        let expected = message.tunneled().checksum();
+                        let got = get!(r, u8);
+                        if expected != got {
+                            return Err(MspError::ChecksumMismatch { expected, got });
+                        }
+                    }
+                    let expected = message.checksum();
+                    let got = get!(r, u8);
+                    if expected == got {
                         return Ok(message);
                     } else {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "wrong Msp checksum",
-                        ));
+                        return Err(MspError::ChecksumMismatch { expected, got });
                     }
                 }
                 None => {
+                    r.frame.clear();
                     if get!(r, u8) as char == '$' {
                         state = Some(State::Header);
+                    } else {
+                        *scanned += 1;
+                        if *scanned > MAX_SCAN {
+                            return Err(MspError::UnexpectedEof);
+                        }
                     }
                 }
             }
@@ -530,7 +1278,7 @@ where
     }
 
     /// tries to fetch a payload from a ressource that both allows us to read and write from/to it
-    pub fn fetch<T: Read + Write>(conn: &mut T) -> io::Result<P> {
+    pub fn fetch<T: MspConnection>(conn: &mut T) -> Result<P, MspError> {
         let msg: Self = MspMessage {
             version: MspVersion::V2,
             direction: MspDirection::Request,
@@ -538,41 +1286,281 @@ where
             function: P::ID,
             payload: None,
         };
-        match msg.request(conn)?.payload {
-            Some(p) => Ok(p),
-            None => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "received empty MSP payload",
-            )),
+        msg.request(conn)?.payload.ok_or(MspError::EmptyPayload)
+    }
+
+    /// like `fetch`, but gives up with `MspError::Timeout` once `deadline` passed
+    pub fn fetch_until<T: MspConnection>(conn: &mut T, deadline: Instant) -> Result<P, MspError> {
+        let msg: Self = MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Request,
+            flag: None,
+            function: P::ID,
+            payload: None,
+        };
+        Self::round_trip(&msg, conn, Some(deadline))?
+            .payload
+            .ok_or(MspError::EmptyPayload)
+    }
+
+    /// like `fetch`, but retries up to `retries` times on checksum errors and timeouts
+    ///
+    /// All attempts together end at most one read timeout of the connection after `timeout`, as
+    /// no read is started after it.
+    pub fn fetch_with<T: MspConnection>(
+        conn: &mut T,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<P, MspError> {
+        let deadline = Instant::now() + timeout;
+        let mut attempt = 0;
+        loop {
+            match Self::fetch_until(conn, deadline) {
+                Err(e) if e.is_transient() && attempt < retries && Instant::now() < deadline => {
+                    debug!("retrying Msp function {}: {}", P::ID, e);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// tries to fetch a payload, sending `request` along as parameter, e.g. a waypoint number
+    pub fn query<Q, T>(request: Q, conn: &mut T) -> Result<P, MspError>
+    where
+        Q: MspPayload + Clone + Debug,
+        T: MspConnection,
+    {
+        let msg = MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Request,
+            flag: None,
+            function: P::ID,
+            payload: Some(request),
+        };
+        Self::round_trip(&msg, conn, None)?
+            .payload
+            .ok_or(MspError::EmptyPayload)
+    }
+
+    /// sends a command with `payload` as parameter and waits for the FC to acknowledge it
+    pub fn send_command<T: MspConnection>(payload: &P, conn: &mut T) -> Result<(), MspError> {
+        let msg = MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Request,
+            flag: None,
+            function: P::ID,
+            payload: Some(payload.clone()),
+        };
+        let ack = msg.request(conn)?;
+        if ack.function != P::ID {
+            conn.mark_failed();
+            return Err(MspError::UnexpectedFunction {
+                expected: P::ID,
+                got: ack.function,
+            });
         }
+        Ok(())
     }
 
-    /// sends the message to
-    pub fn request<T: Read + Write>(&self, conn: &mut T) -> io::Result<Self> {
-        let t_start = std::time::Instant::now();
-        self.encode(conn)?;
+    /// sends the message to `conn` and waits for the response
+    pub fn request<T: MspConnection>(&self, conn: &mut T) -> Result<Self, MspError> {
+        Self::round_trip(self, conn, None)
+    }
+
+    /// sends `request` and decodes the response, which may carry another payload type
+    ///
+    /// Every round-trip is recorded in `MSP_STATS`, a failed one marks `conn` as failed.
+    fn round_trip<Q, T>(
+        request: &MspMessage<Q>,
+        conn: &mut T,
+        deadline: Option<Instant>,
+    ) -> Result<Self, MspError>
+    where
+        Q: MspPayload + Clone + Debug,
+        T: MspConnection,
+    {
+        let t_start = Instant::now();
+        request.encode(conn)?;
         let t_encode = t_start.elapsed();
-        let response = Self::decode(conn)?;
+        let response = Self::decode_until(conn, deadline);
         let t_total = t_start.elapsed();
-        if cfg!(time_metrics) {
-            println!(
-                "time spent: total {:?} encode {:?}, decode {:?}",
-                t_total,
-                t_encode,
-                t_total - t_encode
-            );
+        MSP_STATS.record(&response, t_total);
+        if response.is_err() {
+            conn.mark_failed();
         }
+        let response = response?;
+        debug!(
+            "Msp function {} took {:?}, encode {:?}, decode {:?}, average {:?}",
+            request.function,
+            t_total,
+            t_encode,
+            t_total - t_encode,
+            MSP_STATS.average_latency()
+        );
         Ok(response)
     }
 }
 
-pub trait MspConnection: Read + Write {}
-impl<T: Read + Write> MspConnection for T {}
+/// A connection to the FC which MSP requests are sent over
+pub trait MspConnection: Read + Write {
+    /// tells the connection a request failed, its input may hold the rest of a stale response
+    fn mark_failed(&mut self) {}
+}
+
+impl<T: MspConnection + ?Sized> MspConnection for &mut T {
+    fn mark_failed(&mut self) {
+        (**self).mark_failed()
+    }
+}
+
+impl<T: MspConnection + ?Sized> MspConnection for Box<T> {
+    fn mark_failed(&mut self) {
+        (**self).mark_failed()
+    }
+}
+
+/// the tests stand in an in-memory buffer for the FC
+#[cfg(test)]
+impl MspConnection for io::Cursor<Vec<u8>> {}
+
+/// Tunnels the V2 frames written to it through `MSP_V2_FRAME`, for FCs which only speak V1
+///
+/// Only the requests need to be wrapped, `MspMessage::decode` unwraps tunneled responses by
+/// itself.
+pub struct V1Tunnel<T> {
+    inner: T,
+    request: Vec<u8>,
+}
+
+impl<T> V1Tunnel<T> {
+    pub fn new(inner: T) -> Self {
+        V1Tunnel {
+            inner,
+            request: Vec::new(),
+        }
+    }
+}
+
+impl<T: Read> Read for V1Tunnel<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for V1Tunnel<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        loop {
+            let mut rest = &self.request[..];
+            let mut message = match MspMessage::<MspRaw>::decode(&mut rest) {
+                Ok(message) => message,
+                // the frame is not complete yet
+                Err(MspError::UnexpectedEof) => break,
+                Err(e) => {
+                    self.request.clear();
+                    return Err(e.into());
+                }
+            };
+            let consumed = self.request.len() - rest.len();
+            self.request.drain(..consumed);
+            if message.version == MspVersion::V2 {
+                message.version = MspVersion::V2OverV1;
+            }
+            message.encode(&mut self.inner)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: MspConnection> MspConnection for V1Tunnel<T> {
+    fn mark_failed(&mut self) {
+        self.inner.mark_failed()
+    }
+}
 
 #[cfg(test)]
 mod test_handwritten {
     use super::*;
 
+    #[test]
+    fn error_frames_are_rejections() {
+        let frames: [&[u8]; 3] = [
+            // MSPv2 error for MSP_ATTITUDE, as INAV sends it
+            &[0x24, 0x58, 0x21, 0x00, 0x6c, 0x00, 0x00, 0x00, 0xd8],
+            &[0x24, 0x4d, 0x21, 0x00, 0x6c, 0x6c],
+            // a payload, even of the wrong length, is skipped
+            &[0x24, 0x4d, 0x21, 0x01, 0x6c, 0x01, 0x6c],
+        ];
+        for frame in &frames {
+            match MspMessage::<MspAttitude>::decode(&mut &frame[..]) {
+                Err(MspError::FcRejected { cmd: 108 }) => {}
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        let corrupt = [0x24, 0x4d, 0x21, 0x00, 0x6c, 0x00];
+        assert!(matches!(
+            MspMessage::<MspAttitude>::decode(&mut &corrupt[..]),
+            Err(MspError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn pure_bytes_to_mspv1() {
+        let buf = [0x24u8, 0x4d, 0x3c, 0x00, 0x64, 0x64];
+
+        let message: MspMessage<MspIdent> = MspMessage {
+            version: MspVersion::V1,
+            direction: MspDirection::Request,
+            flag: None,
+            function: 100,
+            payload: None,
+        };
+
+        let new_message = MspMessage::decode(&mut &buf[..]).expect("unable to decode new_message");
+        let mut new_buf = [0u8; 6];
+
+        message
+            .encode(&mut &mut new_buf[..])
+            .expect("unable to encode message");
+
+        assert_eq!(buf, new_buf);
+        assert_eq!(message, new_message);
+    }
+
+    #[test]
+    fn pure_bytes_to_mspv1_payload() {
+        let buf = [
+            0x24u8, 0x4d, 0x3e, 0x06, 0x6c, 0x0a, 0x00, 0xfb, 0xff, 0x5a, 0x00, 0x3e,
+        ];
+
+        let message: MspMessage<MspAttitude> = MspMessage {
+            version: MspVersion::V1,
+            direction: MspDirection::Response,
+            flag: None,
+            function: 108,
+            payload: Some(MspAttitude {
+                angx: 10,
+                angy: -5,
+                heading: 90,
+            }),
+        };
+
+        let new_message = MspMessage::decode(&mut &buf[..]).expect("unable to decode new_message");
+        let mut new_buf = [0u8; 12];
+
+        message
+            .encode(&mut &mut new_buf[..])
+            .expect("unable to encode message");
+
+        assert_eq!(buf, new_buf);
+        assert_eq!(message, new_message);
+    }
+
     #[test]
     fn pure_bytes_to_mspv2() {
         let buf = [0x24u8, 0x58, 0x3c, 0, 0x64, 0, 0, 0, 0x8f];
@@ -634,11 +1622,14 @@ mod test_handwritten {
     fn noised_bytes_to_mspv2() {
         msp_codec! {Special 0x4242, [u8;18]}
 
-        let buf = vec![
-            0x30, 0x60, 0x13, 0x24, 0x58, 0x3e, 0xa5, 0x42, 0x42, 0x12, 0x00, 0x48, 0x65, 0x6c,
-            0x6c, 0x6f, 0x20, 0x66, 0x6c, 0x79, 0x69, 0x6e, 0x67, 0x20, 0x77, 0x6f, 0x72, 0x6c,
-            0x64, 0x82, 0x25,
+        let frame = vec![
+            0x24, 0x58, 0x3e, 0xa5, 0x42, 0x42, 0x12, 0x00, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x20,
+            0x66, 0x6c, 0x79, 0x69, 0x6e, 0x67, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x82,
         ];
+        let mut buf = vec![0x30, 0x60, 0x13];
+        buf.extend_from_slice(&frame);
+        buf.extend_from_slice(&[0x25, 0x24, 0x13, 0x37]);
+        buf.extend_from_slice(&frame);
 
         let message: MspMessage<Special> = MspMessage {
             version: MspVersion::V2,
@@ -653,12 +1644,159 @@ mod test_handwritten {
             )),
         };
 
+        let mut r = &buf[..];
         let new_message: MspMessage<Special> =
-            MspMessage::decode(&mut &buf[..]).expect("unable to decode new_message");
+            MspMessage::decode(&mut r).expect("unable to decode new_message");
+        assert_eq!(message, new_message);
 
+        let new_message: MspMessage<Special> =
+            MspMessage::decode(&mut r).expect("unable to decode second message");
         assert_eq!(message, new_message);
     }
 
+    /// hands out one byte per read, optionally with a timeout in between
+    struct Dribble<'a> {
+        bytes: &'a [u8],
+        stalling: bool,
+        stall: bool,
+    }
+
+    impl Read for Dribble<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.stall = self.stalling && !self.stall;
+            if self.stall {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            (&mut self.bytes).take(1).read(buf)
+        }
+    }
+
+    #[test]
+    fn dribbled_bytes_to_mspv1_payload() {
+        let buf = [
+            0x24u8, 0x4d, 0x3e, 0x06, 0x6c, 0x0a, 0x00, 0xfb, 0xff, 0x5a, 0x00, 0x3e,
+        ];
+        let payload = Some(MspAttitude {
+            angx: 10,
+            angy: -5,
+            heading: 90,
+        });
+
+        let mut r = Dribble {
+            bytes: &buf[..],
+            stalling: false,
+            stall: false,
+        };
+        let message: MspMessage<MspAttitude> =
+            MspMessage::decode(&mut r).expect("unable to reassemble message");
+        assert_eq!(message.payload, payload);
+
+        // a timeout fails the frame, also once it started
+        let mut r = Dribble {
+            bytes: &buf[..],
+            stalling: true,
+            stall: false,
+        };
+        for _ in 0..2 {
+            let result: Result<MspMessage<MspAttitude>, MspError> = MspMessage::decode(&mut r);
+            assert!(
+                matches!(result, Err(MspError::Io(ref e)) if e.kind() == io::ErrorKind::TimedOut)
+            );
+        }
+        assert_eq!(r.bytes.len(), buf.len() - 1);
+    }
+
+    #[test]
+    fn setting_info_encode_decode() {
+        let info = MspSettingInfo {
+            name: "gps_provider".to_string(),
+            pgn: 30,
+            setting_type: 0,
+            section: 1,
+            mode: 1,
+            min: 0,
+            max: 2,
+            index: 7,
+            profile: 0,
+            profile_count: 1,
+            lookup: vec![
+                "NMEA".to_string(),
+                "UBLOX".to_string(),
+                "UBLOX7".to_string(),
+            ],
+            value: vec![1],
+        };
+        let mut buf = Vec::new();
+        info.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), info.size());
+        assert_eq!(
+            MspSettingInfo::decode(&mut &buf[..], buf.len()).unwrap(),
+            info
+        );
+
+        let request = MspSetSetting {
+            index: 7,
+            value: vec![2],
+        };
+        let mut buf = Vec::new();
+        request.encode(&mut buf).unwrap();
+        assert_eq!(buf, [0, 7, 0, 2]);
+        assert_eq!(
+            MspSetSetting::decode(&mut &buf[..], buf.len()).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn resync_after_checksum_error() {
+        // the corrupt length swallows the following frame, which has to be found again
+        let buf = [
+            0x24u8, 0x58, 0x3e, 0, 0x64, 0, 0x09, 0, 0x24, 0x58, 0x3c, 0, 0x64, 0, 0, 0, 0x8f, 0,
+        ];
+
+        let message: MspMessage<MspIdent> = MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Request,
+            flag: Some(0),
+            function: 100,
+            payload: None,
+        };
+        let new_message = MspMessage::decode(&mut &buf[..]).expect("unable to resync");
+        assert_eq!(message, new_message);
+    }
+
+    #[test]
+    fn fixed_payload_length_is_checked() {
+        // MspAttitude with a fourth field of a newer firmware, followed by a regular one
+        let buf = [
+            0x24u8, 0x4d, 0x3e, 0x08, 0x6c, 0x0a, 0x00, 0xfb, 0xff, 0x5a, 0x00, 0x01, 0x00, 0x31,
+            0x24, 0x4d, 0x3e, 0x06, 0x6c, 0x0a, 0x00, 0xfb, 0xff, 0x5a, 0x00, 0x3e,
+        ];
+        let mut r = &buf[..];
+        let result: Result<MspMessage<MspAttitude>, MspError> = MspMessage::decode(&mut r);
+        assert!(matches!(
+            result,
+            Err(MspError::LengthMismatch {
+                expected: 6,
+                got: 8
+            })
+        ));
+        let e = io::Error::from(result.unwrap_err());
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // the reported bytes were consumed, so the next frame is aligned
+        let message: MspMessage<MspAttitude> = MspMessage::decode(&mut r).unwrap();
+        assert_eq!(message.payload.unwrap().heading, 90);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn give_up_on_noise() {
+        let result: Result<MspMessage<MspIdent>, MspError> =
+            MspMessage::decode(&mut io::repeat(0x30));
+        assert!(matches!(result, Err(MspError::UnexpectedEof)));
+    }
+
     #[test]
     fn pure_bytes_to_mspv2_checksum_error() {
         msp_codec! {Special 0x4242, [u8;18]}
@@ -668,8 +1806,266 @@ mod test_handwritten {
             0x66, 0x6c, 0x79, 0x69, 0x6e, 0x67, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x81,
         ];
 
-        let result: io::Result<MspMessage<Special>> = MspMessage::decode(&mut &buf[..]);
-        assert!(result.is_err());
+        let result: Result<MspMessage<Special>, MspError> = MspMessage::decode(&mut &buf[..]);
+        assert!(matches!(
+            result,
+            Err(MspError::ChecksumMismatch {
+                expected: 0x82,
+                got: 0x81
+            })
+        ));
+    }
+
+    /// answers every request with `response`, but times out the first `timeouts` reads
+    struct Flaky {
+        timeouts: usize,
+        response: io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.timeouts > 0 {
+                self.timeouts -= 1;
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.response.set_position(0);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MspConnection for Flaky {}
+
+    #[test]
+    fn fetch_retries_timeouts() {
+        let mut response = Vec::new();
+        MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Response,
+            flag: Some(0),
+            function: MspAltitude::ID,
+            payload: Some(MspAltitude {
+                estimated_alt: 1200,
+                vario: -3,
+            }),
+        }
+        .encode(&mut response)
+        .unwrap();
+        let timeout = Duration::from_secs(1);
+
+        let mut conn = Flaky {
+            timeouts: 2,
+            response: io::Cursor::new(response.clone()),
+        };
+        let altitude: MspAltitude = MspMessage::fetch_with(&mut conn, 2, timeout).unwrap();
+        assert_eq!(altitude.estimated_alt, 1200);
+
+        let mut conn = Flaky {
+            timeouts: 2,
+            response: io::Cursor::new(response),
+        };
+        let result = MspMessage::<MspAltitude>::fetch_with(&mut conn, 1, timeout);
+        assert!(matches!(result, Err(ref e) if e.is_transient()));
+    }
+
+    /// answers every request with `response`, one byte per read after `delay`
+    struct Slow {
+        delay: Duration,
+        response: io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(self.delay);
+            (&mut self.response).take(1).read(buf)
+        }
+    }
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.response.set_position(0);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MspConnection for Slow {}
+
+    #[test]
+    fn fetch_with_ends_dribbling_frames() {
+        let mut response = Vec::new();
+        MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Response,
+            flag: Some(0),
+            function: MspAltitude::ID,
+            payload: Some(MspAltitude {
+                estimated_alt: 1200,
+                vario: -3,
+            }),
+        }
+        .encode(&mut response)
+        .unwrap();
+        // the 15 bytes of the frame take 150 ms to arrive
+        let mut conn = Slow {
+            delay: Duration::from_millis(10),
+            response: io::Cursor::new(response),
+        };
+
+        let t_start = Instant::now();
+        let result = MspMessage::<MspAltitude>::fetch_with(&mut conn, 2, Duration::from_millis(50));
+        assert!(matches!(result, Err(MspError::Timeout)));
+        assert!(t_start.elapsed() < Duration::from_millis(100));
+
+        let altitude: MspAltitude =
+            MspMessage::fetch_with(&mut conn, 0, Duration::from_secs(1)).unwrap();
+        assert_eq!(altitude.estimated_alt, 1200);
+    }
+
+    #[test]
+    fn send_command_checks_ack() {
+        let ack = |function| {
+            let mut buf = Vec::new();
+            MspMessage::<MspSetHead> {
+                version: MspVersion::V2,
+                direction: MspDirection::Response,
+                flag: Some(0),
+                function,
+                payload: None,
+            }
+            .encode(&mut buf)
+            .unwrap();
+            io::Cursor::new(buf)
+        };
+        let head = MspSetHead { mag_hold: 90 };
+
+        let mut conn = Flaky {
+            timeouts: 0,
+            response: ack(MspSetHead::ID),
+        };
+        assert!(MspMessage::send_command(&head, &mut conn).is_ok());
+
+        let mut conn = Flaky {
+            timeouts: 0,
+            response: ack(MspSetRawRc::ID),
+        };
+        let result = MspMessage::send_command(&head, &mut conn);
+        assert!(matches!(
+            result,
+            Err(MspError::UnexpectedFunction { expected, got })
+                if expected == MspSetHead::ID && got == MspSetRawRc::ID
+        ));
+    }
+
+    #[test]
+    fn tunneled_mspv2_over_mspv1() {
+        // MSP_API_VERSION response tunneled through MSP_V2_FRAME, followed by its request
+        let response = [
+            0x24u8, 0x4d, 0x3e, 0x09, 0xff, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x02, 0x04, 0x73,
+            0x81,
+        ];
+        let request = [
+            0x24u8, 0x4d, 0x3c, 0x06, 0xff, 0x00, 0x01, 0x00, 0x00, 0x00, 0x45, 0xbd,
+        ];
+
+        let message: MspMessage<MspApiVersion> =
+            MspMessage::decode(&mut &response[..]).expect("unable to decode tunneled frame");
+        assert_eq!(message.version, MspVersion::V2OverV1);
+        assert_eq!(message.function, MspApiVersion::ID);
+        assert_eq!(
+            message.payload,
+            Some(MspApiVersion {
+                protocol: 0,
+                major: 2,
+                minor: 4
+            })
+        );
+        let mut buf = Vec::new();
+        message.encode(&mut buf).unwrap();
+        assert_eq!(buf, response);
+
+        // a corrupt tunneled checksum is detected even if the outer one fits
+        let mut corrupt = response;
+        corrupt[13] ^= 0xff;
+        corrupt[14] ^= 0xff;
+        let result = MspMessage::<MspApiVersion>::decode(&mut &corrupt[..]);
+        assert!(matches!(result, Err(MspError::ChecksumMismatch { .. })));
+
+        let mut tunnel = V1Tunnel::new(Vec::new());
+        MspMessage::<MspApiVersion> {
+            version: MspVersion::V2,
+            direction: MspDirection::Request,
+            flag: None,
+            function: MspApiVersion::ID,
+            payload: None,
+        }
+        .encode(&mut tunnel)
+        .unwrap();
+        assert_eq!(tunnel.inner, request);
+        assert!(tunnel.request.is_empty());
+    }
+
+    #[test]
+    fn array_payload_access() {
+        let mut channels = [1500u16; 16];
+        channels[2] = 1000;
+        let rc = MspRc::from(channels);
+        assert_eq!(rc[2], 1000);
+        assert_eq!(rc.as_slice().len(), 16);
+        assert!(rc.to_string().starts_with("1500,1500,1000,1500,"));
+        assert!(rc.to_string().ends_with(",1500"));
+        assert_eq!(
+            MspMotorPins::from([1, 2, 3, 4, 5, 6, 7, 8]).to_string(),
+            "1,2,3,4,5,6,7,8"
+        );
+    }
+
+    #[test]
+    fn truncated_bytes_to_mspv2() {
+        let buf = [0x24u8, 0x58, 0x3e, 0xa5, 0x42];
+
+        let result: Result<MspMessage<MspIdent>, MspError> = MspMessage::decode(&mut &buf[..]);
+        assert!(matches!(result, Err(MspError::UnexpectedEof)));
+        let e = io::Error::from(result.unwrap_err());
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stats_of_requests() {
+        let stats = MspStats::new();
+        let ok: Result<(), MspError> = Ok(());
+        stats.record(&ok, Duration::from_millis(8));
+        assert_eq!(stats.average_latency(), Duration::from_millis(8));
+        stats.record(&ok, Duration::from_millis(16));
+        assert_eq!(stats.average_latency(), Duration::from_millis(9));
+
+        let timeout: Result<(), MspError> = Err(io::Error::from(io::ErrorKind::TimedOut).into());
+        stats.record(&timeout, Duration::from_millis(100));
+        let corrupt: Result<(), MspError> = Err(MspError::ChecksumMismatch {
+            expected: 0,
+            got: 1,
+        });
+        stats.record(&corrupt, Duration::from_millis(1));
+
+        assert_eq!(stats.requests.load(Ordering::Relaxed), 4);
+        assert_eq!(stats.responses.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.timeouts.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.checksum_errors.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.latency_buckets[3].load(Ordering::Relaxed), 1);
+        assert_eq!(stats.latency_buckets[4].load(Ordering::Relaxed), 1);
+        assert_eq!(stats.average_latency(), Duration::from_millis(9));
     }
 
     //
@@ -0,0 +1,401 @@
+//! Recorded MSP traffic, to reproduce field bugs and to run the bridge without a FC
+//!
+//! A recording is a text file with one line per burst of bytes on the MSP connection:
+//!
+//! ```text
+//! 1520 tx 24583c006400000034
+//! 4810 rx 2458 3e00 6400 0700 0001 0203 0405 06e1
+//! ```
+//!
+//! Each line holds the microseconds since the start of the recording, the direction (`tx` to the
+//! FC, `rx` from the FC) and the bytes in hex. Whitespace between the hex digits is ignored, as
+//! are empty lines and lines starting with `#`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::msp::MspConnection;
+
+/// Direction of recorded bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    /// sent to the FC
+    Tx,
+    /// received from the FC
+    Rx,
+}
+
+/// One line of a recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub micros: u64,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Tx => "tx",
+            Direction::Rx => "rx",
+        };
+        write!(f, "{} {} ", self.micros, direction)?;
+        for b in &self.bytes {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Entry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.splitn(3, char::is_whitespace);
+        let micros = fields.next().unwrap_or_default();
+        let micros = micros
+            .parse()
+            .map_err(|_| format!("invalid timestamp {}", micros))?;
+        let direction = match fields.next() {
+            Some("tx") => Direction::Tx,
+            Some("rx") => Direction::Rx,
+            d => return Err(format!("invalid direction {}", d.unwrap_or_default())),
+        };
+        let hex: Vec<char> = fields
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        if hex.len() % 2 != 0 {
+            return Err("odd number of hex digits".to_string());
+        }
+        let bytes = hex
+            .chunks(2)
+            .map(|pair| {
+                let pair: String = pair.iter().collect();
+                u8::from_str_radix(&pair, 16).map_err(|_| format!("invalid hex {}", pair))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Entry {
+            micros,
+            direction,
+            bytes,
+        })
+    }
+}
+
+/// function of the MSPv1 or MSPv2 request at the start of `bytes`
+fn request_function(bytes: &[u8]) -> Option<u16> {
+    match bytes {
+        [b'$', b'M', b'<', _len, function, ..] => Some(*function as u16),
+        [b'$', b'X', b'<', _flag, lo, hi, ..] => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+/// Pretends to be a FC by answering requests with recorded responses
+///
+/// The responses to each function are served in the recorded order and start over once all of
+/// them were served. A request without recorded response times out, just like a silent FC.
+#[derive(Debug, Default)]
+pub struct ReplayConnection {
+    responses: HashMap<u16, Vec<Vec<u8>>>,
+    served: HashMap<u16, usize>,
+    request: Vec<u8>,
+    response: VecDeque<u8>,
+}
+
+impl ReplayConnection {
+    /// loads a recording from a file
+    pub fn open(path: &str) -> io::Result<Self> {
+        let recording = fs::read_to_string(path)?;
+        recording
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+    }
+
+    /// queues the next recorded response to the request written so far
+    fn respond(&mut self) {
+        let function = match request_function(&self.request) {
+            Some(function) => function,
+            None => {
+                warn!("unable to replay unknown request {:?}", self.request);
+                self.request.clear();
+                return;
+            }
+        };
+        self.request.clear();
+        match self.responses.get(&function) {
+            Some(responses) => {
+                let served = self.served.entry(function).or_insert(0);
+                self.response.extend(&responses[*served % responses.len()]);
+                *served += 1;
+            }
+            None => warn!("no recorded response to function {}", function),
+        }
+    }
+}
+
+impl FromStr for ReplayConnection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conn = ReplayConnection::default();
+        let mut function = None;
+        for (no, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry: Entry = line
+                .parse()
+                .map_err(|e| format!("line {}: {}", no + 1, e))?;
+            match entry.direction {
+                Direction::Tx => {
+                    function = request_function(&entry.bytes);
+                    if let Some(function) = function {
+                        conn.responses.entry(function).or_default().push(Vec::new());
+                    }
+                }
+                // responses before the first request have nothing to answer
+                Direction::Rx => {
+                    if let Some(response) = function
+                        .and_then(|f| conn.responses.get_mut(&f))
+                        .and_then(|r| r.last_mut())
+                    {
+                        response.extend(entry.bytes);
+                    }
+                }
+            }
+        }
+        Ok(conn)
+    }
+}
+
+impl Read for ReplayConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.request.is_empty() {
+            self.respond();
+        }
+        if self.response.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let len = buf.len().min(self.response.len());
+        for (b, r) in buf.iter_mut().zip(self.response.drain(..len)) {
+            *b = r;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for ReplayConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        trace!("replay discards {:?}", buf);
+        self.request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MspConnection for ReplayConnection {}
+
+/// Tees the traffic of a MSP connection into a recording
+///
+/// Consecutive reads or writes are collected into one entry, so a recording holds one line per
+/// request and one per response, timestamped with the arrival of their first byte.
+pub struct RecordingConnection<T, W: Write = fs::File> {
+    inner: T,
+    log: W,
+    t0: Instant,
+    pending: Option<Entry>,
+}
+
+impl<T, W: Write> RecordingConnection<T, W> {
+    /// records the traffic of `inner` to `log`, timestamps count from `t0`
+    pub fn new(inner: T, log: W, t0: Instant) -> Self {
+        RecordingConnection {
+            inner,
+            log,
+            t0,
+            pending: None,
+        }
+    }
+
+    /// adds `bytes` to the pending entry, writing it out first if the direction changed
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        match &mut self.pending {
+            Some(entry) if entry.direction == direction => entry.bytes.extend_from_slice(bytes),
+            _ => {
+                self.write_pending()?;
+                self.pending = Some(Entry {
+                    micros: self.t0.elapsed().as_micros() as u64,
+                    direction,
+                    bytes: bytes.to_vec(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// writes the pending entry to the log
+    fn write_pending(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some(entry) => writeln!(self.log, "{}", entry),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Read, W: Write> Read for RecordingConnection<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.record(Direction::Rx, &buf[..len])?;
+        Ok(len)
+    }
+}
+
+impl<T: Write, W: Write> Write for RecordingConnection<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.record(Direction::Tx, &buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.log.flush()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: MspConnection, W: Write> MspConnection for RecordingConnection<T, W> {
+    fn mark_failed(&mut self) {
+        self.inner.mark_failed()
+    }
+}
+
+impl<T, W: Write> Drop for RecordingConnection<T, W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_pending().and_then(|_| self.log.flush()) {
+            warn!("unable to complete MSP recording: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::msp::*;
+
+    /// encodes a MSPv2 frame of the function of `P`
+    pub fn frame<P: MspPayload + Clone + fmt::Debug>(
+        direction: MspDirection,
+        payload: Option<P>,
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        MspMessage {
+            version: MspVersion::V2,
+            direction,
+            flag: None,
+            function: P::ID,
+            payload,
+        }
+        .encode(&mut buf)
+        .unwrap();
+        buf
+    }
+
+    /// a line of a recording
+    pub fn entry(micros: u64, direction: Direction, bytes: Vec<u8>) -> String {
+        Entry {
+            micros,
+            direction,
+            bytes,
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn entry_roundtrip() {
+        let e = Entry {
+            micros: 1520,
+            direction: Direction::Rx,
+            bytes: vec![0x24, 0x58, 0x3e, 0x00, 0xff],
+        };
+        assert_eq!(e.to_string(), "1520 rx 24583e00ff");
+        assert_eq!("1520 rx 2458 3e00 ff".parse(), Ok(e));
+        assert!("1520 up 00".parse::<Entry>().is_err());
+        assert!("1520 tx 0".parse::<Entry>().is_err());
+    }
+
+    /// two altitude requests, answered with 1 and 3 m
+    fn altitude_recording() -> String {
+        let request = frame::<MspAltitude>(MspDirection::Request, None);
+        let response = |estimated_alt| {
+            frame(
+                MspDirection::Response,
+                Some(MspAltitude {
+                    estimated_alt,
+                    vario: 0,
+                }),
+            )
+        };
+        [
+            "# recorded on the bench".to_string(),
+            entry(0, Direction::Rx, vec![0x00]),
+            entry(10, Direction::Tx, request.clone()),
+            entry(20, Direction::Rx, response(1)),
+            entry(30, Direction::Tx, request),
+            entry(40, Direction::Rx, response(3)),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn replay_in_a_loop() {
+        let mut conn: ReplayConnection = altitude_recording().parse().unwrap();
+
+        for alt in &[1, 3, 1] {
+            let payload: MspAltitude = MspMessage::fetch(&mut conn).unwrap();
+            assert_eq!(payload.estimated_alt, *alt);
+        }
+        assert!(MspMessage::<MspStatus>::fetch(&mut conn).is_err());
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let replay: ReplayConnection = altitude_recording().parse().unwrap();
+        let mut log = Vec::new();
+        let mut conn = RecordingConnection::new(replay, &mut log, Instant::now());
+        for _ in 0..2 {
+            let _: MspAltitude = MspMessage::fetch(&mut conn).unwrap();
+        }
+        drop(conn);
+
+        let recording = String::from_utf8(log).unwrap();
+        let entries: Vec<Entry> = recording.lines().map(|l| l.parse().unwrap()).collect();
+        let directions: Vec<_> = entries.iter().map(|e| e.direction).collect();
+        assert_eq!(
+            directions,
+            vec![Direction::Tx, Direction::Rx, Direction::Tx, Direction::Rx]
+        );
+        assert!(entries.windows(2).all(|w| w[0].micros <= w[1].micros));
+
+        let mut conn: ReplayConnection = recording.parse().unwrap();
+        for alt in &[1, 3] {
+            let payload: MspAltitude = MspMessage::fetch(&mut conn).unwrap();
+            assert_eq!(payload.estimated_alt, *alt);
+        }
+    }
+}
@@ -24,12 +24,18 @@ struct FrameInformation {
 }
 
 impl<T: Clone + Copy + PartialEq> Schedule<T> {
-    /// Initializes a new instance of `Schedule`
+    /// Initializes a new instance of `Schedule` with a major frame of one second
+    #[cfg(test)]
     pub fn new(size: usize) -> Self {
+        Self::with_duration(size, Duration::new(1, 0))
+    }
+
+    /// Initializes a new instance of `Schedule` with a major frame of `duration`
+    pub fn with_duration(size: usize, duration: Duration) -> Self {
         Schedule {
             time: vec![ArcSwapOption::from(None); size],
             len: size.try_into().expect("Schedule too big"),
-            duration: Duration::new(1, 0),
+            duration,
             frame: Arc::new(Mutex::new(FrameInformation {
                 last: 0,
                 last_time: Instant::now(),
@@ -42,8 +48,7 @@ impl<T: Clone + Copy + PartialEq> Schedule<T> {
         loop {
             let mut fi = self.frame.lock().await;
             let index = (fi.last % self.len as u128) as usize;
-            let minor_frame_duration = self.duration / self.len;
-            let next_minor_frame_time = fi.last_time + minor_frame_duration;
+            let next_minor_frame_time = fi.last_time + self.minor_frame();
 
             Timer::at(next_minor_frame_time).await;
             fi.last_time = next_minor_frame_time;
@@ -54,6 +59,11 @@ impl<T: Clone + Copy + PartialEq> Schedule<T> {
         }
     }
 
+    /// duration of one slot
+    pub fn minor_frame(&self) -> Duration {
+        self.duration / self.len
+    }
+
     /// counts the occurences of a given task in the current schedule
     pub fn count(&self, task: &T) -> usize {
         self.time
@@ -62,66 +72,120 @@ impl<T: Clone + Copy + PartialEq> Schedule<T> {
             .count()
     }
 
-    /// tries to insert a schedule into
-    pub fn insert(&self, frequency: u32, task: T) -> Result<(), &'static str> {
-        if frequency == 0 {
+    /// lists every distinct task of the current schedule with the number of its slots
+    pub fn entries(&self) -> Vec<(T, usize)> {
+        let mut entries: Vec<(T, usize)> = Vec::new();
+        for mt in &self.time {
+            if let Some(task) = mt.load().as_ref() {
+                match entries.iter_mut().find(|(t, _)| *t == **task) {
+                    Some((_, count)) => *count += 1,
+                    None => entries.push((**task, 1)),
+                }
+            }
+        }
+        entries
+    }
+
+    /// share of the slots which hold a task, from 0 to 1
+    pub fn utilization(&self) -> f64 {
+        let used = self.time.iter().filter(|mt| mt.load().is_some()).count();
+        used as f64 / self.time.len() as f64
+    }
+
+    /// effective frequency of a given task in Hz
+    pub fn frequency_of(&self, task: &T) -> f64 {
+        self.count(task) as f64 / self.duration.as_secs_f64()
+    }
+
+    /// tries to insert a schedule into, yielding the indices of the slots it occupies
+    ///
+    /// The slots are claimed by compare-and-swap, if a concurrent `insert` claims one of them
+    /// first the already claimed slots are released and the placement is planned again. A
+    /// frequency of 0 or below deletes the task and occupies no slot, while a positive one which
+    /// rounds to no slot per major frame is an error.
+    pub fn insert(&self, frequency: f64, task: T) -> Result<Vec<usize>, &'static str> {
+        if frequency <= 0. {
             self.delete(&task);
-            return Ok(());
+            return Ok(Vec::new());
         }
         let mut new_schedule = vec![0; self.time.len()];
-        let interval = self.time.len() as f64 / frequency as f64 / self.duration.as_secs_f64();
+        let interval = self.time.len() as f64 / frequency / self.duration.as_secs_f64();
 
-        let frame_count = (self.duration.as_secs_f64() * frequency as f64).round() as usize;
+        let frame_count = (self.duration.as_secs_f64() * frequency).round() as usize;
+        if frame_count > self.time.len() {
+            return Err("frequency exceeds the slots of the schedule");
+        }
+        if frame_count == 0 {
+            return Err("frequency is below one slot per major frame");
+        }
         for i in 0..frame_count {
             let index = (i as f64 * interval).round() as usize;
             new_schedule[index] = 1;
         }
 
-        let mut min = usize::max_value();
-        let mut tau = 0;
+        let task = Some(Arc::new(task));
+        loop {
+            let tau = self
+                .plan(&new_schedule)
+                .ok_or("task does not fit current schedule")?;
+            if let Some(slots) = self.commit(&new_schedule, tau, &task) {
+                return Ok(slots);
+            }
+        }
+    }
+
+    /// finds the offset at which `new_schedule` does not collide with any occupied slot
+    fn plan(&self, new_schedule: &[usize]) -> Option<usize> {
         let time_use: Vec<usize> = self
             .time
             .iter()
             .map(|mt| if (*mt.load()).is_none() { 0 } else { 1 })
             .collect();
-        for i in 0..self.time.len() {
-            let sum: usize = time_use
+        (0..self.time.len()).find(|i| {
+            time_use
                 .iter()
-                .zip(new_schedule.iter().cycle().skip(i))
-                .map(|a| a.0 * a.1)
-                .sum();
-            if sum < min {
-                min = sum;
-                tau = i;
-            }
-            if sum == 0 {
-                break;
-            }
-        }
-        match min {
-            0 => {
-                for (i, t) in new_schedule
-                    .iter()
-                    .cycle()
-                    .skip(tau)
-                    .enumerate()
-                    .take(self.time.len())
-                {
-                    if *t == 1 {
-                        assert!(self.time[i].load().is_none());
-                        self.time[i].store(Some(Arc::new(task)));
-                    }
+                .zip(new_schedule.iter().cycle().skip(*i))
+                .all(|a| a.0 * a.1 == 0)
+        })
+    }
+
+    /// claims the slots of `new_schedule` shifted by `tau`, releases them again on a collision
+    fn commit(
+        &self,
+        new_schedule: &[usize],
+        tau: usize,
+        task: &Option<Arc<T>>,
+    ) -> Option<Vec<usize>> {
+        let slots: Vec<usize> = new_schedule
+            .iter()
+            .cycle()
+            .skip(tau)
+            .enumerate()
+            .take(self.time.len())
+            .filter(|(_, t)| **t == 1)
+            .map(|(i, _)| i)
+            .collect();
+        for (n, i) in slots.iter().enumerate() {
+            let previous = self.time[*i].compare_and_swap(&None::<Arc<T>>, task.clone());
+            if previous.is_some() {
+                for i in &slots[..n] {
+                    self.time[*i].compare_and_swap(task, None);
                 }
-                Ok(())
+                return None;
             }
-            _ => Err("task does not fit current schedule"),
         }
+        Some(slots)
     }
 
     pub fn delete(&self, task: &T) {
-        self.time.iter().for_each(|mt| match mt.load().as_ref() {
-            Some(ref t) if *task == ***t => mt.store(None),
-            _ => {}
+        self.time.iter().for_each(|mt| {
+            let current = mt.load();
+            match current.as_ref() {
+                Some(ref t) if *task == ***t => {
+                    mt.compare_and_swap(&current, None);
+                }
+                _ => {}
+            }
         })
     }
 }
@@ -166,7 +230,7 @@ mod test {
         let range = 3..10;
         for i in range.clone() {
             let t = Task { id: i };
-            s.insert(i, t).unwrap();
+            s.insert(i.into(), t).unwrap();
         }
         for i in range {
             let count = s.count(&Task { id: i });
@@ -181,7 +245,7 @@ mod test {
         let range = 0..20;
         for i in range.clone() {
             let t = Task { id: i };
-            s.insert(freq, t).unwrap();
+            s.insert(freq.into(), t).unwrap();
         }
         for i in range {
             let count = s.count(&Task { id: i });
@@ -194,7 +258,142 @@ mod test {
         let s = Schedule::new(200);
         for i in 3..14 {
             let t = Task { id: i };
-            s.insert(i % 5 + 1, t).unwrap();
+            s.insert((i % 5 + 1).into(), t).unwrap();
+        }
+    }
+
+    #[test]
+    fn sub_hertz_frequency() {
+        let s = Schedule::with_duration(10, Duration::from_secs(2));
+        let t = Task { id: 1 };
+        s.insert(0.5, t).unwrap();
+        assert_eq!(s.count(&t), 1);
+    }
+
+    #[test]
+    fn frequency_below_one_slot() {
+        let s = Schedule::new(50);
+        let t = Task { id: 1 };
+        assert!(s.insert(0.4, t).is_err());
+        assert!(s.insert(f64::NAN, t).is_err());
+        assert_eq!(s.count(&t), 0);
+        assert_eq!(s.insert(0.6, t).unwrap().len(), 1);
+        assert_eq!(s.insert(0., t), Ok(Vec::new()));
+        assert_eq!(s.count(&t), 0);
+    }
+
+    #[test]
+    fn frequency_above_slots() {
+        let s = Schedule::new(50);
+        let t = Task { id: 1 };
+        assert!(s.insert(60., t).is_err());
+        assert_eq!(s.count(&t), 0);
+        assert!(s.insert(50., t).is_ok());
+        assert_eq!(s.count(&t), 50);
+    }
+
+    /// the slots holding `task`, in ascending order
+    fn slots_of(s: &Schedule<Task>, task: Task) -> Vec<usize> {
+        (0..s.time.len())
+            .filter(|i| matches!(s.time[*i].load().as_ref(), Some(t) if **t == task))
+            .collect()
+    }
+
+    /// distances between consecutive `slots`, wrapping around the end of a frame of `len`
+    fn gaps(slots: &[usize], len: usize) -> Vec<usize> {
+        slots
+            .iter()
+            .zip(slots.iter().cycle().skip(1))
+            .map(|(a, b)| (b + len - a - 1) % len + 1)
+            .collect()
+    }
+
+    #[test]
+    fn even_spacing() {
+        let s = Schedule::new(50);
+        let first = Task { id: 1 };
+        let slots = s.insert(10., first).unwrap();
+        assert_eq!(slots, slots_of(&s, first));
+        assert_eq!(slots.len(), 10);
+        assert!(gaps(&slots, 50).iter().all(|gap| *gap == 5));
+
+        // a frequency not dividing the slots still spreads without clustering
+        let second = Task { id: 2 };
+        let gaps = gaps(&s.insert(3., second).unwrap(), 50);
+        assert_eq!(gaps.len(), 3);
+        assert!(gaps.iter().max().unwrap() - gaps.iter().min().unwrap() <= 1);
+    }
+
+    #[test]
+    fn equal_frequencies_interleave() {
+        let s = Schedule::new(50);
+        let (first, second) = (Task { id: 1 }, Task { id: 2 });
+        let a = s.insert(10., first).unwrap();
+        let b = s.insert(10., second).unwrap();
+        assert!(a.iter().all(|slot| !b.contains(slot)));
+        assert!(gaps(&b, 50).iter().all(|gap| *gap == 5));
+        // the second task is shifted by a constant offset, so both keep their spacing
+        let offset = (b[0] + 50 - a[0]) % 50;
+        assert!(a.iter().zip(&b).all(|(a, b)| (b + 50 - a) % 50 == offset));
+    }
+
+    #[test]
+    fn full_frame_does_not_fit() {
+        let s = Schedule::new(50);
+        for id in 0..5 {
+            s.insert(10., Task { id }).unwrap();
+        }
+        assert_eq!(s.utilization(), 1.);
+        let late = Task { id: 5 };
+        assert_eq!(
+            s.insert(1., late),
+            Err("task does not fit current schedule")
+        );
+        assert_eq!(s.count(&late), 0);
+
+        // free slots of the wrong pattern do not fit either
+        let s = Schedule::new(50);
+        s.insert(25., Task { id: 1 }).unwrap();
+        assert!(s.insert(10., late).is_err());
+        assert!(s.insert(25., late).is_ok());
+        assert_eq!(s.insert(0., late), Ok(Vec::new()));
+        assert_eq!(s.count(&late), 0);
+    }
+
+    #[test]
+    fn entries_and_frequencies() {
+        let s = Schedule::with_duration(100, Duration::from_secs(2));
+        s.insert(5., Task { id: 1 }).unwrap();
+        s.insert(2., Task { id: 2 }).unwrap();
+        let mut entries = s.entries();
+        entries.sort_by_key(|(t, _)| t.id);
+        assert_eq!(entries, vec![(Task { id: 1 }, 10), (Task { id: 2 }, 4)]);
+        assert_eq!(s.frequency_of(&Task { id: 1 }), 5.);
+        assert_eq!(s.frequency_of(&Task { id: 3 }), 0.);
+    }
+
+    #[test]
+    fn concurrent_insert_delete() {
+        let s = Arc::new(Schedule::new(100));
+        let threads: Vec<_> = (0..8)
+            .map(|id| {
+                let s = s.clone();
+                std::thread::spawn(move || {
+                    let t = Task { id };
+                    for i in 0..500 {
+                        if s.insert((i % 7 + 1).into(), t).is_ok() {
+                            s.delete(&t);
+                        }
+                    }
+                    s.insert(5., t).unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        for id in 0..8 {
+            assert_eq!(s.count(&Task { id }), 5);
         }
     }
 
@@ -207,7 +406,7 @@ mod test {
         let tol = Duration::from_millis(10);
         let hundred_milli = Duration::from_millis(100);
         smol::block_on(async move {
-            s.insert(3, t).unwrap();
+            s.insert(3., t).unwrap();
             assert_eq!(s.next().await, t);
             assert!(hundred_milli < t0.elapsed() && t0.elapsed() < hundred_milli + tol);
             sleep(Duration::from_millis(700));
@@ -0,0 +1,402 @@
+//! Simulated generators, to try the scheduler and a GCS without a FC
+//!
+//! Everything is derived from the time since start: the drone circles home at a constant height
+//! while rocking gently, and its battery drains within `BATTERY_DRAIN` before starting over. No
+//! generator touches the MSP connection.
+
+use std::io;
+
+use mavlink::common::MavMessage::*;
+use mavlink::common::*;
+
+use crate::core::TranslatorRegistry;
+use crate::msp::MspConnection;
+use crate::translator::{
+    cell_voltages, local_offset, offset_position, quaternion, servo_output_port,
+    standard_atmosphere, system_time, unsent_origin, BridgeState, MSP_STATUS_ARMED,
+};
+use crate::Config;
+
+/// position of home in 1e7 deg
+const HOME: (i32, i32) = (473_977_420, 85_455_940);
+
+/// altitude of home above mean sea level in mm
+const HOME_ALT: i32 = 488_000;
+
+/// radius of the circle flown around home in m
+const CIRCLE_RADIUS: f64 = 50.;
+
+/// ground speed on the circle in m/s
+const CIRCLE_SPEED: f64 = 5.;
+
+/// height above home in m
+const HEIGHT: f64 = 20.;
+
+/// number of LiPo cells of the battery
+const BATTERY_CELLS: u8 = 4;
+
+/// cell voltage of a full and of an empty battery
+const CELL_VOLTAGE: (f64, f64) = (4.2, 3.3);
+
+/// time in seconds to drain a full battery
+const BATTERY_DRAIN: f64 = 600.;
+
+/// constant current drawn from the battery in cA
+const BATTERY_CURRENT: i16 = 1000;
+
+/// seconds since start
+fn elapsed(conf: &Config) -> f64 {
+    conf.t0.elapsed().as_secs_f64()
+}
+
+/// position in 1e7 deg and course in deg on the circle around home after `t` seconds
+fn track(t: f64) -> (i32, i32, f64) {
+    let bearing = (t * CIRCLE_SPEED / CIRCLE_RADIUS).to_degrees() % 360.;
+    let (lat, lon) = offset_position(HOME.0, HOME.1, CIRCLE_RADIUS, bearing);
+    // flying clockwise, the course is perpendicular to the bearing from home
+    (lat, lon, (bearing + 90.) % 360.)
+}
+
+/// attitude while circling after `t` seconds, banked into the turn and rocking a little
+fn attitude_data(t: f64) -> ATTITUDE_DATA {
+    let pi = std::f64::consts::PI;
+    let bank = (CIRCLE_SPEED.powi(2) / (CIRCLE_RADIUS * 9.81)).atan();
+    let (_, _, course) = track(t);
+    let yaw = match course.to_radians() {
+        yaw if yaw > pi => yaw - 2. * pi,
+        yaw => yaw,
+    };
+    ATTITUDE_DATA {
+        time_boot_ms: (t * 1000.) as u32,
+        roll: (bank + 0.05 * (1.3 * t).sin()) as f32,
+        pitch: (-0.05 + 0.05 * (0.7 * t).sin()) as f32,
+        yaw: yaw as f32,
+        rollspeed: (0.065 * (1.3 * t).cos()) as f32,
+        pitchspeed: (0.035 * (0.7 * t).cos()) as f32,
+        yawspeed: (CIRCLE_SPEED / CIRCLE_RADIUS) as f32,
+    }
+}
+
+/// pack voltage in mV, drawn charge in mAh and remaining charge in percent after `t` seconds
+fn battery(t: f64) -> (u16, i32, i8) {
+    let drained = (t % BATTERY_DRAIN) / BATTERY_DRAIN;
+    let (full, empty) = CELL_VOLTAGE;
+    let cell = full - (full - empty) * drained;
+    let capacity = BATTERY_CURRENT as f64 * 10. * BATTERY_DRAIN / 3600.;
+    (
+        (cell * BATTERY_CELLS as f64 * 1000.).round() as u16,
+        (capacity * drained).round() as i32,
+        ((1. - drained) * 100.).round() as i8,
+    )
+}
+
+pub fn heartbeat(
+    _conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    Ok(vec![HEARTBEAT(HEARTBEAT_DATA {
+        custom_mode: MSP_STATUS_ARMED,
+        mavtype: MavType::MAV_TYPE_QUADROTOR,
+        autopilot: MavAutopilot::MAV_AUTOPILOT_GENERIC,
+        base_mode: MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
+            | MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED,
+        system_status: MavState::MAV_STATE_ACTIVE,
+        mavlink_version: 0x3,
+    })])
+}
+
+pub fn sys_status(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let sensors = MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_GYRO
+        | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_ACCEL
+        | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_MAG
+        | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_ABSOLUTE_PRESSURE
+        | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_GPS;
+    let (voltage, _, remaining) = battery(elapsed(conf));
+    Ok(vec![SYS_STATUS(SYS_STATUS_DATA {
+        onboard_control_sensors_present: sensors,
+        onboard_control_sensors_enabled: sensors,
+        onboard_control_sensors_health: sensors,
+        load: 0,
+        voltage_battery: voltage,
+        current_battery: BATTERY_CURRENT,
+        drop_rate_comm: 0,
+        errors_comm: 0,
+        errors_count1: 0,
+        errors_count2: 0,
+        errors_count3: 0,
+        errors_count4: 0,
+        battery_remaining: remaining,
+    })])
+}
+
+pub fn gps_raw_int(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let (lat, lon, course) = track(elapsed(conf));
+    Ok(vec![GPS_RAW_INT(GPS_RAW_INT_DATA {
+        time_usec: conf.t0.elapsed().as_micros() as u64,
+        lat,
+        lon,
+        alt: HOME_ALT + (HEIGHT * 1000.) as i32,
+        eph: 100,
+        epv: 150,
+        vel: (CIRCLE_SPEED * 100.) as u16,
+        cog: (course * 100.) as u16,
+        fix_type: GpsFixType::GPS_FIX_TYPE_3D_FIX,
+        satellites_visible: 12,
+    })])
+}
+
+pub fn global_position_int(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let (lat, lon, course) = track(elapsed(conf));
+    let (east, north) = course.to_radians().sin_cos();
+    Ok(vec![GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        lat,
+        lon,
+        alt: HOME_ALT + (HEIGHT * 1000.) as i32,
+        relative_alt: (HEIGHT * 1000.) as i32,
+        vx: (north * CIRCLE_SPEED * 100.).round() as i16,
+        vy: (east * CIRCLE_SPEED * 100.).round() as i16,
+        vz: 0,
+        hdg: (course * 100.) as u16,
+    })])
+}
+
+pub fn local_position_ned(
+    conf: &Config,
+    state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let origin = match state.gps_origin.get() {
+        Some(origin) => origin,
+        None => return Ok(Vec::new()),
+    };
+    let (lat, lon, course) = track(elapsed(conf));
+    let (x, y) = local_offset((origin.latitude, origin.longitude), lat, lon);
+    let (east, north) = course.to_radians().sin_cos();
+    Ok(vec![LOCAL_POSITION_NED(LOCAL_POSITION_NED_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        x: x as f32,
+        y: y as f32,
+        z: (origin.altitude - HOME_ALT) as f32 / 1000. - HEIGHT as f32,
+        vx: (north * CIRCLE_SPEED) as f32,
+        vy: (east * CIRCLE_SPEED) as f32,
+        vz: 0.,
+    })])
+}
+
+pub fn home_position(
+    _conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    Ok(vec![HOME_POSITION(HOME_POSITION_DATA {
+        latitude: HOME.0,
+        longitude: HOME.1,
+        altitude: HOME_ALT,
+        x: 0.,
+        y: 0.,
+        z: 0.,
+        q: [1., 0., 0., 0.],
+        approach_x: 0.,
+        approach_y: 0.,
+        approach_z: 0.,
+    })])
+}
+
+pub fn gps_global_origin(
+    _conf: &Config,
+    state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let origin = state.gps_origin.get_or_set(GPS_GLOBAL_ORIGIN_DATA {
+        latitude: HOME.0,
+        longitude: HOME.1,
+        altitude: HOME_ALT,
+    });
+    Ok(unsent_origin(origin, context))
+}
+
+pub fn scaled_pressure(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let (press_abs, temperature) = standard_atmosphere(HEIGHT as f32);
+    Ok(vec![SCALED_PRESSURE(SCALED_PRESSURE_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        press_abs,
+        press_diff: 0.,
+        temperature,
+    })])
+}
+
+pub fn attitude(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    Ok(vec![ATTITUDE(attitude_data(elapsed(conf)))])
+}
+
+pub fn attitude_quaternion(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let attitude = attitude_data(elapsed(conf));
+    let [q1, q2, q3, q4] = quaternion(attitude.roll, attitude.pitch, attitude.yaw);
+    Ok(vec![ATTITUDE_QUATERNION(ATTITUDE_QUATERNION_DATA {
+        time_boot_ms: attitude.time_boot_ms,
+        q1,
+        q2,
+        q3,
+        q4,
+        rollspeed: attitude.rollspeed,
+        pitchspeed: attitude.pitchspeed,
+        yawspeed: attitude.yawspeed,
+    })])
+}
+
+/// motor output in microseconds to hover
+const HOVER_US: f32 = 1450.;
+
+/// outputs of the four motors of a quad X, mixing the attitude into the hover throttle
+fn motor_outputs(attitude: &ATTITUDE_DATA) -> [u16; 8] {
+    let (roll, pitch) = (attitude.roll * 200., attitude.pitch * 200.);
+    let mut outputs = [0; 8];
+    let mix = [(-1., 1.), (-1., -1.), (1., 1.), (1., -1.)];
+    for (output, (r, p)) in outputs.iter_mut().zip(mix.iter()) {
+        *output = (HOVER_US + r * roll + p * pitch).round() as u16;
+    }
+    outputs
+}
+
+/// the motors on port 2, as `translator::servo_output_raw` reports them, the simulated drone
+/// has no servos
+pub fn servo_output_raw(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let attitude = attitude_data(elapsed(conf));
+    let time_usec = conf.t0.elapsed().as_micros() as u32;
+    Ok(vec![SERVO_OUTPUT_RAW(servo_output_port(
+        time_usec,
+        &motor_outputs(&attitude),
+        2,
+    ))])
+}
+
+pub fn battery_status(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let (voltage, current_consumed, battery_remaining) = battery(elapsed(conf));
+    Ok(vec![BATTERY_STATUS(BATTERY_STATUS_DATA {
+        current_consumed,
+        energy_consumed: -1,
+        temperature: i16::MAX,
+        voltages: cell_voltages(BATTERY_CELLS, voltage / 10),
+        current_battery: BATTERY_CURRENT,
+        id: 0,
+        battery_function: MavBatteryFunction::MAV_BATTERY_FUNCTION_ALL,
+        mavtype: MavBatteryType::MAV_BATTERY_TYPE_LIPO,
+        battery_remaining,
+    })])
+}
+
+pub fn extended_sys_state(
+    _conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    Ok(vec![EXTENDED_SYS_STATE(EXTENDED_SYS_STATE_DATA {
+        vtol_state: MavVtolState::MAV_VTOL_STATE_UNDEFINED,
+        landed_state: MavLandedState::MAV_LANDED_STATE_IN_AIR,
+    })])
+}
+
+/// the simulated generators, used instead of the default registry by `--simulate`
+pub fn registry() -> TranslatorRegistry {
+    let mut registry = TranslatorRegistry::new();
+    registry
+        .register(0, heartbeat, &[])
+        .register(1, sys_status, &[])
+        .register(2, system_time, &[])
+        .register(24, gps_raw_int, &[])
+        .register(29, scaled_pressure, &[])
+        .register(30, attitude, &[])
+        .register(31, attitude_quaternion, &[])
+        .register(32, local_position_ned, &[])
+        .register(33, global_position_int, &[])
+        .register(36, servo_output_raw, &[])
+        .register(49, gps_global_origin, &[])
+        .register(147, battery_status, &[])
+        .register(242, home_position, &[])
+        .register(245, extended_sys_state, &[]);
+    registry
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn track_circles_home() {
+        let period = 2. * std::f64::consts::PI * CIRCLE_RADIUS / CIRCLE_SPEED;
+        for t in &[0., period / 4., period / 2., 0.75 * period] {
+            let (lat, lon, course) = track(*t);
+            let north = (lat - HOME.0) as f64 / 1e7 * 111_195.;
+            let east =
+                (lon - HOME.1) as f64 / 1e7 * 111_195. * (HOME.0 as f64 / 1e7).to_radians().cos();
+            let radius = (north * north + east * east).sqrt();
+            assert!((radius - CIRCLE_RADIUS).abs() < 0.1, "radius {}", radius);
+            assert!((0. ..360.).contains(&course));
+        }
+        let (lat, lon, _) = track(period);
+        let (start_lat, start_lon, _) = track(0.);
+        assert!((lat - start_lat).abs() <= 1 && (lon - start_lon).abs() <= 1);
+    }
+
+    #[test]
+    fn battery_drains_and_restarts() {
+        let (full, drawn, remaining) = battery(0.);
+        assert_eq!((full, drawn, remaining), (16800, 0, 100));
+
+        let (half, _, remaining) = battery(BATTERY_DRAIN / 2.);
+        assert_eq!((half, remaining), (15000, 50));
+
+        let (empty, drawn, remaining) = battery(BATTERY_DRAIN - 0.001);
+        assert!(empty < half && remaining == 0);
+        assert_eq!(drawn, 1667);
+
+        assert_eq!(battery(BATTERY_DRAIN), battery(0.));
+    }
+}
@@ -1,49 +1,1220 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use mavlink::common::MavMessage::*;
 use mavlink::common::*;
+use mavlink::MavHeader;
 
 use crate::msp::*;
 use crate::Config;
 
+/// a fetched payload and when it was fetched
+type CachedPayload = (Instant, Box<dyn Any + Send>);
+
+/// Payloads fetched recently, shared by all generators to save redundant MSP round-trips
+#[derive(Clone, Default)]
+pub struct FetchCache(Arc<Mutex<HashMap<u16, CachedPayload>>>);
+
+impl Debug for FetchCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.0.lock().map(|c| c.len()).unwrap_or_default();
+        write!(f, "FetchCache({} payloads)", len)
+    }
+}
+
+impl FetchCache {
+    /// yields the payload of function `P::ID` if it was fetched less than `ttl` ago
+    fn get<P: MspPayload + Clone + 'static>(&self, ttl: Duration) -> Option<P> {
+        let mut cache = self.0.lock().ok()?;
+        match cache.get(&P::ID) {
+            Some((fetched, payload)) if fetched.elapsed() < ttl => payload.downcast_ref().cloned(),
+            Some(_) => {
+                cache.remove(&P::ID);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// remembers a freshly fetched payload
+    fn put<P: MspPayload + Clone + Send + 'static>(&self, payload: &P) {
+        if let Ok(mut cache) = self.0.lock() {
+            cache.insert(P::ID, (Instant::now(), Box::new(payload.clone())));
+        }
+    }
+}
+
+/// Time of the last successful MSP fetch, to notice a FC which stopped answering
+#[derive(Clone, Debug)]
+pub struct Watchdog(Arc<Mutex<Instant>>);
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl Watchdog {
+    /// notes a successful fetch
+    fn feed(&self) {
+        if let Ok(mut fed) = self.0.lock() {
+            *fed = Instant::now();
+        }
+    }
+
+    /// checks whether the last successful fetch is older than `--telemetry-timeout-ms`, a
+    /// simulated FC never goes stale
+    pub fn is_stale(&self, conf: &Config) -> bool {
+        if conf.simulate {
+            return false;
+        }
+        let timeout = Duration::from_millis(conf.telemetry_timeout_ms);
+        self.0.lock().map_or(false, |fed| fed.elapsed() > timeout)
+    }
+}
+
+/// Origin of the local frame, fixed by the first 3D GPS fix unless the GCS sets it
+#[derive(Clone, Debug, Default)]
+pub struct GpsOrigin(Arc<Mutex<Option<GPS_GLOBAL_ORIGIN_DATA>>>);
+
+impl GpsOrigin {
+    /// the origin, if it is fixed already
+    pub fn get(&self) -> Option<GPS_GLOBAL_ORIGIN_DATA> {
+        self.0.lock().ok()?.clone()
+    }
+
+    /// fixes the origin at `origin` unless it is fixed already, yields the origin in effect
+    pub fn get_or_set(&self, origin: GPS_GLOBAL_ORIGIN_DATA) -> GPS_GLOBAL_ORIGIN_DATA {
+        match self.0.lock() {
+            Ok(mut fixed) => fixed.get_or_insert(origin).clone(),
+            Err(_) => origin,
+        }
+    }
+
+    /// moves the origin, as the GCS does with `SET_GPS_GLOBAL_ORIGIN`
+    pub fn set(&self, origin: GPS_GLOBAL_ORIGIN_DATA) {
+        if let Ok(mut fixed) = self.0.lock() {
+            *fixed = Some(origin);
+        }
+    }
+}
+
+/// time after the last `GPS_INPUT` at which the GPS of the FC is polled again
+const GPS_INJECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Fix injected by `GPS_INPUT`, which stands in for the GPS of the FC while it is fresh
+#[derive(Clone, Debug, Default)]
+pub struct GpsInjection(Arc<Mutex<Option<(Instant, MspRawGps)>>>);
+
+impl GpsInjection {
+    /// the injected fix, unless the last injection timed out
+    fn active(&self) -> Option<MspRawGps> {
+        match self.0.lock().ok()?.as_ref() {
+            Some((injected, gps)) if injected.elapsed() < GPS_INJECTION_TIMEOUT => Some(*gps),
+            _ => None,
+        }
+    }
+
+    /// notes a freshly injected fix
+    fn inject(&self, gps: MspRawGps) {
+        if let Ok(mut injection) = self.0.lock() {
+            *injection = Some((Instant::now(), gps));
+        }
+    }
+}
+
+/// State learned from the FC and the GCS while the bridge runs, shared by all generators
+///
+/// `Config` only holds the parsed arguments.
+#[derive(Clone, Debug, Default)]
+pub struct BridgeState {
+    /// firmware variant reported by the FC, e.g. `INAV` or `BTFL`
+    pub fc_variant: Option<String>,
+    /// craft name configured on the FC, if it has one
+    pub craft_name: Option<String>,
+    /// MSP API version `(major, minor)` reported by the FC
+    pub api_version: Option<(u8, u8)>,
+    /// MSP payloads fetched recently
+    pub fetch_cache: FetchCache,
+    /// time of the last successful MSP fetch
+    pub watchdog: Watchdog,
+    /// origin of the local frame
+    pub gps_origin: GpsOrigin,
+    /// fix injected by the GCS
+    pub gps_injection: GpsInjection,
+}
+
+/// fetches a payload, retrying as often as configured
+///
+/// A payload fetched less than `--fetch-ttl-ms` ago is reused rather than fetched again. While a
+/// fix is injected by `GPS_INPUT`, `MspRawGps` is not polled but the injected fix is used.
+fn fetch<P, T>(conf: &Config, state: &BridgeState, conn: &mut T) -> io::Result<P>
+where
+    P: MspPayload + Clone + Debug + Send + 'static,
+    T: MspConnection,
+{
+    if let Some(gps) = state.gps_injection.active() {
+        if let Some(payload) = (&gps as &dyn Any).downcast_ref::<P>() {
+            return Ok(payload.clone());
+        }
+    }
+    let ttl = Duration::from_millis(conf.fetch_ttl_ms);
+    if let Some(payload) = state.fetch_cache.get(ttl) {
+        return Ok(payload);
+    }
+    // no retry is started once every attempt could have timed out
+    let timeout = Duration::from_millis(conf.msp_timeout_ms.get()) * (conf.msp_retries as u32 + 1);
+    let payload = MspMessage::fetch_with(conn, conf.msp_retries, timeout)?;
+    state.watchdog.feed();
+    state.fetch_cache.put(&payload);
+    Ok(payload)
+}
+
+/// bit of `MspStatus.flag` indicating an armed FC, the ARM box always comes first
+pub const MSP_STATUS_ARMED: u32 = 1 << 0;
+
+/// position of the box `name` in the `;` separated `MspBoxNames`, which is also its bit in
+/// `MspStatus.flag`
+pub fn box_index(names: &str, name: &str) -> Option<usize> {
+    names.split(';').position(|n| n == name)
+}
+
+/// arms or disarms by moving the arm switch channel via `MspSetRawRc`
+///
+/// The remaining channels keep their current values. Arming is denied while the FC is in
+/// failsafe.
+pub fn arm_disarm(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    arm: bool,
+) -> io::Result<MavResult> {
+    if arm {
+        let status: MspStatus = fetch(conf, state, &mut mspconn)?;
+        let boxes: MspBoxNames = fetch(conf, state, &mut mspconn)?;
+        if let Some(i) = box_index(&boxes.names, "FAILSAFE") {
+            if i < 32 && status.flag & (1 << i) != 0 {
+                warn!("refusing to arm during failsafe");
+                return Ok(MavResult::MAV_RESULT_DENIED);
+            }
+        }
+    }
+
+    let rc: MspRc = fetch(conf, state, &mut mspconn)?;
+    let mut channels = [0u16; 16];
+    channels.copy_from_slice(rc.as_slice());
+    match conf
+        .arm_channel
+        .checked_sub(1)
+        .and_then(|i| channels.get_mut(i))
+    {
+        Some(channel) if arm => *channel = conf.arm_high_us,
+        Some(channel) => *channel = conf.arm_low_us,
+        None => {
+            warn!("invalid arm channel {}", conf.arm_channel);
+            return Ok(MavResult::MAV_RESULT_FAILED);
+        }
+    }
+
+    set_raw_rc(mspconn, channels)?;
+    Ok(MavResult::MAV_RESULT_ACCEPTED)
+}
+
+/// RC channels, counting from 1, driven by the x, y, z and r axes of `MANUAL_CONTROL`
+///
+/// Parsed from a comma separated list like `2,1,3,4`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisChannels(pub [usize; 4]);
+
+impl FromStr for AxisChannels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let channels: Vec<usize> = s
+            .split(',')
+            .map(|c| {
+                c.trim()
+                    .parse()
+                    .map_err(|_| format!("invalid channel {}", c))
+            })
+            .collect::<Result<_, _>>()?;
+        match channels[..] {
+            [x, y, z, r] => Ok(AxisChannels([x, y, z, r])),
+            _ => Err(format!("expected 4 channels, got {}", channels.len())),
+        }
+    }
+}
+
+/// RC channel values in us, starting at channel 1
+///
+/// Parsed from a comma separated list like `1500,1500,1000,1500`, at most 16 channels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RcFrame(pub Vec<u16>);
+
+impl RcFrame {
+    /// sets the first channels of `channels` to this frame, the others keep their value
+    pub fn apply(&self, channels: &mut [u16; 16]) {
+        channels[..self.0.len()].copy_from_slice(&self.0);
+    }
+}
+
+impl FromStr for RcFrame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<u16> = s
+            .split(',')
+            .map(|v| {
+                v.trim()
+                    .parse()
+                    .map_err(|_| format!("invalid channel value {}", v))
+            })
+            .collect::<Result<_, _>>()?;
+        match values.len() {
+            1..=16 => Ok(RcFrame(values)),
+            len => Err(format!("expected 1 to 16 channels, got {}", len)),
+        }
+    }
+}
+
+/// airframes accepted by `--mav-type`
+const MAV_TYPES: [(&str, MavType); 16] = [
+    ("generic", MavType::MAV_TYPE_GENERIC),
+    ("fixed-wing", MavType::MAV_TYPE_FIXED_WING),
+    ("quadrotor", MavType::MAV_TYPE_QUADROTOR),
+    ("coaxial", MavType::MAV_TYPE_COAXIAL),
+    ("helicopter", MavType::MAV_TYPE_HELICOPTER),
+    ("airship", MavType::MAV_TYPE_AIRSHIP),
+    ("ground-rover", MavType::MAV_TYPE_GROUND_ROVER),
+    ("surface-boat", MavType::MAV_TYPE_SURFACE_BOAT),
+    ("submarine", MavType::MAV_TYPE_SUBMARINE),
+    ("hexarotor", MavType::MAV_TYPE_HEXAROTOR),
+    ("octorotor", MavType::MAV_TYPE_OCTOROTOR),
+    ("tricopter", MavType::MAV_TYPE_TRICOPTER),
+    ("flapping-wing", MavType::MAV_TYPE_FLAPPING_WING),
+    ("vtol-duorotor", MavType::MAV_TYPE_VTOL_DUOROTOR),
+    ("vtol-quadrotor", MavType::MAV_TYPE_VTOL_QUADROTOR),
+    ("vtol-tiltrotor", MavType::MAV_TYPE_VTOL_TILTROTOR),
+];
+
+/// autopilots accepted by `--autopilot`
+const AUTOPILOTS: [(&str, MavAutopilot); 4] = [
+    ("generic", MavAutopilot::MAV_AUTOPILOT_GENERIC),
+    ("ardupilotmega", MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA),
+    ("px4", MavAutopilot::MAV_AUTOPILOT_PX4),
+    ("invalid", MavAutopilot::MAV_AUTOPILOT_INVALID),
+];
+
+/// looks up `s` in `table`, ignoring case and whether words are separated by `-` or `_`
+fn parse_name<T: Copy>(table: &[(&str, T)], what: &str, s: &str) -> Result<T, String> {
+    let name = s.to_lowercase().replace('_', "-");
+    table
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| {
+            let names: Vec<&str> = table.iter().map(|(n, _)| *n).collect();
+            format!(
+                "unknown {} {}, expected one of {}",
+                what,
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+/// Airframe reported in the `HEARTBEAT`, parsed from names like `quadrotor` or `fixed-wing`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VehicleType(pub MavType);
+
+impl FromStr for VehicleType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_name(&MAV_TYPES, "MAV type", s).map(VehicleType)
+    }
+}
+
+/// Autopilot reported in the `HEARTBEAT`, parsed from names like `generic` or `px4`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Autopilot(pub MavAutopilot);
+
+impl FromStr for Autopilot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_name(&AUTOPILOTS, "autopilot", s).map(Autopilot)
+    }
+}
+
+/// fetches the RC channels the FC currently receives
+pub fn fetch_rc(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+) -> io::Result<[u16; 16]> {
+    let rc: MspRc = fetch(conf, state, &mut mspconn)?;
+    let mut channels = [0u16; 16];
+    channels.copy_from_slice(rc.as_slice());
+    Ok(channels)
+}
+
+/// overrides the RC channels of the FC via `MspSetRawRc`
+pub fn set_raw_rc(mut mspconn: &mut dyn MspConnection, channels: [u16; 16]) -> io::Result<()> {
+    MspMessage::send_command(&MspSetRawRc::from(channels), &mut mspconn)?;
+    Ok(())
+}
+
+/// converts a stick axis in [-1000, 1000] to a RC channel value in us
+fn axis_to_rc(value: i16) -> u16 {
+    (1500 + value as i32 / 2).clamp(1000, 2000) as u16
+}
+
+/// converts thrust in [0, 1000] to a RC channel value in us
+fn thrust_to_rc(value: i16) -> u16 {
+    (1000 + value as i32).clamp(1000, 2000) as u16
+}
+
+/// patches the axes and buttons of `MANUAL_CONTROL` into the RC channels
+///
+/// Each button drives one channel from `--manual-control-buttons` on, 2000 us while pressed and
+/// 1000 us while released. Channels out of range are ignored.
+pub fn manual_control(conf: &Config, msg: &MANUAL_CONTROL_DATA, channels: &mut [u16; 16]) {
+    let AxisChannels([x, y, z, r]) = conf.manual_control_axes;
+    let axes = [
+        (x, axis_to_rc(msg.x)),
+        (y, axis_to_rc(msg.y)),
+        (z, thrust_to_rc(msg.z)),
+        (r, axis_to_rc(msg.r)),
+    ];
+    let buttons = conf.manual_control_buttons.into_iter().flat_map(|first| {
+        (0..16).map(move |i| match msg.buttons & (1 << i) {
+            0 => (first + i, 1000),
+            _ => (first + i, 2000),
+        })
+    });
+    for (channel, value) in axes.iter().copied().chain(buttons) {
+        if let Some(c) = channel.checked_sub(1).and_then(|i| channels.get_mut(i)) {
+            *c = value;
+        }
+    }
+}
+
+/// `SET_ATTITUDE_TARGET` type_mask bit to ignore the body yaw rate
+const ATTITUDE_TARGET_IGNORE_YAW_RATE: u8 = 4;
+
+/// `SET_ATTITUDE_TARGET` type_mask bit to ignore the thrust
+const ATTITUDE_TARGET_IGNORE_THRUST: u8 = 64;
+
+/// `SET_ATTITUDE_TARGET` type_mask bit to ignore the attitude quaternion
+const ATTITUDE_TARGET_IGNORE_ATTITUDE: u8 = 128;
+
+/// roll and pitch in radians of the attitude quaternion `q`, given as w, x, y, z
+fn quaternion_roll_pitch(q: [f32; 4]) -> (f32, f32) {
+    let [w, x, y, z] = q;
+    let roll = (2. * (w * x + y * z)).atan2(1. - 2. * (x * x + y * y));
+    let pitch = (2. * (w * y - z * x)).clamp(-1., 1.).asin();
+    (roll, pitch)
+}
+
+/// converts `value` to a RC channel value in us, with full deflection at `max`
+fn scaled_to_rc(value: f32, max: f32) -> u16 {
+    axis_to_rc((value / max * 1000.).clamp(-1000., 1000.) as i16)
+}
+
+/// patches the attitude, yaw rate and thrust of `SET_ATTITUDE_TARGET` into the RC channels
+///
+/// Roll and pitch become stick deflections relative to `--attitude-target-max-angle`, which
+/// matches the angle limit of the FC in angle mode. The yaw rate is relative to
+/// `--attitude-target-max-yaw-rate`, thrust in [0, 1] maps to the throttle range. The channels
+/// are those of `--manual-control-axes`, fields ignored by the type_mask keep their channel. The
+/// body roll and pitch rates are not supported, as the FC can not be commanded rates via RC.
+pub fn attitude_target(conf: &Config, msg: &SET_ATTITUDE_TARGET_DATA, channels: &mut [u16; 16]) {
+    let AxisChannels([x, y, z, r]) = conf.manual_control_axes;
+    let mut axes = Vec::with_capacity(4);
+    if msg.type_mask & ATTITUDE_TARGET_IGNORE_ATTITUDE == 0 {
+        let (roll, pitch) = quaternion_roll_pitch(msg.q);
+        let max = conf.attitude_target_max_angle.to_radians();
+        // pushing the stick forward pitches the nose down
+        axes.push((x, scaled_to_rc(-pitch, max)));
+        axes.push((y, scaled_to_rc(roll, max)));
+    }
+    if msg.type_mask & ATTITUDE_TARGET_IGNORE_THRUST == 0 {
+        axes.push((z, thrust_to_rc((msg.thrust.clamp(0., 1.) * 1000.) as i16)));
+    }
+    if msg.type_mask & ATTITUDE_TARGET_IGNORE_YAW_RATE == 0 {
+        let max = conf.attitude_target_max_yaw_rate.to_radians();
+        axes.push((r, scaled_to_rc(msg.body_yaw_rate, max)));
+    }
+    for (channel, value) in axes {
+        if let Some(c) = channel.checked_sub(1).and_then(|i| channels.get_mut(i)) {
+            *c = value;
+        }
+    }
+}
+
+/// `ATTITUDE_TARGET` confirming a `SET_ATTITUDE_TARGET` the FC is commanded
+pub fn attitude_target_feedback(msg: &SET_ATTITUDE_TARGET_DATA) -> MavMessage {
+    ATTITUDE_TARGET(ATTITUDE_TARGET_DATA {
+        time_boot_ms: msg.time_boot_ms,
+        q: msg.q,
+        body_roll_rate: msg.body_roll_rate,
+        body_pitch_rate: msg.body_pitch_rate,
+        body_yaw_rate: msg.body_yaw_rate,
+        thrust: msg.thrust,
+        type_mask: msg.type_mask,
+    })
+}
+
+/// `RC_CHANNELS_OVERRIDE` value to leave a channel as it is
+const RC_OVERRIDE_IGNORE: u16 = u16::MAX;
+
+/// `RC_CHANNELS_OVERRIDE` value to hand a channel back to the FC
+const RC_OVERRIDE_RELEASE: u16 = 0;
+
+/// the eight channels of `RC_CHANNELS_OVERRIDE`
+fn override_values(msg: &RC_CHANNELS_OVERRIDE_DATA) -> [u16; 8] {
+    [
+        msg.chan1_raw,
+        msg.chan2_raw,
+        msg.chan3_raw,
+        msg.chan4_raw,
+        msg.chan5_raw,
+        msg.chan6_raw,
+        msg.chan7_raw,
+        msg.chan8_raw,
+    ]
+}
+
+/// checks whether `RC_CHANNELS_OVERRIDE` hands all channels back to the FC
+pub fn releases_all_channels(msg: &RC_CHANNELS_OVERRIDE_DATA) -> bool {
+    override_values(msg)
+        .iter()
+        .all(|v| *v == RC_OVERRIDE_RELEASE)
+}
+
+/// whether `RC_CHANNELS_OVERRIDE` hands at least one channel back to the FC
+pub fn releases_any_channel(msg: &RC_CHANNELS_OVERRIDE_DATA) -> bool {
+    override_values(msg).contains(&RC_OVERRIDE_RELEASE)
+}
+
+/// patches the channels of `RC_CHANNELS_OVERRIDE` into the RC channels
+///
+/// Ignored channels keep their value, released channels get the value the FC reports itself in
+/// `fc`.
+pub fn rc_channels_override(
+    msg: &RC_CHANNELS_OVERRIDE_DATA,
+    fc: &[u16; 16],
+    channels: &mut [u16; 16],
+) {
+    for (i, value) in override_values(msg).iter().enumerate() {
+        match *value {
+            RC_OVERRIDE_IGNORE => {}
+            RC_OVERRIDE_RELEASE => channels[i] = fc[i],
+            v => channels[i] = v,
+        }
+    }
+}
+
+/// derives arm state and flight mode from the active boxes in `MspStatus.flag`
+///
+/// The active box bits are passed as `custom_mode`, so a GCS can display the flight mode. Once
+/// no fetch succeeded for `--telemetry-timeout-ms` the system status turns critical.
 pub fn heartbeat(
-    _conf: &Config,
-    _mspconn: &mut dyn MspConnection,
-    _context: Option<&MavMessage>,
-) -> io::Result<MavMessage> {
-    Ok(HEARTBEAT(HEARTBEAT_DATA {
-        custom_mode: 0,
-        mavtype: MavType::MAV_TYPE_GENERIC,
-        autopilot: MavAutopilot::MAV_AUTOPILOT_GENERIC_WAYPOINTS_AND_SIMPLE_NAVIGATION_ONLY,
-        base_mode: MavModeFlag::empty(),
-        system_status: MavState::MAV_STATE_STANDBY,
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let stale = state.watchdog.is_stale(conf);
+    let status: MspStatus = match fetch(conf, state, &mut mspconn) {
+        Ok(status) => status,
+        // keeps reporting the last known state, marked as critical
+        Err(e) if stale => {
+            debug!("telemetry is stale: {}", e);
+            let mut heartbeat = match context {
+                Some(HEARTBEAT(previous)) => previous.clone(),
+                _ => HEARTBEAT_DATA::default(),
+            };
+            heartbeat.system_status = MavState::MAV_STATE_CRITICAL;
+            return Ok(vec![HEARTBEAT(heartbeat)]);
+        }
+        Err(e) => return Err(e),
+    };
+    let armed = status.flag & MSP_STATUS_ARMED != 0;
+
+    let mut base_mode = MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED;
+    if armed {
+        base_mode |= MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED;
+    }
+    Ok(vec![HEARTBEAT(HEARTBEAT_DATA {
+        custom_mode: status.flag,
+        mavtype: conf.mav_type.0,
+        autopilot: match (conf.autopilot, state.fc_variant.as_deref()) {
+            (Some(Autopilot(autopilot)), _) => autopilot,
+            (None, Some("INAV")) => MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+            (None, _) => MavAutopilot::MAV_AUTOPILOT_GENERIC,
+        },
+        base_mode,
+        system_status: match (stale, armed) {
+            (true, _) => MavState::MAV_STATE_CRITICAL,
+            (false, true) => MavState::MAV_STATE_ACTIVE,
+            (false, false) => MavState::MAV_STATE_STANDBY,
+        },
         mavlink_version: 0x3,
-    }))
+    })])
 }
 
-pub fn param_value(
-    _conf: &Config,
-    _mspconn: &mut dyn MspConnection,
-    _context: Option<&MavMessage>,
+/// names of the PID axes, in the order in which `MspPid` transmits them
+pub const PID_AXES: [&str; 10] = [
+    "ROLL", "PITCH", "YAW", "ALT", "POS", "POSR", "NAVR", "LEVEL", "MAG", "VEL",
+];
+
+/// names of the three gains of each PID axis
+pub const PID_TERMS: [&str; 3] = ["P", "I", "D"];
+
+/// count of the PID gains exposed as parameters, they come first
+pub const PID_PARAM_COUNT: usize = PID_AXES.len() * PID_TERMS.len();
+
+/// names of the rate parameters with the range INAV accepts, in the order in which
+/// `MspRcTuning` transmits them
+///
+/// INAV ignores the rc rate, it is kept as MSP still carries it.
+pub const RC_TUNING_PARAMS: [(&str, u8, u8); 7] = [
+    ("RC_RATE", 0, 255),
+    ("RC_EXPO", 0, 100),
+    ("RC_RP_RATE", 6, 180),
+    ("RC_YAW_RATE", 2, 180),
+    ("TPA_RATE", 0, 100),
+    ("THR_MID", 0, 100),
+    ("THR_EXPO", 0, 100),
+];
+
+/// total count of parameters exposed to the GCS
+pub const PARAM_COUNT: usize = PID_PARAM_COUNT + RC_TUNING_PARAMS.len();
+
+/// converts a parameter name to the null padded representation used by MAVLink
+pub fn to_param_id(name: &str) -> [char; 16] {
+    let mut param_id = ['\0'; 16];
+    for (c, n) in param_id.iter_mut().zip(name.chars()) {
+        *c = n;
+    }
+    param_id
+}
+
+/// converts a null padded MAVLink parameter id back to its name
+pub fn from_param_id(param_id: &[char; 16]) -> String {
+    param_id.iter().take_while(|c| **c != '\0').collect()
+}
+
+/// name of the parameter at `index`, e.g. `ROLL_P` for index 0
+fn param_name(index: usize) -> String {
+    match index.checked_sub(PID_PARAM_COUNT) {
+        Some(rate) => RC_TUNING_PARAMS[rate].0.to_string(),
+        None => {
+            let (axis, term) = (index / PID_TERMS.len(), index % PID_TERMS.len());
+            format!("{}_{}", PID_AXES[axis], PID_TERMS[term])
+        }
+    }
+}
+
+/// looks up the index of a parameter by its name
+pub fn param_index(name: &str) -> Option<usize> {
+    (0..PARAM_COUNT).find(|index| name == param_name(*index))
+}
+
+/// builds a `STATUSTEXT` with the text padded to the 50 chars MAVLink expects
+pub fn statustext(severity: MavSeverity, text: &str) -> MavMessage {
+    let mut text: Vec<char> = text.chars().take(50).collect();
+    text.resize(50, '\0');
+    STATUSTEXT(STATUSTEXT_DATA { severity, text })
+}
+
+/// splits `text` into as many `STATUSTEXT` as needed to carry it completely
+///
+/// This MAVLink version has no chunk id, so the GCS shows the parts as consecutive lines.
+pub fn statustexts(severity: MavSeverity, text: &str) -> Vec<MavMessage> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![statustext(severity, "")];
+    }
+    chars
+        .chunks(50)
+        .map(|chunk| statustext(severity, &chunk.iter().collect::<String>()))
+        .collect()
+}
+
+/// builds a `COMMAND_ACK` for `command`
+pub fn command_ack(command: MavCmd, result: MavResult) -> MavMessage {
+    COMMAND_ACK(COMMAND_ACK_DATA { command, result })
+}
+
+/// reboots the FC on `MAV_CMD_PREFLIGHT_REBOOT_SHUTDOWN`, if param1 asks for it
+///
+/// The reboot is denied while armed, shutting down is not supported.
+pub fn reboot(
+    mut mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> io::Result<MavResult> {
+    if msg.param1 != 1. {
+        return Ok(MavResult::MAV_RESULT_UNSUPPORTED);
+    }
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    if status.flag & MSP_STATUS_ARMED != 0 {
+        warn!("refusing to reboot while armed");
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    MspMessage::send_command(&MspReboot {}, &mut mspconn)?;
+    Ok(MavResult::MAV_RESULT_ACCEPTED)
+}
+
+/// target heading in degrees of `MAV_CMD_CONDITION_YAW`, wrapped into 0 to 359
+///
+/// param1 holds the angle, which param4 marks as relative to `current` and param3 as
+/// counter-clockwise if negative.
+fn condition_yaw_heading(msg: &COMMAND_LONG_DATA, current: i16) -> i16 {
+    let target = match (msg.param4 != 0., msg.param3 < 0.) {
+        (true, true) => current as f32 - msg.param1,
+        (true, false) => current as f32 + msg.param1,
+        (false, _) => msg.param1,
+    };
+    (target.round() as i32).rem_euclid(360) as i16
+}
+
+/// holds the heading commanded by `MAV_CMD_CONDITION_YAW` via `MspSetHead`
+pub fn condition_yaw(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> io::Result<MavResult> {
+    let attitude: MspAttitude = fetch(conf, state, &mut mspconn)?;
+    let mag_hold = condition_yaw_heading(msg, attitude.heading);
+    MspMessage::send_command(&MspSetHead { mag_hold }, &mut mspconn)?;
+    Ok(MavResult::MAV_RESULT_ACCEPTED)
+}
+
+/// motor output of a stopped motor, in microseconds
+pub const MOTOR_MIN_US: u16 = 1000;
+
+/// longest motor test, longer timeouts of `MAV_CMD_DO_MOTOR_TEST` are cut short
+pub const MOTOR_TEST_MAX_DURATION: Duration = Duration::from_secs(10);
+
+/// motor output requested by `MAV_CMD_DO_MOTOR_TEST`, in microseconds
+///
+/// Only throttle in percent or as PWM is supported.
+pub fn motor_test_output(msg: &COMMAND_LONG_DATA) -> Option<u16> {
+    match msg.param2 as u8 {
+        t if t == MotorTestThrottleType::MOTOR_TEST_THROTTLE_PERCENT as u8 => {
+            Some(MOTOR_MIN_US + (msg.param3.clamp(0., 100.) * 10.).round() as u16)
+        }
+        t if t == MotorTestThrottleType::MOTOR_TEST_THROTTLE_PWM as u8 => {
+            Some(msg.param3.round() as u16)
+        }
+        _ => None,
+    }
+}
+
+/// duration of the motor test requested by `MAV_CMD_DO_MOTOR_TEST`
+///
+/// Clamped before the conversion, which panics for infinite or huge timeouts, a NaN one is 0.
+pub fn motor_test_duration(msg: &COMMAND_LONG_DATA) -> Duration {
+    let max = MOTOR_TEST_MAX_DURATION.as_secs_f32();
+    Duration::from_secs_f32(msg.param4.max(0.).min(max))
+}
+
+/// spins motor param1 (counting from 1) as requested by `MAV_CMD_DO_MOTOR_TEST` via
+/// `MspSetMotor`, all other motors stay at their minimum
+///
+/// The test is denied while armed or above `--motor-test-max-percent` throttle. The caller has
+/// to stop the motor after `motor_test_duration`.
+pub fn motor_test(
+    conf: &Config,
+    mut mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> io::Result<MavResult> {
+    let output = match motor_test_output(msg) {
+        Some(output) => output,
+        None => return Ok(MavResult::MAV_RESULT_UNSUPPORTED),
+    };
+    let limit = MOTOR_MIN_US + (conf.motor_test_max_percent * 10.).round() as u16;
+    let motor = msg.param1.round() as usize;
+    if output > limit || motor == 0 || motor > 16 {
+        warn!("refusing to run motor {} at {} us", motor, output);
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    if status.flag & MSP_STATUS_ARMED != 0 {
+        warn!("refusing to test motors while armed");
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    let mut motors = [MOTOR_MIN_US; 16];
+    motors[motor - 1] = output.max(MOTOR_MIN_US);
+    MspMessage::send_command(&MspSetMotor::from(motors), &mut mspconn)?;
+    Ok(MavResult::MAV_RESULT_ACCEPTED)
+}
+
+/// stops all motors after a motor test
+pub fn stop_motors(mut mspconn: &mut dyn MspConnection) -> io::Result<()> {
+    MspMessage::send_command(&MspSetMotor::from([MOTOR_MIN_US; 16]), &mut mspconn)?;
+    Ok(())
+}
+
+/// lowest servo output `MAV_CMD_DO_SET_SERVO` may set, in microseconds
+pub const SERVO_MIN_US: u16 = 750;
+
+/// highest servo output `MAV_CMD_DO_SET_SERVO` may set, in microseconds
+pub const SERVO_MAX_US: u16 = 2250;
+
+/// `MspSetServoConf.forward_from_channel` of a servo which follows no RC channel
+const SERVO_FORWARDING_DISABLED: u8 = u8::MAX;
+
+/// moves servo param1 (counting from 1) to the PWM param2 as requested by
+/// `MAV_CMD_DO_SET_SERVO`
+///
+/// MSP has no servo override, so the servo is pinned to the output by setting its minimum,
+/// maximum and middle to it via `MspSetServoConf`. This overwrites the configured range of the
+/// servo until the FC reboots. An EEPROM write would save the pinned range for good, so the
+/// command is denied with `--persist-writes`, as well as while armed or outside of
+/// [`SERVO_MIN_US`, `SERVO_MAX_US`]. It fails unless `MspServo` reads back the output.
+pub fn set_servo(
+    conf: &Config,
+    mut mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> io::Result<MavResult> {
+    if conf.persist_writes {
+        warn!("refusing to pin servos, --persist-writes would save their range");
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    let servo = msg.param1.round() as usize;
+    let output = msg.param2.round();
+    if servo == 0 || servo > 16 || !(SERVO_MIN_US as f32..=SERVO_MAX_US as f32).contains(&output) {
+        warn!("refusing to set servo {} to {} us", servo, output);
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    let output = output as u16;
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    if status.flag & MSP_STATUS_ARMED != 0 {
+        warn!("refusing to set servos while armed");
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    let servo_conf = MspSetServoConf {
+        index: (servo - 1) as u8,
+        min: output,
+        max: output,
+        middle: output,
+        rate: 100,
+        forward_from_channel: SERVO_FORWARDING_DISABLED,
+        reversed_sources: 0,
+    };
+    MspMessage::send_command(&servo_conf, &mut mspconn)?;
+
+    // bypasses the fetch cache, which may still hold the output before
+    let servos: MspServo = MspMessage::fetch(&mut mspconn)?;
+    match servos.as_slice()[servo - 1] {
+        o if o == output => Ok(MavResult::MAV_RESULT_ACCEPTED),
+        o => {
+            warn!("servo {} is at {} us instead of {} us", servo, o, output);
+            Ok(MavResult::MAV_RESULT_FAILED)
+        }
+    }
+}
+
+/// duration of the mag calibration of the FC, in which the drone has to be rotated around all
+/// axes
+pub const MAG_CALIBRATION_TIME: Duration = Duration::from_secs(30);
+
+/// starts the calibrations requested by `MAV_CMD_PREFLIGHT_CALIBRATION`
+///
+/// A non-zero param1 calibrates gyro and accelerometer, a non-zero param2 the magnetometer. The
+/// calibration is denied while armed.
+pub fn preflight_calibration(
+    mut mspconn: &mut dyn MspConnection,
+    msg: &COMMAND_LONG_DATA,
+) -> io::Result<MavResult> {
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    if status.flag & MSP_STATUS_ARMED != 0 {
+        warn!("refusing to calibrate while armed");
+        return Ok(MavResult::MAV_RESULT_DENIED);
+    }
+    if msg.param1 != 0. {
+        MspMessage::send_command(&MspAccCalibration {}, &mut mspconn)?;
+    }
+    if msg.param2 != 0. {
+        MspMessage::send_command(&MspMagCalibration {}, &mut mspconn)?;
+    }
+    Ok(MavResult::MAV_RESULT_ACCEPTED)
+}
+
+/// the gains of all PID axes, ordered like `PID_AXES`
+pub fn pid_gains(payload: &MspPid) -> [[u8; 3]; 10] {
+    [
+        payload.roll,
+        payload.pitch,
+        payload.yaw,
+        payload.alt,
+        payload.pos,
+        payload.posr,
+        payload.navr,
+        payload.level,
+        payload.mag,
+        payload.vel,
+    ]
+}
+
+/// builds the `PARAM_VALUE` for the parameter at `index`, e.g. `ROLL_P` for index 0
+pub fn pid_param(payload: &MspPid, index: usize) -> MavMessage {
+    let (axis, term) = (index / PID_TERMS.len(), index % PID_TERMS.len());
+    PARAM_VALUE(PARAM_VALUE_DATA {
+        param_value: pid_gains(payload)[axis][term] as f32,
+        param_count: PARAM_COUNT as u16,
+        param_index: index as u16,
+        param_id: to_param_id(&param_name(index)),
+        param_type: MavParamType::MAV_PARAM_TYPE_UINT8,
+    })
+}
+
+/// writes the parameter at `index` to the FC and yields the resulting `PARAM_VALUE`
+///
+/// The whole PID block is read first so only the requested gain is altered. `value` is clamped
+/// to the `u8` range MSP uses.
+pub fn set_pid_param(
+    mut mspconn: &mut dyn MspConnection,
+    index: usize,
+    value: f32,
 ) -> io::Result<MavMessage> {
-    Ok(PARAM_VALUE(PARAM_VALUE_DATA {
-        param_value: 0.,
-        param_count: 0,
-        param_index: 0,
-        param_id: [
-            ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ', ' ',
-        ],
+    let (axis, term) = (index / PID_TERMS.len(), index % PID_TERMS.len());
+    let payload: MspPid = MspMessage::fetch(&mut mspconn)?;
+    let mut gains = pid_gains(&payload);
+    gains[axis][term] = value.max(u8::MIN as f32).min(u8::MAX as f32).round() as u8;
+
+    let gains = MspSetPid {
+        roll: gains[0],
+        pitch: gains[1],
+        yaw: gains[2],
+        alt: gains[3],
+        pos: gains[4],
+        posr: gains[5],
+        navr: gains[6],
+        level: gains[7],
+        mag: gains[8],
+        vel: gains[9],
+    };
+    MspMessage::send_command(&gains, &mut mspconn)?;
+
+    let payload: MspPid = MspMessage::fetch(&mut mspconn)?;
+    Ok(pid_param(&payload, index))
+}
+
+/// the rates, ordered like `RC_TUNING_PARAMS`
+fn rc_tuning_values(payload: &MspRcTuning) -> [u8; 7] {
+    [
+        payload.rc_rate,
+        payload.rc_expo,
+        payload.roll_pitch_rate,
+        payload.yaw_rate,
+        payload.dyn_thr_pid,
+        payload.throttle_mid,
+        payload.throttle_expo,
+    ]
+}
+
+/// builds the `PARAM_VALUE` for the rate parameter at `index`, counting after the PID gains
+pub fn rc_tuning_param(payload: &MspRcTuning, index: usize) -> MavMessage {
+    PARAM_VALUE(PARAM_VALUE_DATA {
+        param_value: rc_tuning_values(payload)[index - PID_PARAM_COUNT] as f32,
+        param_count: PARAM_COUNT as u16,
+        param_index: index as u16,
+        param_id: to_param_id(&param_name(index)),
         param_type: MavParamType::MAV_PARAM_TYPE_UINT8,
-    }))
+    })
+}
+
+/// the rates of `payload` with the one at `index` replaced by `value`, clamped to its range
+fn set_rc_tuning(payload: &MspRcTuning, index: usize, value: f32) -> MspSetRcTuning {
+    let mut rates = rc_tuning_values(payload);
+    let (_, min, max) = RC_TUNING_PARAMS[index - PID_PARAM_COUNT];
+    rates[index - PID_PARAM_COUNT] = value.clamp(min as f32, max as f32).round() as u8;
+    MspSetRcTuning {
+        rc_rate: rates[0],
+        rc_expo: rates[1],
+        roll_pitch_rate: rates[2],
+        yaw_rate: rates[3],
+        dyn_thr_pid: rates[4],
+        throttle_mid: rates[5],
+        throttle_expo: rates[6],
+    }
+}
+
+/// writes the rate parameter at `index` to the FC and yields the resulting `PARAM_VALUE`
+///
+/// Like `set_pid_param`, the rates are read first so only the requested one is altered.
+pub fn set_rc_tuning_param(
+    mut mspconn: &mut dyn MspConnection,
+    index: usize,
+    value: f32,
+) -> io::Result<MavMessage> {
+    let payload: MspRcTuning = MspMessage::fetch(&mut mspconn)?;
+    MspMessage::send_command(&set_rc_tuning(&payload, index, value), &mut mspconn)?;
+
+    let payload: MspRcTuning = MspMessage::fetch(&mut mspconn)?;
+    Ok(rc_tuning_param(&payload, index))
 }
 
+/// reads the PID gain or rate parameter at `index` from the FC
+pub fn fetch_param(mut mspconn: &mut dyn MspConnection, index: usize) -> io::Result<MavMessage> {
+    if index < PID_PARAM_COUNT {
+        Ok(pid_param(&MspMessage::fetch(&mut mspconn)?, index))
+    } else {
+        Ok(rc_tuning_param(&MspMessage::fetch(&mut mspconn)?, index))
+    }
+}
+
+/// writes the PID gain or rate parameter at `index` to the FC
+pub fn set_param(
+    mspconn: &mut dyn MspConnection,
+    index: usize,
+    value: f32,
+) -> io::Result<MavMessage> {
+    if index < PID_PARAM_COUNT {
+        set_pid_param(mspconn, index, value)
+    } else {
+        set_rc_tuning_param(mspconn, index, value)
+    }
+}
+
+/// reads all PID gain and rate parameters from the FC
+pub fn fetch_params(mut mspconn: &mut dyn MspConnection) -> io::Result<Vec<MavMessage>> {
+    let gains: MspPid = MspMessage::fetch(&mut mspconn)?;
+    let rates: MspRcTuning = MspMessage::fetch(&mut mspconn)?;
+    Ok((0..PARAM_COUNT)
+        .map(|index| match index {
+            i if i < PID_PARAM_COUNT => pid_param(&gains, i),
+            i => rc_tuning_param(&rates, i),
+        })
+        .collect())
+}
+
+/// saves written parameters to the EEPROM of the FC, if `--persist-writes` is given
+///
+/// Yields a `STATUSTEXT` confirming the save. The FC rejects EEPROM writes while armed, so
+/// nothing is saved then and a warning is yielded instead.
+pub fn persist_writes(
+    conf: &Config,
+    mut mspconn: &mut dyn MspConnection,
+) -> io::Result<Vec<MavMessage>> {
+    if !conf.persist_writes {
+        return Ok(Vec::new());
+    }
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    if status.flag & MSP_STATUS_ARMED != 0 {
+        warn!("not saving parameters to EEPROM while armed");
+        return Ok(statustexts(
+            MavSeverity::MAV_SEVERITY_WARNING,
+            "armed, parameters not saved to EEPROM",
+        ));
+    }
+    MspMessage::send_command(&MspEepromWrite {}, &mut mspconn)?;
+    Ok(statustexts(
+        MavSeverity::MAV_SEVERITY_INFO,
+        "parameters saved to EEPROM",
+    ))
+}
+
+/// name of the parameter selecting the PID and rate profile of the FC, counting from 0
+pub const PROFILE_PARAM: &str = "PROFILE";
+
+/// switches the FC to another PID and rate profile
+///
+/// Yields the `PARAM_VALUE` of the profile read back from the FC and a `STATUSTEXT` confirming
+/// the switch, or warning that the FC did not switch.
+pub fn select_profile(
+    mut mspconn: &mut dyn MspConnection,
+    profile: f32,
+) -> io::Result<Vec<MavMessage>> {
+    let profile = profile.round().clamp(0., u8::MAX as f32) as u8;
+    MspMessage::send_command(&MspSelectSetting { profile }, &mut mspconn)?;
+
+    let active = active_profile(mspconn)?;
+    let (severity, text) = if active == profile {
+        (
+            MavSeverity::MAV_SEVERITY_INFO,
+            format!("profile {} active", active),
+        )
+    } else {
+        (
+            MavSeverity::MAV_SEVERITY_WARNING,
+            format!("profile {} rejected, {} still active", profile, active),
+        )
+    };
+    let mut messages = vec![profile_value(active)];
+    messages.extend(statustexts(severity, &text));
+    Ok(messages)
+}
+
+/// fetches the active profile of the FC
+fn active_profile(mut mspconn: &mut dyn MspConnection) -> io::Result<u8> {
+    // bypasses the fetch cache, a stale status would hide a switch
+    let status: MspStatus = MspMessage::fetch(&mut mspconn)?;
+    Ok(status.global_conf_current_set)
+}
+
+/// `PARAM_VALUE` of the `active` profile
+fn profile_value(active: u8) -> MavMessage {
+    PARAM_VALUE(PARAM_VALUE_DATA {
+        param_value: active as f32,
+        param_count: 1,
+        param_index: u16::MAX,
+        param_id: to_param_id(PROFILE_PARAM),
+        param_type: MavParamType::MAV_PARAM_TYPE_UINT8,
+    })
+}
+
+/// reads the active profile of the FC as `PARAM_VALUE`
+pub fn read_profile(mspconn: &mut dyn MspConnection) -> io::Result<MavMessage> {
+    Ok(profile_value(active_profile(mspconn)?))
+}
+
+/// MAVLink type of a MSP setting, strings can not be represented as parameter
+fn setting_param_type(setting_type: u8) -> Option<MavParamType> {
+    match setting_type {
+        0 => Some(MavParamType::MAV_PARAM_TYPE_UINT8),
+        1 => Some(MavParamType::MAV_PARAM_TYPE_INT8),
+        2 => Some(MavParamType::MAV_PARAM_TYPE_UINT16),
+        3 => Some(MavParamType::MAV_PARAM_TYPE_INT16),
+        4 => Some(MavParamType::MAV_PARAM_TYPE_UINT32),
+        5 => Some(MavParamType::MAV_PARAM_TYPE_REAL32),
+        _ => None,
+    }
+}
+
+/// checks whether a setting can be represented as MAVLink parameter
+pub fn is_param_setting(info: &MspSettingInfo) -> bool {
+    setting_param_type(info.setting_type).is_some()
+}
+
+/// decodes the raw value of a setting
+fn setting_value(setting_type: u8, value: &[u8]) -> Option<f32> {
+    Some(match (setting_type, value) {
+        (0, [v, ..]) => *v as f32,
+        (1, [v, ..]) => *v as i8 as f32,
+        (2, [a, b, ..]) => u16::from_le_bytes([*a, *b]) as f32,
+        (3, [a, b, ..]) => i16::from_le_bytes([*a, *b]) as f32,
+        (4, [a, b, c, d, ..]) => u32::from_le_bytes([*a, *b, *c, *d]) as f32,
+        (5, [a, b, c, d, ..]) => f32::from_le_bytes([*a, *b, *c, *d]),
+        _ => return None,
+    })
+}
+
+/// encodes a parameter value as raw value of a setting, clamped to the range of the setting
+fn setting_bytes(info: &MspSettingInfo, value: f32) -> Option<Vec<u8>> {
+    let (min, max) = (info.min as f32, info.max as f32);
+    let value = if min <= max {
+        value.clamp(min, max)
+    } else {
+        value
+    };
+    Some(match info.setting_type {
+        0 => vec![value.round() as u8],
+        1 => (value.round() as i8).to_le_bytes().to_vec(),
+        2 => (value.round() as u16).to_le_bytes().to_vec(),
+        3 => (value.round() as i16).to_le_bytes().to_vec(),
+        4 => (value.round() as u32).to_le_bytes().to_vec(),
+        5 => value.to_le_bytes().to_vec(),
+        _ => return None,
+    })
+}
+
+/// builds the `PARAM_VALUE` of a setting, which is parameter `index` out of `count`
+///
+/// Setting names longer than 16 chars are truncated, as MAVLink does not allow more.
+pub fn setting_param(info: &MspSettingInfo, index: usize, count: usize) -> MavMessage {
+    PARAM_VALUE(PARAM_VALUE_DATA {
+        param_value: setting_value(info.setting_type, &info.value).unwrap_or(0.),
+        param_count: count as u16,
+        param_index: index as u16,
+        param_id: to_param_id(&info.name),
+        param_type: setting_param_type(info.setting_type)
+            .unwrap_or(MavParamType::MAV_PARAM_TYPE_REAL32),
+    })
+}
+
+/// looks up a setting parameter by its index or, if `index` is -1, by its name
+pub fn find_setting(settings: &[MspSettingInfo], index: i16, name: &str) -> Option<usize> {
+    if index >= 0 {
+        Some(index as usize).filter(|index| *index < settings.len())
+    } else {
+        settings
+            .iter()
+            .position(|info| from_param_id(&to_param_id(&info.name)) == name)
+    }
+}
+
+/// fetches the description and current value of the setting `index`
+pub fn fetch_setting_info(
+    mut mspconn: &mut dyn MspConnection,
+    index: u16,
+) -> io::Result<MspSettingInfo> {
+    Ok(MspMessage::query(MspSettingIndex(index), &mut mspconn)?)
+}
+
+/// writes `value` to a setting and yields the description with the resulting value
+pub fn set_setting(
+    mut mspconn: &mut dyn MspConnection,
+    info: &MspSettingInfo,
+    value: f32,
+) -> io::Result<MspSettingInfo> {
+    let value = setting_bytes(info, value).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} can not be set as parameter", info.name),
+        )
+    })?;
+    let setting = MspSetSetting {
+        index: info.index,
+        value,
+    };
+    MspMessage::send_command(&setting, &mut mspconn)?;
+    fetch_setting_info(mspconn, info.index)
+}
+
+/// yields the parameter following the one in `context`, or the first one
+pub fn param_value(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let index = match context {
+        Some(PARAM_VALUE(msg)) => (msg.param_index as usize + 1) % PARAM_COUNT,
+        _ => 0,
+    };
+    if index < PID_PARAM_COUNT {
+        Ok(vec![pid_param(&fetch(conf, state, &mut mspconn)?, index)])
+    } else {
+        Ok(vec![rc_tuning_param(
+            &fetch(conf, state, &mut mspconn)?,
+            index,
+        )])
+    }
+}
+
+/// forwards the MSP sensor readings unscaled, see `scaled_imu` for physical units
 pub fn raw_imu(
     conf: &Config,
+    state: &BridgeState,
     mut mspconn: &mut dyn MspConnection,
     _context: Option<&MavMessage>,
-) -> io::Result<MavMessage> {
-    let payload: MspRawImu = MspMessage::fetch(&mut mspconn)?;
-    Ok(RAW_IMU(RAW_IMU_DATA {
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspRawImu = fetch(conf, state, &mut mspconn)?;
+    Ok(vec![RAW_IMU(RAW_IMU_DATA {
         time_usec: conf.t0.elapsed().as_micros() as u64,
         xacc: payload.accx,
         yacc: payload.accy,
@@ -54,38 +1225,2049 @@ pub fn raw_imu(
         xmag: payload.magx,
         ymag: payload.magy,
         zmag: payload.magz,
-    }))
+    })])
+}
+
+/// scales a raw sensor reading by `factor`, saturating at the bounds of `i16`
+fn scale_sensor(value: i16, factor: f64) -> i16 {
+    (value as f64 * factor).round() as i16
+}
+
+pub fn scaled_imu(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspRawImu = fetch(conf, state, &mut mspconn)?;
+    Ok(vec![SCALED_IMU(SCALED_IMU_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        xacc: scale_sensor(payload.accx, conf.acc_scale),
+        yacc: scale_sensor(payload.accy, conf.acc_scale),
+        zacc: scale_sensor(payload.accz, conf.acc_scale),
+        xgyro: scale_sensor(payload.gyrx, axis_sign(conf.invert_roll) * conf.gyro_scale),
+        ygyro: scale_sensor(payload.gyry, axis_sign(conf.invert_pitch) * conf.gyro_scale),
+        zgyro: scale_sensor(payload.gyrz, axis_sign(conf.invert_yaw) * conf.gyro_scale),
+        xmag: scale_sensor(payload.magx, conf.mag_scale),
+        ymag: scale_sensor(payload.magy, conf.mag_scale),
+        zmag: scale_sensor(payload.magz, conf.mag_scale),
+    })])
+}
+
+/// corrects a magnetic heading in deg by `--declination-deg` and wraps it into -180 to 180, as
+/// MAVLink wants yaw in -pi to pi
+fn true_heading(conf: &Config, heading: i16) -> f64 {
+    match (heading as f64 + conf.declination_deg as f64).rem_euclid(360.) {
+        h if h > 180. => h - 360.,
+        h => h,
+    }
+}
+
+/// -1 for an axis inverted by `--invert-*`, 1 otherwise
+fn axis_sign(invert: bool) -> f64 {
+    if invert {
+        -1.
+    } else {
+        1.
+    }
+}
+
+/// roll, pitch and yaw in radians, MSP reports roll and pitch in 0.1 deg and heading in deg
+fn euler_angles(conf: &Config, payload: &MspAttitude) -> (f32, f32, f32) {
+    (
+        (axis_sign(conf.invert_roll) * payload.angx as f64 / 10.).to_radians() as f32,
+        (axis_sign(conf.invert_pitch) * -payload.angy as f64 / 10.).to_radians() as f32,
+        (axis_sign(conf.invert_yaw) * true_heading(conf, payload.heading)).to_radians() as f32,
+    )
+}
+
+/// low-pass filters the yaw in radians against the `previous` one to reduce compass noise,
+/// keeping `--heading-smoothing` of the previous yaw
+fn smooth_yaw(conf: &Config, yaw: f32, previous: f32) -> f32 {
+    let pi = std::f32::consts::PI;
+    let smoothing = conf.heading_smoothing.clamp(0., 1.);
+    let smoothed = previous + (1. - smoothing) * angle_diff(yaw, previous);
+    if smoothed > pi {
+        smoothed - 2. * pi
+    } else if smoothed < -pi {
+        smoothed + 2. * pi
+    } else {
+        smoothed
+    }
+}
+
+/// converts euler angles in radians to a normalized quaternion `(w, x, y, z)`
+pub fn quaternion(roll: f32, pitch: f32, yaw: f32) -> [f32; 4] {
+    let (sr, cr) = (roll / 2.).sin_cos();
+    let (sp, cp) = (pitch / 2.).sin_cos();
+    let (sy, cy) = (yaw / 2.).sin_cos();
+    let q = [
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    ];
+    let norm = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+    [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]
+}
+
+/// difference of two angles in radians, wrapped to -pi..pi
+fn angle_diff(a: f32, b: f32) -> f32 {
+    let pi = std::f32::consts::PI;
+    let diff = (a - b) % (2. * pi);
+    if diff > pi {
+        diff - 2. * pi
+    } else if diff < -pi {
+        diff + 2. * pi
+    } else {
+        diff
+    }
+}
+
+/// differentiates the angles of `current` against `previous` to get the angular rates
+fn attitude_rates(current: &mut ATTITUDE_DATA, previous: &ATTITUDE_DATA) {
+    let dt = current.time_boot_ms.wrapping_sub(previous.time_boot_ms) as f32 / 1000.;
+    if dt <= 0. {
+        return;
+    }
+    current.rollspeed = angle_diff(current.roll, previous.roll) / dt;
+    current.pitchspeed = angle_diff(current.pitch, previous.pitch) / dt;
+    current.yawspeed = angle_diff(current.yaw, previous.yaw) / dt;
 }
 
 pub fn attitude(
     conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspAttitude = fetch(conf, state, &mut mspconn)?;
+    let (roll, pitch, yaw) = euler_angles(conf, &payload);
+    let mut data = ATTITUDE_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        roll,
+        pitch,
+        yaw,
+        rollspeed: 0.,
+        pitchspeed: 0.,
+        yawspeed: 0.,
+    };
+    if let Some(ATTITUDE(previous)) = context {
+        data.yaw = smooth_yaw(conf, data.yaw, previous.yaw);
+        attitude_rates(&mut data, previous);
+    }
+    Ok(vec![ATTITUDE(data)])
+}
+
+pub fn attitude_quaternion(
+    conf: &Config,
+    state: &BridgeState,
     mut mspconn: &mut dyn MspConnection,
     _context: Option<&MavMessage>,
-) -> io::Result<MavMessage> {
-    let payload: MspAttitude = MspMessage::fetch(&mut mspconn)?;
-    Ok(ATTITUDE(ATTITUDE_DATA {
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspAttitude = fetch(conf, state, &mut mspconn)?;
+    let (roll, pitch, yaw) = euler_angles(conf, &payload);
+    let [q1, q2, q3, q4] = quaternion(roll, pitch, yaw);
+    Ok(vec![ATTITUDE_QUATERNION(ATTITUDE_QUATERNION_DATA {
         time_boot_ms: conf.t0.elapsed().as_millis() as u32,
-        roll: (payload.angx as f64 / 10.).to_radians() as f32,
-        pitch: (-payload.angy as f64 / 10.).to_radians() as f32,
-        yaw: (payload.heading as f64).to_radians() as f32,
+        q1,
+        q2,
+        q3,
+        q4,
         rollspeed: 0.,
         pitchspeed: 0.,
         yawspeed: 0.,
+    })])
+}
+
+/// estimates the remaining battery percentage from the cell voltage, `vbat` is in 0.1V
+///
+/// The cell count is guessed from the pack voltage, assuming LiPo cells between 3.3V and 4.2V.
+fn battery_remaining(vbat: u8) -> i8 {
+    if vbat == 0 {
+        // no battery sensor
+        return -1;
+    }
+    let cells = (vbat as f64 / 42.).ceil();
+    let cell_voltage = vbat as f64 / 10. / cells;
+    ((cell_voltage - 3.3) / (4.2 - 3.3) * 100.).clamp(0., 100.) as i8
+}
+
+pub fn sys_status(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspAnalog = fetch(conf, state, &mut mspconn)?;
+    let status: MspStatus = fetch(conf, state, &mut mspconn)?;
+    let (present, health) = sensors(status.sensor);
+    Ok(vec![SYS_STATUS(SYS_STATUS_DATA {
+        onboard_control_sensors_present: present,
+        onboard_control_sensors_enabled: present,
+        onboard_control_sensors_health: health,
+        load: 0,
+        voltage_battery: payload.vbat as u16 * 100,
+        current_battery: payload.amperage as i16,
+        drop_rate_comm: 0,
+        errors_comm: 0,
+        errors_count1: 0,
+        errors_count2: status.i2c_errors_count,
+        errors_count3: 0,
+        errors_count4: 0,
+        battery_remaining: battery_remaining(payload.vbat),
+    })])
+}
+
+/// MSP sensor bits of `MspStatus` and their MAVLink counterpart, the sonar is a rangefinder
+const SENSOR_BITS: [(u16, MavSysStatusSensor); 6] = [
+    (1 << 0, MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_ACCEL),
+    (
+        1 << 1,
+        MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_ABSOLUTE_PRESSURE,
+    ),
+    (1 << 2, MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_MAG),
+    (1 << 3, MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_GPS),
+    (
+        1 << 4,
+        MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_LASER_POSITION,
+    ),
+    (
+        1 << 5,
+        MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_OPTICAL_FLOW,
+    ),
+];
+
+/// set if the FC reports a hardware failure of any sensor
+const SENSOR_FAILURE: u16 = 1 << 15;
+
+/// present and healthy sensors from the `MspStatus` sensor bitmask
+///
+/// The gyro is always present. MSP only tells that some sensor failed, not which, so a failure
+/// marks all sensors as unhealthy.
+fn sensors(sensor: u16) -> (MavSysStatusSensor, MavSysStatusSensor) {
+    let present = SENSOR_BITS
+        .iter()
+        .filter(|(bit, _)| sensor & bit != 0)
+        .fold(
+            MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_GYRO,
+            |acc, (_, s)| acc | *s,
+        );
+    let health = match sensor & SENSOR_FAILURE {
+        0 => present,
+        _ => MavSysStatusSensor::empty(),
+    };
+    (present, health)
+}
+
+/// altitude in cm below which an armed drone is still considered on ground
+const ON_GROUND_ALTITUDE: i32 = 50;
+
+/// ground speed in cm/s below which an armed drone is still considered on ground
+const ON_GROUND_SPEED: u16 = 50;
+
+/// infers whether the drone is on ground from arm state, altitude and ground speed
+fn landed_state(armed: bool, altitude: i32, speed: u16) -> MavLandedState {
+    if !armed || (altitude.abs() < ON_GROUND_ALTITUDE && speed < ON_GROUND_SPEED) {
+        MavLandedState::MAV_LANDED_STATE_ON_GROUND
+    } else {
+        MavLandedState::MAV_LANDED_STATE_IN_AIR
+    }
+}
+
+/// reports whether the drone is in air, MSP knows no VTOL state
+pub fn extended_sys_state(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let status: MspStatus = fetch(conf, state, &mut mspconn)?;
+    let altitude: MspAltitude = fetch(conf, state, &mut mspconn)?;
+    let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    Ok(vec![EXTENDED_SYS_STATE(EXTENDED_SYS_STATE_DATA {
+        vtol_state: MavVtolState::MAV_VTOL_STATE_UNDEFINED,
+        landed_state: landed_state(
+            status.flag & MSP_STATUS_ARMED != 0,
+            altitude.estimated_alt,
+            gps.speed,
+        ),
+    })])
+}
+
+/// reports the time since start and the wall clock, which needs no MSP
+pub fn system_time(
+    conf: &Config,
+    _state: &BridgeState,
+    _mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(vec![SYSTEM_TIME(SYSTEM_TIME_DATA {
+        time_unix_usec: unix.as_micros() as u64,
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+    })])
+}
+
+/// answers a `TIMESYNC` request with the time since start in ns
+///
+/// A `TIMESYNC` with `tc1` set is the answer to someone else's request and is ignored.
+pub fn timesync(conf: &Config, msg: &TIMESYNC_DATA) -> Option<MavMessage> {
+    match msg.tc1 {
+        0 => Some(TIMESYNC(TIMESYNC_DATA {
+            tc1: conf.t0.elapsed().as_nanos() as i64,
+            ts1: msg.ts1,
+        })),
+        _ => None,
+    }
+}
+
+/// first MSP API version to answer `MspBatteryState`
+const BATTERY_STATE_API: (u8, u8) = (1, 33);
+
+/// spreads the pack voltage in 0.01V evenly over the cells, in mV
+///
+/// An unknown cell count reports the whole pack as first cell, as MAVLink suggests.
+pub fn cell_voltages(cell_count: u8, voltage: u16) -> [u16; 10] {
+    let mut voltages = [u16::MAX; 10];
+    let pack = voltage as u32 * 10;
+    match cell_count as usize {
+        0 => voltages[0] = pack.min(u16::MAX as u32 - 1) as u16,
+        cells => {
+            for v in voltages.iter_mut().take(cells) {
+                *v = (pack / cells as u32) as u16;
+            }
+        }
+    }
+    voltages
+}
+
+/// remaining capacity in percent from the drawn mAh, if the capacity is configured
+fn capacity_remaining(capacity: u16, mah_drawn: u16) -> Option<i8> {
+    match capacity {
+        0 => None,
+        _ => Some((100 - mah_drawn as i32 * 100 / capacity as i32).clamp(0, 100) as i8),
+    }
+}
+
+/// uses `MspBatteryState` if the FC supports it, `MspAnalog` otherwise
+pub fn battery_status(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let supported = state
+        .api_version
+        .map_or(false, |version| version >= BATTERY_STATE_API);
+    let (voltages, current_battery, current_consumed, battery_remaining) = if supported {
+        let battery: MspBatteryState = fetch(conf, state, &mut mspconn)?;
+        (
+            cell_voltages(battery.cell_count, battery.voltage_precise),
+            battery.amperage as i16,
+            battery.mah_drawn as i32,
+            capacity_remaining(battery.capacity, battery.mah_drawn)
+                .unwrap_or_else(|| battery_remaining(battery.voltage)),
+        )
+    } else {
+        let analog: MspAnalog = fetch(conf, state, &mut mspconn)?;
+        (
+            cell_voltages(0, analog.vbat as u16 * 10),
+            analog.amperage as i16,
+            analog.int_power_meter_sum as i32,
+            battery_remaining(analog.vbat),
+        )
+    };
+    Ok(vec![BATTERY_STATUS(BATTERY_STATUS_DATA {
+        current_consumed,
+        energy_consumed: -1,
+        temperature: i16::MAX,
+        voltages,
+        current_battery,
+        id: 0,
+        battery_function: MavBatteryFunction::MAV_BATTERY_FUNCTION_ALL,
+        mavtype: MavBatteryType::MAV_BATTERY_TYPE_LIPO,
+        battery_remaining,
+    })])
+}
+
+/// maps the MSP fix (0: none, 1: 2D, 2: 3D) onto MAVLink
+fn gps_fix_type(fix: u8) -> GpsFixType {
+    match fix {
+        1 => GpsFixType::GPS_FIX_TYPE_2D_FIX,
+        2 => GpsFixType::GPS_FIX_TYPE_3D_FIX,
+        _ => GpsFixType::GPS_FIX_TYPE_NO_FIX,
+    }
+}
+
+/// maps the MAVLink fix type onto MSP (0: none, 1: 2D, 2: 3D), DGPS and RTK count as 3D
+fn msp_gps_fix(fix_type: u8) -> u8 {
+    match fix_type {
+        0 | 1 => 0,
+        2 => 1,
+        _ => 2,
+    }
+}
+
+/// the fix of a `GPS_INPUT` as `MspRawGps` reports it
+///
+/// An altitude or horizontal velocity the source marks as ignored is reported as 0.
+pub fn injected_gps(msg: &GPS_INPUT_DATA) -> MspRawGps {
+    let altitude = if msg
+        .ignore_flags
+        .contains(GpsInputIgnoreFlags::GPS_INPUT_IGNORE_FLAG_ALT)
+    {
+        0.
+    } else {
+        msg.alt
+    };
+    let (speed, course) = if msg
+        .ignore_flags
+        .contains(GpsInputIgnoreFlags::GPS_INPUT_IGNORE_FLAG_VEL_HORIZ)
+    {
+        (0., 0.)
+    } else {
+        (
+            msg.vn.hypot(msg.ve),
+            msg.ve.atan2(msg.vn).to_degrees().rem_euclid(360.),
+        )
+    };
+    MspRawGps {
+        fix: msp_gps_fix(msg.fix_type),
+        num_sat: msg.satellites_visible,
+        coord_lat: msg.lat,
+        coord_lon: msg.lon,
+        altitude: altitude.round().clamp(0., u16::MAX as f32) as u16,
+        speed: (speed * 100.).round().clamp(0., u16::MAX as f32) as u16,
+        ground_course: (course * 10.).round() as u16 % 3600,
+    }
+}
+
+/// forwards the fix of a `GPS_INPUT` to the FC
+///
+/// The GPS of the FC is not polled until the injected fix is older than
+/// `GPS_INJECTION_TIMEOUT`, the generators report the injected fix meanwhile.
+pub fn inject_gps(
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    msg: &GPS_INPUT_DATA,
+) -> io::Result<()> {
+    let gps = injected_gps(msg);
+    let payload = MspSetRawGps {
+        fix: gps.fix,
+        num_sat: gps.num_sat,
+        coord_lat: gps.coord_lat,
+        coord_lon: gps.coord_lon,
+        altitude: gps.altitude,
+        speed: gps.speed,
+    };
+    MspMessage::send_command(&payload, &mut mspconn)?;
+    state.gps_injection.inject(gps);
+    Ok(())
+}
+
+pub fn gps_raw_int(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let payload: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    Ok(vec![GPS_RAW_INT(GPS_RAW_INT_DATA {
+        time_usec: conf.t0.elapsed().as_micros() as u64,
+        lat: payload.coord_lat,
+        lon: payload.coord_lon,
+        alt: payload.altitude as i32 * 1000,
+        eph: u16::MAX,
+        epv: u16::MAX,
+        vel: payload.speed,
+        cog: (payload.ground_course % 3600) * 10,
+        fix_type: gps_fix_type(payload.fix),
+        satellites_visible: payload.num_sat,
+    })])
+}
+
+/// Polls both `MspRawGps` and `MspAltitude`, so this costs two MSP round-trips per call. A
+/// failed altitude fetch is tolerated, the position is still emitted with `relative_alt = 0`.
+pub fn global_position_int(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    let (relative_alt, vz) = match fetch::<MspAltitude, _>(conf, state, &mut mspconn) {
+        Ok(altitude) => (altitude.estimated_alt * 10, -altitude.vario),
+        Err(e) => {
+            warn!("unable to fetch MspAltitude: {:?}", e);
+            (0, 0)
+        }
+    };
+    Ok(vec![GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        lat: gps.coord_lat,
+        lon: gps.coord_lon,
+        alt: gps.altitude as i32 * 1000,
+        relative_alt,
+        vx: 0,
+        vy: 0,
+        vz,
+        hdg: (gps.ground_course % 3600) * 10,
+    })])
+}
+
+/// The FC has no GPS fix, so there is no position to report
+#[derive(Debug)]
+pub struct NoGpsFix;
+
+impl fmt::Display for NoGpsFix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no GPS fix")
+    }
+}
+
+impl Error for NoGpsFix {}
+
+/// checks whether a generator failed only because of a missing GPS fix
+pub fn is_no_gps_fix(e: &io::Error) -> bool {
+    e.get_ref().map_or(false, |e| e.is::<NoGpsFix>())
+}
+
+/// mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_000.;
+
+/// moves a position in 1e7 deg by `distance` meters towards `bearing` deg
+///
+/// Uses a flat earth approximation, which is fine for the distances a drone flies from home.
+pub fn offset_position(lat: i32, lon: i32, distance: f64, bearing: f64) -> (i32, i32) {
+    let north = distance * bearing.to_radians().cos();
+    let east = distance * bearing.to_radians().sin();
+    let lat_deg = lat as f64 / 1e7;
+    let dlat = (north / EARTH_RADIUS).to_degrees();
+    let dlon = (east / (EARTH_RADIUS * lat_deg.to_radians().cos())).to_degrees();
+    (
+        (lat as f64 + dlat * 1e7).round() as i32,
+        (lon as f64 + dlon * 1e7).round() as i32,
+    )
+}
+
+/// north and east offset in m of a position in 1e7 deg from `origin`, the inverse of
+/// `offset_position`
+///
+/// This equirectangular approximation scales the east offset by the mean latitude, which stays
+/// within a centimeter of the great circle distance up to 10 km from the origin and within a
+/// meter up to 50 km. Taking the earth as a sphere scales both offsets by up to 0.5 % though.
+pub fn local_offset(origin: (i32, i32), lat: i32, lon: i32) -> (f64, f64) {
+    let mean_lat = ((origin.0 as f64 + lat as f64) / 2e7).to_radians();
+    let dlat = ((lat - origin.0) as f64 / 1e7).to_radians();
+    let dlon = ((lon - origin.1) as f64 / 1e7).to_radians();
+    (dlat * EARTH_RADIUS, dlon * EARTH_RADIUS * mean_lat.cos())
+}
+
+/// `MspRawGps.fix` of a 3D fix
+pub const MSP_GPS_FIX_3D: u8 = 2;
+
+/// yields `GPS_GLOBAL_ORIGIN` unless `context` shows it was sent already
+pub fn unsent_origin(
+    origin: GPS_GLOBAL_ORIGIN_DATA,
+    context: Option<&MavMessage>,
+) -> Vec<MavMessage> {
+    match context {
+        Some(GPS_GLOBAL_ORIGIN(sent)) if *sent == origin => Vec::new(),
+        _ => vec![GPS_GLOBAL_ORIGIN(origin)],
+    }
+}
+
+/// The origin is fixed at the position of the first 3D fix and sent once, it is sent again only
+/// after the GCS moved it.
+pub fn gps_global_origin(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let origin = match state.gps_origin.get() {
+        Some(origin) => origin,
+        None => {
+            let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+            // a bad fix reports 0/0, which must not become the origin
+            if gps.fix < MSP_GPS_FIX_3D || (gps.coord_lat == 0 && gps.coord_lon == 0) {
+                return Err(io::Error::new(io::ErrorKind::Other, NoGpsFix));
+            }
+            let origin = state.gps_origin.get_or_set(GPS_GLOBAL_ORIGIN_DATA {
+                latitude: gps.coord_lat,
+                longitude: gps.coord_lon,
+                altitude: gps.altitude as i32 * 1000,
+            });
+            info!("GPS origin fixed at {:?}", origin);
+            origin
+        }
+    };
+    Ok(unsent_origin(origin, context))
+}
+
+/// The position is relative to the origin of `gps_global_origin`, nothing is sent before it is
+/// fixed. Velocities are derived from the GPS ground speed and course and the vario.
+pub fn local_position_ned(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let origin = match state.gps_origin.get() {
+        Some(origin) => origin,
+        None => return Ok(Vec::new()),
+    };
+    let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    if gps.fix == 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, NoGpsFix));
+    }
+    let vario = match fetch::<MspAltitude, _>(conf, state, &mut mspconn) {
+        Ok(altitude) => altitude.vario,
+        Err(e) => {
+            warn!("unable to fetch MspAltitude: {:?}", e);
+            0
+        }
+    };
+    let (x, y) = local_offset(
+        (origin.latitude, origin.longitude),
+        gps.coord_lat,
+        gps.coord_lon,
+    );
+    let (east, north) = (gps.ground_course as f64 / 10.).to_radians().sin_cos();
+    let speed = gps.speed as f64 / 100.;
+    Ok(vec![LOCAL_POSITION_NED(LOCAL_POSITION_NED_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        x: x as f32,
+        y: y as f32,
+        z: (origin.altitude - gps.altitude as i32 * 1000) as f32 / 1000.,
+        vx: (north * speed) as f32,
+        vy: (east * speed) as f32,
+        vz: -vario as f32 / 100.,
+    })])
+}
+
+/// Home is waypoint 0 if the FC reports it, otherwise it is computed from the distance and
+/// direction to home.
+pub fn home_position(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    if gps.fix == 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, NoGpsFix));
+    }
+    let (latitude, longitude) = match fetch_wp(mspconn, 0) {
+        Ok(wp) if wp.lat != 0 || wp.lon != 0 => (wp.lat, wp.lon),
+        _ => {
+            let comp: MspCompGps = fetch(conf, state, &mut mspconn)?;
+            offset_position(
+                gps.coord_lat,
+                gps.coord_lon,
+                comp.distance_to_home as f64,
+                comp.direction_to_home as f64,
+            )
+        }
+    };
+    let relative_alt = match fetch::<MspAltitude, _>(conf, state, &mut mspconn) {
+        Ok(altitude) => altitude.estimated_alt * 10,
+        Err(e) => {
+            warn!("unable to fetch MspAltitude: {:?}", e);
+            0
+        }
+    };
+    Ok(vec![HOME_POSITION(HOME_POSITION_DATA {
+        latitude,
+        longitude,
+        altitude: gps.altitude as i32 * 1000 - relative_alt,
+        x: 0.,
+        y: 0.,
+        z: 0.,
+        q: [1., 0., 0., 0.],
+        approach_x: 0.,
+        approach_y: 0.,
+        approach_z: 0.,
+    })])
+}
+
+/// pressure in hPa and temperature in cdegC of the standard atmosphere at `altitude` in m
+pub fn standard_atmosphere(altitude: f32) -> (f32, i16) {
+    let press_abs = 1013.25 * (1. - 2.25577e-5 * altitude).powf(5.25588);
+    let temperature = 1500. - 0.65 * altitude;
+    (press_abs, temperature.round() as i16)
+}
+
+/// MSP offers no raw barometer readings, so pressure and temperature are those of the standard
+/// atmosphere at the estimated altitude, as if home was at sea level. They are derived, not
+/// measured, and only follow altitude changes.
+pub fn scaled_pressure(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let altitude: MspAltitude = fetch(conf, state, &mut mspconn)?;
+    let (press_abs, temperature) = standard_atmosphere(altitude.estimated_alt as f32 / 100.);
+    Ok(vec![SCALED_PRESSURE(SCALED_PRESSURE_DATA {
+        time_boot_ms: conf.t0.elapsed().as_millis() as u32,
+        press_abs,
+        press_diff: 0.,
+        temperature,
+    })])
+}
+
+/// closest distance in cm a rangefinder measures
+const RANGEFINDER_MIN_CM: u16 = 2;
+
+/// farthest distance in cm a rangefinder measures, INAV reports anything beyond as out of range
+const RANGEFINDER_MAX_CM: u16 = 400;
+
+/// a downward facing rangefinder reading `distance` cm. Negative readings, which INAV reports
+/// while the rangefinder is out of range or failed, and readings beyond the range are reported as
+/// the max range with an unknown covariance to mark them unhealthy.
+fn distance_sensor_data(time_boot_ms: u32, distance: i32) -> DISTANCE_SENSOR_DATA {
+    let range = RANGEFINDER_MIN_CM as i32..=RANGEFINDER_MAX_CM as i32;
+    let (current_distance, covariance) = if range.contains(&distance) {
+        (distance as u16, 0)
+    } else {
+        (RANGEFINDER_MAX_CM, u8::MAX)
+    };
+    DISTANCE_SENSOR_DATA {
+        time_boot_ms,
+        min_distance: RANGEFINDER_MIN_CM,
+        max_distance: RANGEFINDER_MAX_CM,
+        current_distance,
+        mavtype: MavDistanceSensor::MAV_DISTANCE_SENSOR_LASER,
+        id: 0,
+        orientation: MavSensorOrientation::MAV_SENSOR_ROTATION_PITCH_270,
+        covariance,
+    }
+}
+
+pub fn distance_sensor(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let sonar: MspSonarAltitude = fetch(conf, state, &mut mspconn)?;
+    Ok(vec![DISTANCE_SENSOR(distance_sensor_data(
+        conf.t0.elapsed().as_millis() as u32,
+        sonar.distance,
+    ))])
+}
+
+/// MSP offers no airspeed, so `airspeed` is reported equal to the GPS groundspeed.
+pub fn vfr_hud(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let altitude: MspAltitude = fetch(conf, state, &mut mspconn)?;
+    let attitude: MspAttitude = fetch(conf, state, &mut mspconn)?;
+    let gps: MspRawGps = fetch(conf, state, &mut mspconn)?;
+    let rc: MspRc = fetch(conf, state, &mut mspconn)?;
+
+    let groundspeed = gps.speed as f32 / 100.;
+    // channel 3 is throttle, scaled from 1000..2000 us to 0..100 %
+    let throttle = (rc[2].clamp(1000, 2000) - 1000) / 10;
+    Ok(vec![VFR_HUD(VFR_HUD_DATA {
+        airspeed: groundspeed,
+        groundspeed,
+        alt: altitude.estimated_alt as f32 / 100.,
+        climb: altitude.vario as f32 / 100.,
+        heading: true_heading(conf, attitude.heading)
+            .rem_euclid(360.)
+            .round() as i16,
+        throttle,
+    })])
+}
+
+/// `SERVO_OUTPUT_RAW` port of motors 1 to 8, motors 9 to 16 follow on the next port
+const MOTOR_PORT: u8 = 2;
+
+/// fills the eight outputs of one `SERVO_OUTPUT_RAW` port with the first eight of `outputs`
+pub fn servo_output_port(time_usec: u32, outputs: &[u16], port: u8) -> SERVO_OUTPUT_RAW_DATA {
+    SERVO_OUTPUT_RAW_DATA {
+        time_usec,
+        servo1_raw: outputs[0],
+        servo2_raw: outputs[1],
+        servo3_raw: outputs[2],
+        servo4_raw: outputs[3],
+        servo5_raw: outputs[4],
+        servo6_raw: outputs[5],
+        servo7_raw: outputs[6],
+        servo8_raw: outputs[7],
+        port,
+    }
+}
+
+/// one `SERVO_OUTPUT_RAW` per eight of `outputs`, numbering the ports from `first_port`
+fn servo_output_ports(time_usec: u32, outputs: &[u16], first_port: u8) -> Vec<MavMessage> {
+    outputs
+        .chunks(8)
+        .zip(first_port..)
+        .map(|(outputs, port)| SERVO_OUTPUT_RAW(servo_output_port(time_usec, outputs, port)))
+        .collect()
+}
+
+/// Reports servos and motors, eight outputs per port: port 0 holds servos 1 to 8, port 1 servos
+/// 9 to 16, port 2 motors 1 to 8 and port 3 motors 9 to 16.
+///
+/// This MAVLink version has no `ESC_STATUS`, so ESC telemetry is not reported. A failed motor
+/// fetch is tolerated, the servos are still emitted.
+pub fn servo_output_raw(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let servos: MspServo = fetch(conf, state, &mut mspconn)?;
+    let time_usec = conf.t0.elapsed().as_micros() as u32;
+    let mut messages = servo_output_ports(time_usec, servos.as_slice(), 0);
+    match fetch::<MspMotor, _>(conf, state, &mut mspconn) {
+        Ok(motors) => messages.extend(servo_output_ports(time_usec, motors.as_slice(), MOTOR_PORT)),
+        Err(e) => warn!("unable to fetch MspMotor: {:?}", e),
+    }
+    Ok(messages)
+}
+
+/// scales the MSP RSSI (0..`max`) to the MAVLink range (0..254)
+fn scale_rssi(rssi: u16, max: u16) -> u8 {
+    (rssi.min(max) as u32 * 254 / max.max(1) as u32) as u8
+}
+
+/// `RC_CHANNELS.rssi` of an unknown signal strength
+const RSSI_UNKNOWN: u8 = u8::MAX;
+
+/// scales the RSSI carried by RC `channel` (counting from 1, 1000..2000 us) to the MAVLink range
+/// (0..254), the RSSI is unknown for a channel out of range
+fn channel_rssi(channels: &[u16], channel: usize) -> u8 {
+    match channel.checked_sub(1).and_then(|i| channels.get(i)) {
+        Some(value) => (((*value).clamp(1000, 2000) - 1000) as u32 * 254 / 1000) as u8,
+        None => RSSI_UNKNOWN,
+    }
+}
+
+/// fills `RC_CHANNELS` with the 16 MSP channels verbatim, channel 17 and 18 are reported unused
+fn rc_channels_data(time_boot_ms: u32, channels: &[u16], rssi: u8) -> RC_CHANNELS_DATA {
+    RC_CHANNELS_DATA {
+        time_boot_ms,
+        chan1_raw: channels[0],
+        chan2_raw: channels[1],
+        chan3_raw: channels[2],
+        chan4_raw: channels[3],
+        chan5_raw: channels[4],
+        chan6_raw: channels[5],
+        chan7_raw: channels[6],
+        chan8_raw: channels[7],
+        chan9_raw: channels[8],
+        chan10_raw: channels[9],
+        chan11_raw: channels[10],
+        chan12_raw: channels[11],
+        chan13_raw: channels[12],
+        chan14_raw: channels[13],
+        chan15_raw: channels[14],
+        chan16_raw: channels[15],
+        chan17_raw: u16::MAX,
+        chan18_raw: u16::MAX,
+        chancount: 16,
+        rssi,
+    }
+}
+
+pub fn rc_channels(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let rc: MspRc = fetch(conf, state, &mut mspconn)?;
+    let rssi = match conf.rssi_channel {
+        Some(channel) => channel_rssi(rc.as_slice(), channel),
+        None => {
+            let analog: MspAnalog = fetch(conf, state, &mut mspconn)?;
+            scale_rssi(analog.rssi, conf.rssi_max)
+        }
+    };
+    Ok(vec![RC_CHANNELS(rc_channels_data(
+        conf.t0.elapsed().as_millis() as u32,
+        rc.as_slice(),
+        rssi,
+    ))])
+}
+
+/// packs a version as `major.minor.patch`, marked as official release
+fn pack_version(major: u8, minor: u8, patch: u8) -> u32 {
+    u32::from_be_bytes([
+        major,
+        minor,
+        patch,
+        FirmwareVersionType::FIRMWARE_VERSION_TYPE_OFFICIAL as u8,
+    ])
+}
+
+/// reports the FC firmware as `flight_sw_version` and the MSP API as `middleware_sw_version`
+///
+/// Versions the FC does not report are left at zero.
+pub fn autopilot_version(
+    conf: &Config,
+    state: &BridgeState,
+    mut mspconn: &mut dyn MspConnection,
+    _context: Option<&MavMessage>,
+) -> io::Result<Vec<MavMessage>> {
+    let flight_sw_version = match fetch::<MspFcVersion, _>(conf, state, &mut mspconn) {
+        Ok(v) => pack_version(v.major, v.minor, v.patch),
+        Err(e) => {
+            warn!("unable to fetch MspFcVersion: {:?}", e);
+            0
+        }
+    };
+    let middleware_sw_version = match fetch::<MspApiVersion, _>(conf, state, &mut mspconn) {
+        Ok(v) => pack_version(v.major, v.minor, 0),
+        Err(e) => {
+            warn!("unable to fetch MspApiVersion: {:?}", e);
+            0
+        }
+    };
+    Ok(vec![AUTOPILOT_VERSION(AUTOPILOT_VERSION_DATA {
+        capabilities: MavProtocolCapability::empty(),
+        uid: 0,
+        flight_sw_version,
+        middleware_sw_version,
+        os_sw_version: 0,
+        board_version: 0,
+        vendor_id: 0,
+        product_id: 0,
+        flight_custom_version: [0; 8],
+        middleware_custom_version: [0; 8],
+        os_custom_version: [0; 8],
+    })])
+}
+
+/// the craft name of `payload`, nothing if the FC has none configured
+pub fn craft_name(payload: &MspName) -> Option<String> {
+    Some(
+        payload
+            .name
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace()),
+    )
+    .filter(|name| !name.is_empty())
+    .map(str::to_string)
+}
+
+/// the `STATUSTEXT` naming the craft for the GCS, nothing if the FC has no craft name
+pub fn craft_name_text(state: &BridgeState) -> Vec<MavMessage> {
+    match &state.craft_name {
+        Some(name) => statustexts(MavSeverity::MAV_SEVERITY_INFO, &format!("craft {}", name)),
+        None => Vec::new(),
+    }
+}
+
+/// `MspWp.nav_flag` of the last waypoint of a mission
+pub const MSP_WP_LAST: u8 = 0xa5;
+
+/// upper bound for the count of waypoints a FC holds
+pub const MAX_WAYPOINTS: u8 = 60;
+
+/// fetches the waypoint `wp_no`, waypoint 0 is home
+pub fn fetch_wp(mut mspconn: &mut dyn MspConnection, wp_no: u8) -> io::Result<MspWp> {
+    Ok(MspMessage::query(MspWpRequest { wp_no }, &mut mspconn)?)
+}
+
+/// counts the waypoints of the mission on the FC by walking them until the last or an empty one
+pub fn mission_length(mspconn: &mut dyn MspConnection) -> io::Result<u16> {
+    for wp_no in 1..=MAX_WAYPOINTS {
+        let wp = fetch_wp(mspconn, wp_no)?;
+        if wp.nav_flag == MSP_WP_LAST {
+            return Ok(wp_no.into());
+        }
+        if wp.lat == 0 && wp.lon == 0 {
+            return Ok((wp_no - 1).into());
+        }
+    }
+    Ok(MAX_WAYPOINTS.into())
+}
+
+/// converts a MSP waypoint into a mission item
+///
+/// A terminating waypoint without coordinates returns to home, it becomes a
+/// `MAV_CMD_NAV_RETURN_TO_LAUNCH`.
+pub fn mission_item_from_wp(wp: &MspWp, seq: u16, target: &MavHeader) -> MISSION_ITEM_INT_DATA {
+    let rth = wp.lat == 0 && wp.lon == 0;
+    MISSION_ITEM_INT_DATA {
+        param1: wp.time_to_stay as f32 / 1000.,
+        param2: 0.,
+        param3: 0.,
+        param4: if rth { 0. } else { wp.heading as f32 },
+        x: wp.lat,
+        y: wp.lon,
+        z: wp.alt_hold as f32 / 100.,
+        seq,
+        command: if rth {
+            MavCmd::MAV_CMD_NAV_RETURN_TO_LAUNCH
+        } else {
+            MavCmd::MAV_CMD_NAV_WAYPOINT
+        },
+        target_system: target.system_id,
+        target_component: target.component_id,
+        frame: if rth {
+            MavFrame::MAV_FRAME_MISSION
+        } else {
+            MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT
+        },
+        current: 0,
+        autocontinue: 1,
+    }
+}
+
+/// converts a mission item into a MSP waypoint, the inverse of `mission_item_from_wp`
+pub fn wp_from_mission_item(item: &MISSION_ITEM_INT_DATA, last: bool) -> MspSetWp {
+    let rth = item.command == MavCmd::MAV_CMD_NAV_RETURN_TO_LAUNCH;
+    MspSetWp {
+        wp_no: (item.seq + 1) as u8,
+        lat: if rth { 0 } else { item.x },
+        lon: if rth { 0 } else { item.y },
+        alt_hold: (item.z * 100.).max(0.) as u32,
+        heading: if rth { 0 } else { item.param4 as i16 },
+        time_to_stay: (item.param1 * 1000.).max(0.).min(u16::MAX as f32) as u16,
+        nav_flag: if last { MSP_WP_LAST } else { 0 },
+    }
+}
+
+/// writes a waypoint to the FC
+pub fn set_wp(mut mspconn: &mut dyn MspConnection, wp: MspSetWp) -> io::Result<()> {
+    MspMessage::send_command(&wp, &mut mspconn)?;
+    Ok(())
+}
+
+/// requests mission item `seq` from `target`
+pub fn mission_request_int(seq: u16, target: &MavHeader) -> MavMessage {
+    MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+        seq,
+        target_system: target.system_id,
+        target_component: target.component_id,
+    })
+}
+
+/// concludes a mission transfer with `target`
+pub fn mission_ack(target: &MavHeader, result: MavMissionResult) -> MavMessage {
+    MISSION_ACK(MISSION_ACK_DATA {
+        target_system: target.system_id,
+        target_component: target.component_id,
+        mavtype: result,
+    })
+}
+
+/// answers a `MISSION_REQUEST_LIST` from `target`
+pub fn mission_count(
+    mspconn: &mut dyn MspConnection,
+    target: &MavHeader,
+) -> io::Result<MavMessage> {
+    Ok(MISSION_COUNT(MISSION_COUNT_DATA {
+        count: mission_length(mspconn)?,
+        target_system: target.system_id,
+        target_component: target.component_id,
     }))
 }
 
-//pub fn mission_count(
-//    conf: &Config,
-//    mut mspconn: &mut dyn MspConnection,
-//    id: u32,
-//    context: Option<&MavMessage>,
-//) -> io::Result<MavMessage> {
-//    use std::convert::TryInto;
-//         let msg:MISSION_REQUEST_DATA  = context.unwrap().try_into().unwrap();
-//        Ok(MISSION_COUNT(MISSION_COUNT_DATA {
-//            target_system: msg.target_system,
-//            target_component: msg.target_component,
-//            count: 0,
-//        }))
-//
-//}
+/// answers a `MISSION_REQUEST` or `MISSION_REQUEST_INT` for item `seq` from `target`
+pub fn mission_item(
+    mspconn: &mut dyn MspConnection,
+    seq: u16,
+    target: &MavHeader,
+) -> io::Result<MavMessage> {
+    let wp_no = seq
+        .checked_add(1)
+        .and_then(|n| u8::try_from(n).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "mission item out of range"))?;
+    let wp = fetch_wp(mspconn, wp_no)?;
+    Ok(MISSION_ITEM_INT(mission_item_from_wp(&wp, seq, target)))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// parses the arguments of a bridge to `/dev/null` followed by `extra`
+    pub fn conf(extra: &[&str]) -> Config {
+        use clap::Clap;
+
+        Config::parse_from(["bridge", "-s", "/dev/null"].iter().chain(extra))
+    }
+
+    #[test]
+    fn fetch_cache_reuses_fresh_payloads() {
+        use crate::replay::test::{entry, frame};
+        use crate::replay::{Direction, ReplayConnection};
+
+        let recording: Vec<String> = [1, 3]
+            .iter()
+            .flat_map(|alt| {
+                vec![
+                    entry(
+                        0,
+                        Direction::Tx,
+                        frame::<MspAltitude>(MspDirection::Request, None),
+                    ),
+                    entry(
+                        0,
+                        Direction::Rx,
+                        frame(
+                            MspDirection::Response,
+                            Some(MspAltitude {
+                                estimated_alt: *alt,
+                                vario: 0,
+                            }),
+                        ),
+                    ),
+                ]
+            })
+            .collect();
+        let recording = recording.join("\n");
+
+        let fetch_twice = |ttl: &str| {
+            let conf = conf(&["--fetch-ttl-ms", ttl]);
+            let state = BridgeState::default();
+            let mut conn: ReplayConnection = recording.parse().unwrap();
+            let first: MspAltitude = fetch(&conf, &state, &mut conn).unwrap();
+            let second: MspAltitude = fetch(&conf, &state, &mut conn).unwrap();
+            (first.estimated_alt, second.estimated_alt)
+        };
+        assert_eq!(fetch_twice("60000"), (1, 1));
+        assert_eq!(fetch_twice("0"), (1, 3));
+    }
+
+    #[test]
+    fn set_servo_confirms_output() {
+        use crate::replay::test::{entry, frame};
+        use crate::replay::{Direction, ReplayConnection};
+
+        let status = MspStatus {
+            cycle_time: 0,
+            i2c_errors_count: 0,
+            sensor: 0,
+            flag: 0,
+            global_conf_current_set: 0,
+        };
+        let mut servos = [1500; 16];
+        servos[1] = 1200;
+        let recording = [
+            entry(
+                0,
+                Direction::Tx,
+                frame::<MspStatus>(MspDirection::Request, None),
+            ),
+            entry(
+                0,
+                Direction::Rx,
+                frame(MspDirection::Response, Some(status)),
+            ),
+            entry(
+                0,
+                Direction::Tx,
+                frame::<MspSetServoConf>(MspDirection::Request, None),
+            ),
+            entry(
+                0,
+                Direction::Rx,
+                frame::<MspSetServoConf>(MspDirection::Response, None),
+            ),
+            entry(
+                0,
+                Direction::Tx,
+                frame::<MspServo>(MspDirection::Request, None),
+            ),
+            entry(
+                0,
+                Direction::Rx,
+                frame(MspDirection::Response, Some(MspServo::from(servos))),
+            ),
+        ]
+        .join("\n");
+
+        let command = |param1, param2| COMMAND_LONG_DATA {
+            param1,
+            param2,
+            command: MavCmd::MAV_CMD_DO_SET_SERVO,
+            ..Default::default()
+        };
+        let persisting = conf(&["--persist-writes"]);
+        let conf = conf(&[]);
+        let mut conn: ReplayConnection = recording.parse().unwrap();
+        let mut set =
+            |conf: &Config, servo, output| set_servo(conf, &mut conn, &command(servo, output));
+        assert_eq!(
+            set(&conf, 2., 1200.).unwrap(),
+            MavResult::MAV_RESULT_ACCEPTED
+        );
+        assert_eq!(set(&conf, 1., 1200.).unwrap(), MavResult::MAV_RESULT_FAILED);
+        for (servo, output) in &[(0., 1500.), (17., 1500.), (1., 500.), (1., 2500.)] {
+            assert_eq!(
+                set(&conf, *servo, *output).unwrap(),
+                MavResult::MAV_RESULT_DENIED
+            );
+        }
+
+        // an EEPROM write would save the pinned range
+        assert_eq!(
+            set(&persisting, 2., 1200.).unwrap(),
+            MavResult::MAV_RESULT_DENIED
+        );
+    }
+
+    #[test]
+    fn manual_control_to_rc() {
+        assert_eq!("2,1,3,4".parse(), Ok(AxisChannels([2, 1, 3, 4])));
+        assert!("1,2,3".parse::<AxisChannels>().is_err());
+
+        let conf = conf(&["--manual-control-buttons", "15"]);
+        let msg = MANUAL_CONTROL_DATA {
+            x: -1000,
+            y: 400,
+            z: 1500,
+            r: 0,
+            buttons: 0b10,
+            target: 1,
+        };
+        let mut channels = [1234; 16];
+        manual_control(&conf, &msg, &mut channels);
+        assert_eq!(&channels[..6], &[1700, 1000, 2000, 1500, 1234, 1234]);
+        assert_eq!(&channels[14..], &[1000, 2000]);
+    }
+
+    #[test]
+    fn stale_heartbeat_is_critical() {
+        let mut mspconn = io::Cursor::new(Vec::new());
+        let previous = HEARTBEAT(HEARTBEAT_DATA {
+            custom_mode: 7,
+            system_status: MavState::MAV_STATE_ACTIVE,
+            ..Default::default()
+        });
+
+        let state = BridgeState::default();
+        assert!(heartbeat(&conf(&[]), &state, &mut mspconn, Some(&previous)).is_err());
+
+        let conf = conf(&["--telemetry-timeout-ms", "0"]);
+        std::thread::sleep(Duration::from_millis(1));
+        match &heartbeat(&conf, &state, &mut mspconn, Some(&previous)).unwrap()[..] {
+            [HEARTBEAT(data)] => {
+                assert_eq!(data.system_status, MavState::MAV_STATE_CRITICAL);
+                assert_eq!(data.custom_mode, 7);
+            }
+            messages => panic!("unexpected {:?}", messages),
+        }
+    }
+
+    #[test]
+    fn rc_frame_applies_to_leading_channels() {
+        let frame: RcFrame = "1500, 1500,1000,1500".parse().unwrap();
+        let mut channels = [2000u16; 16];
+        frame.apply(&mut channels);
+        assert_eq!(channels[..5], [1500, 1500, 1000, 1500, 2000]);
+
+        assert!("".parse::<RcFrame>().is_err());
+        assert!("1500,x".parse::<RcFrame>().is_err());
+        assert!(vec!["1500"; 17].join(",").parse::<RcFrame>().is_err());
+    }
+
+    #[test]
+    fn rc_override_ignore_and_release() {
+        let msg = RC_CHANNELS_OVERRIDE_DATA {
+            chan1_raw: 1600,
+            chan2_raw: RC_OVERRIDE_IGNORE,
+            chan3_raw: RC_OVERRIDE_RELEASE,
+            chan4_raw: 1400,
+            chan5_raw: RC_OVERRIDE_IGNORE,
+            chan6_raw: RC_OVERRIDE_IGNORE,
+            chan7_raw: RC_OVERRIDE_IGNORE,
+            chan8_raw: RC_OVERRIDE_IGNORE,
+            target_system: 1,
+            target_component: 1,
+        };
+        let fc = [1100; 16];
+        let mut channels = [1500; 16];
+        rc_channels_override(&msg, &fc, &mut channels);
+        assert_eq!(&channels[..5], &[1600, 1500, 1100, 1400, 1500]);
+        assert!(!releases_all_channels(&msg));
+        assert!(releases_any_channel(&msg));
+        assert!(!releases_any_channel(&RC_CHANNELS_OVERRIDE_DATA {
+            chan3_raw: 1500,
+            ..msg
+        }));
+        assert!(releases_all_channels(&RC_CHANNELS_OVERRIDE_DATA::default()));
+    }
+
+    #[test]
+    fn attitude_target_to_rc() {
+        let conf = conf(&[]);
+        // rolled right by 30 degrees, half the default angle limit
+        let half = (30f32.to_radians() / 2.).sin_cos();
+        let msg = SET_ATTITUDE_TARGET_DATA {
+            q: [half.1, half.0, 0., 0.],
+            body_yaw_rate: -conf.attitude_target_max_yaw_rate.to_radians(),
+            thrust: 0.5,
+            type_mask: ATTITUDE_TARGET_IGNORE_THRUST,
+            ..Default::default()
+        };
+        let mut channels = [1234; 16];
+        attitude_target(&conf, &msg, &mut channels);
+        assert_eq!(&channels[..5], &[1750, 1500, 1234, 1000, 1234]);
+
+        let msg = SET_ATTITUDE_TARGET_DATA {
+            type_mask: ATTITUDE_TARGET_IGNORE_ATTITUDE | ATTITUDE_TARGET_IGNORE_YAW_RATE,
+            ..msg
+        };
+        attitude_target(&conf, &msg, &mut channels);
+        assert_eq!(&channels[..5], &[1750, 1500, 1500, 1000, 1234]);
+    }
+
+    #[test]
+    fn heartbeat_airframe() {
+        assert_eq!(
+            "Fixed_Wing".parse(),
+            Ok(VehicleType(MavType::MAV_TYPE_FIXED_WING))
+        );
+        assert_eq!(
+            "PX4".parse(),
+            Ok(Autopilot(MavAutopilot::MAV_AUTOPILOT_PX4))
+        );
+        assert!("blimp"
+            .parse::<VehicleType>()
+            .unwrap_err()
+            .contains("quadrotor"));
+
+        let airframe = |conf: &Config, state: &BridgeState| {
+            state.fetch_cache.put(&MspStatus {
+                cycle_time: 0,
+                i2c_errors_count: 0,
+                sensor: 0,
+                flag: 0,
+                global_conf_current_set: 0,
+            });
+            let mut conn = crate::replay::ReplayConnection::default();
+            match &heartbeat(conf, state, &mut conn, None).unwrap()[0] {
+                HEARTBEAT(heartbeat) => (heartbeat.mavtype, heartbeat.autopilot),
+                message => panic!("unexpected {:?}", message),
+            }
+        };
+        let mut state = BridgeState::default();
+        state.fc_variant = Some("INAV".to_string());
+        assert_eq!(
+            airframe(&conf(&["--fetch-ttl-ms", "60000"]), &state),
+            (
+                MavType::MAV_TYPE_GENERIC,
+                MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA
+            )
+        );
+        let conf = conf(&[
+            "--fetch-ttl-ms",
+            "60000",
+            "--mav-type",
+            "quadrotor",
+            "--autopilot",
+            "generic",
+        ]);
+        assert_eq!(
+            airframe(&conf, &state),
+            (
+                MavType::MAV_TYPE_QUADROTOR,
+                MavAutopilot::MAV_AUTOPILOT_GENERIC
+            )
+        );
+    }
+
+    #[test]
+    fn condition_yaw_targets() {
+        let yaw = |angle, direction, relative| COMMAND_LONG_DATA {
+            param1: angle,
+            param3: direction,
+            param4: relative,
+            command: MavCmd::MAV_CMD_CONDITION_YAW,
+            ..Default::default()
+        };
+        assert_eq!(condition_yaw_heading(&yaw(90., 0., 0.), 200), 90);
+        assert_eq!(condition_yaw_heading(&yaw(370., 0., 0.), 200), 10);
+        assert_eq!(condition_yaw_heading(&yaw(30., 1., 1.), 350), 20);
+        assert_eq!(condition_yaw_heading(&yaw(30., -1., 1.), 10), 340);
+    }
+
+    #[test]
+    fn timesync_roundtrip() {
+        let conf = conf(&[]);
+        let request = TIMESYNC_DATA { tc1: 0, ts1: 42 };
+        match timesync(&conf, &request) {
+            Some(TIMESYNC(reply)) => {
+                assert_eq!(reply.ts1, 42);
+                assert!(reply.tc1 > 0);
+            }
+            _ => panic!("no TIMESYNC reply"),
+        }
+        assert!(timesync(&conf, &TIMESYNC_DATA { tc1: 7, ts1: 42 }).is_none());
+    }
+
+    #[test]
+    fn landed_or_in_air() {
+        let on_ground = MavLandedState::MAV_LANDED_STATE_ON_GROUND;
+        let in_air = MavLandedState::MAV_LANDED_STATE_IN_AIR;
+        assert_eq!(landed_state(false, 5000, 1000), on_ground);
+        assert_eq!(landed_state(true, 10, 0), on_ground);
+        assert_eq!(landed_state(true, 10, 300), in_air);
+        assert_eq!(landed_state(true, 5000, 0), in_air);
+    }
+
+    #[test]
+    fn sensor_scaling() {
+        // one g in INAV units
+        assert_eq!(scale_sensor(512, 1.953125), 1000);
+        assert_eq!(scale_sensor(-512, 1.953125), -1000);
+        // 90 deg/s in mrad/s
+        assert_eq!(scale_sensor(90, 17.453293), 1571);
+        assert_eq!(scale_sensor(i16::MAX, 17.453293), i16::MAX);
+    }
+
+    #[test]
+    fn heading_wraps_into_signed_yaw() {
+        let conf = conf(&[]);
+        let yaw = |heading| {
+            let (_, _, yaw) = euler_angles(
+                &conf,
+                &MspAttitude {
+                    angx: 0,
+                    angy: 0,
+                    heading,
+                },
+            );
+            yaw
+        };
+        assert_eq!(yaw(0), 0.);
+        assert!((yaw(180) - std::f32::consts::PI).abs() < 1e-6);
+        assert!((yaw(350) + 10f32.to_radians()).abs() < 1e-6);
+        assert!((yaw(359) + 1f32.to_radians()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn heading_declination_and_smoothing() {
+        let east = conf(&["--declination-deg", "3.5"]);
+        assert_eq!(true_heading(&east, 90), 93.5);
+        assert_eq!(true_heading(&east, 178), -178.5);
+        let west = conf(&["--declination-deg=-3.5"]);
+        assert_eq!(true_heading(&west, 2), -1.5);
+
+        let yaw = 1.2;
+        assert_eq!(smooth_yaw(&west, yaw, yaw), yaw);
+        let smoothed = smooth_yaw(&west, 0.2, 0.);
+        assert!(smoothed > 0. && smoothed < 0.2);
+        // across the wrap the filter takes the short way round
+        let pi = std::f32::consts::PI;
+        let smoothed = smooth_yaw(&west, -pi + 0.1, pi - 0.1);
+        assert!(smoothed.abs() > pi - 0.1);
+
+        let conf = conf(&["--heading-smoothing", "0"]);
+        assert_eq!(smooth_yaw(&conf, 0.2, 0.), 0.2);
+    }
+
+    #[test]
+    fn inverted_axes() {
+        let payload = MspAttitude {
+            angx: 100,
+            angy: 200,
+            heading: 30,
+        };
+        let (roll, pitch, yaw) = euler_angles(&conf(&[]), &payload);
+        assert!(roll > 0. && pitch < 0. && yaw > 0.);
+
+        let conf = conf(&["--invert-roll", "--invert-pitch", "--invert-yaw"]);
+        assert_eq!(euler_angles(&conf, &payload), (-roll, -pitch, -yaw));
+        assert_eq!(scale_sensor(i16::MIN, axis_sign(true)), i16::MAX);
+    }
+
+    #[test]
+    fn zero_euler_angles_are_identity_quaternion() {
+        assert_eq!(quaternion(0., 0., 0.), [1., 0., 0., 0.]);
+        let q = quaternion(0., 0., std::f32::consts::PI);
+        assert!(q[0].abs() < 1e-6 && (q[3] - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn home_from_distance_and_direction() {
+        // 1 km north of the equator
+        assert_eq!(offset_position(0, 0, 1000., 0.), (89932, 0));
+        // 1 km east of the equator
+        assert_eq!(offset_position(0, 0, 1000., 90.), (0, 89932));
+        // back and forth ends up within a meter of where it started
+        let (lat, lon) = offset_position(480_000_000, 110_000_000, 500., 45.);
+        let (lat, lon) = offset_position(lat, lon, 500., 225.);
+        assert!((lat - 480_000_000).abs() <= 10 && (lon - 110_000_000).abs() <= 10);
+        let e = io::Error::new(io::ErrorKind::Other, NoGpsFix);
+        assert!(is_no_gps_fix(&e));
+        assert!(!is_no_gps_fix(&io::Error::from(io::ErrorKind::Other)));
+    }
+
+    #[test]
+    fn gps_origin_is_fixed_once() {
+        use crate::replay::ReplayConnection;
+
+        let conf = conf(&["--fetch-ttl-ms", "60000"]);
+        let state = BridgeState::default();
+        let mut conn = ReplayConnection::default();
+        let mut origin_after = |fix, coord_lat, coord_lon, context: Option<&MavMessage>| {
+            state.fetch_cache.put(&MspRawGps {
+                fix,
+                num_sat: 8,
+                coord_lat,
+                coord_lon,
+                altitude: 500,
+                speed: 0,
+                ground_course: 0,
+            });
+            gps_global_origin(&conf, &state, &mut conn, context)
+        };
+        assert!(is_no_gps_fix(&origin_after(1, 1, 1, None).unwrap_err()));
+        assert!(is_no_gps_fix(&origin_after(2, 0, 0, None).unwrap_err()));
+        assert_eq!(state.gps_origin.get(), None);
+
+        let origin = GPS_GLOBAL_ORIGIN_DATA {
+            latitude: 473_977_420,
+            longitude: 85_455_940,
+            altitude: 500_000,
+        };
+        let sent = origin_after(2, origin.latitude, origin.longitude, None).unwrap();
+        assert_eq!(sent, vec![GPS_GLOBAL_ORIGIN(origin.clone())]);
+        // later fixes neither move nor resend the origin
+        assert!(origin_after(2, 1, 1, sent.first()).unwrap().is_empty());
+        assert_eq!(state.gps_origin.get(), Some(origin));
+
+        let moved = GPS_GLOBAL_ORIGIN_DATA {
+            latitude: 1,
+            longitude: 1,
+            altitude: 0,
+        };
+        state.gps_origin.set(moved.clone());
+        let resent = origin_after(2, 2, 2, sent.first()).unwrap();
+        assert_eq!(resent, vec![GPS_GLOBAL_ORIGIN(moved)]);
+    }
+
+    #[test]
+    fn gps_raw_int_wraps_course() {
+        use crate::replay::ReplayConnection;
+
+        let conf = conf(&["--fetch-ttl-ms", "60000"]);
+        let state = BridgeState::default();
+        state.fetch_cache.put(&MspRawGps {
+            fix: 2,
+            num_sat: 8,
+            coord_lat: 1,
+            coord_lon: 1,
+            altitude: 500,
+            speed: 0,
+            ground_course: 7200,
+        });
+        match &gps_raw_int(&conf, &state, &mut ReplayConnection::default(), None).unwrap()[..] {
+            [GPS_RAW_INT(data)] => assert_eq!(data.cog, 0),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn global_position_int_wraps_heading() {
+        use crate::replay::ReplayConnection;
+
+        let conf = conf(&["--fetch-ttl-ms", "60000"]);
+        let state = BridgeState::default();
+        state.fetch_cache.put(&MspRawGps {
+            fix: 2,
+            num_sat: 8,
+            coord_lat: 1,
+            coord_lon: 1,
+            altitude: 500,
+            speed: 0,
+            ground_course: 65535,
+        });
+        state.fetch_cache.put(&MspAltitude {
+            estimated_alt: 100,
+            vario: 0,
+        });
+        let sent =
+            global_position_int(&conf, &state, &mut ReplayConnection::default(), None).unwrap();
+        match &sent[..] {
+            [GLOBAL_POSITION_INT(data)] => assert_eq!(data.hdg, 735 * 10),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn local_offset_inverts_offset_position() {
+        let origin = (473_977_420, 85_455_940);
+        // offset_position scales by the latitude it starts from, so both agree only near origin
+        for (distance, bearing) in &[(0., 0.), (100., 0.), (1000., 90.), (1000., 225.)] {
+            let (lat, lon) = offset_position(origin.0, origin.1, *distance, *bearing);
+            let (north, east) = local_offset(origin, lat, lon);
+            let bearing = bearing.to_radians();
+            assert!((north - distance * bearing.cos()).abs() < 0.1);
+            assert!((east - distance * bearing.sin()).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn gps_input_is_injected() {
+        use crate::replay::ReplayConnection;
+
+        let mut msg = GPS_INPUT_DATA {
+            lat: 473_977_420,
+            lon: 85_455_940,
+            alt: 488.4,
+            vn: 3.,
+            ve: -3.,
+            ignore_flags: GpsInputIgnoreFlags::empty(),
+            fix_type: 3,
+            satellites_visible: 12,
+            ..Default::default()
+        };
+        let gps = injected_gps(&msg);
+        assert_eq!((gps.fix, gps.num_sat), (2, 12));
+        assert_eq!((gps.coord_lat, gps.coord_lon), (473_977_420, 85_455_940));
+        assert_eq!(
+            (gps.altitude, gps.speed, gps.ground_course),
+            (488, 424, 3150)
+        );
+
+        msg.fix_type = 1;
+        msg.ignore_flags = GpsInputIgnoreFlags::GPS_INPUT_IGNORE_FLAG_ALT
+            | GpsInputIgnoreFlags::GPS_INPUT_IGNORE_FLAG_VEL_HORIZ;
+        let gps = injected_gps(&msg);
+        assert_eq!(
+            (gps.fix, gps.altitude, gps.speed, gps.ground_course),
+            (0, 0, 0, 0)
+        );
+
+        // while injecting, the GPS of the FC is not polled
+        let conf = conf(&["--fetch-ttl-ms", "0"]);
+        let state = BridgeState::default();
+        let mut conn = ReplayConnection::default();
+        state.gps_injection.inject(gps);
+        let polled: MspRawGps = fetch(&conf, &state, &mut conn).unwrap();
+        assert_eq!(polled, gps);
+    }
+
+    #[test]
+    fn statustext_chunks() {
+        let text: String = (0..120).map(|i| (b'a' + i % 26) as char).collect();
+        let messages = statustexts(MavSeverity::MAV_SEVERITY_INFO, &text);
+        assert_eq!(messages.len(), 3);
+        let received: String = messages
+            .iter()
+            .flat_map(|m| match m {
+                STATUSTEXT(data) => data.text.clone(),
+                _ => panic!("not a STATUSTEXT"),
+            })
+            .filter(|c| *c != '\0')
+            .collect();
+        assert_eq!(received, text);
+        assert_eq!(
+            statustexts(MavSeverity::MAV_SEVERITY_INFO, "short").len(),
+            1
+        );
+    }
+
+    fn setting(name: &str, setting_type: u8, min: i32, max: u32, value: Vec<u8>) -> MspSettingInfo {
+        MspSettingInfo {
+            name: name.to_string(),
+            pgn: 0,
+            setting_type,
+            section: 0,
+            mode: 0,
+            min,
+            max,
+            index: 0,
+            profile: 0,
+            profile_count: 1,
+            lookup: Vec::new(),
+            value,
+        }
+    }
+
+    #[test]
+    fn rc_tuning_read_modify_write() {
+        let payload = MspRcTuning {
+            rc_rate: 100,
+            rc_expo: 70,
+            roll_pitch_rate: 20,
+            yaw_rate: 20,
+            dyn_thr_pid: 10,
+            throttle_mid: 50,
+            throttle_expo: 0,
+        };
+        let index = param_index("RC_YAW_RATE").unwrap();
+        assert_eq!(index, PID_PARAM_COUNT + 3);
+        match rc_tuning_param(&payload, index) {
+            PARAM_VALUE(data) => {
+                assert_eq!(from_param_id(&data.param_id), "RC_YAW_RATE");
+                assert_eq!(data.param_value, 20.);
+                assert_eq!(data.param_count as usize, PARAM_COUNT);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let written = set_rc_tuning(&payload, index, 45.4);
+        assert_eq!(written.yaw_rate, 45);
+        assert_eq!(
+            (written.rc_rate, written.rc_expo, written.roll_pitch_rate),
+            (100, 70, 20)
+        );
+        assert_eq!(
+            (
+                written.dyn_thr_pid,
+                written.throttle_mid,
+                written.throttle_expo
+            ),
+            (10, 50, 0)
+        );
+        // out of range values are clamped to what INAV accepts
+        let index = param_index("RC_RP_RATE").unwrap();
+        assert_eq!(set_rc_tuning(&payload, index, 1.).roll_pitch_rate, 6);
+        let index = param_index("THR_MID").unwrap();
+        assert_eq!(set_rc_tuning(&payload, index, 300.).throttle_mid, 100);
+        assert_eq!(param_index("ROLL_P"), Some(0));
+    }
+
+    #[test]
+    fn craft_name_of_fc() {
+        let name = |name: &str| {
+            craft_name(&MspName {
+                name: name.to_string(),
+            })
+        };
+        assert_eq!(name("QUAD 1"), Some("QUAD 1".to_string()));
+        assert_eq!(name(" WING\0\0"), Some("WING".to_string()));
+        assert_eq!(name(""), None);
+        assert_eq!(name("\0 "), None);
+
+        let mut state = BridgeState::default();
+        assert!(craft_name_text(&state).is_empty());
+        state.craft_name = name("QUAD 1");
+        match &craft_name_text(&state)[..] {
+            [STATUSTEXT(data)] => assert!(data
+                .text
+                .iter()
+                .collect::<String>()
+                .starts_with("craft QUAD 1")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn settings_as_params() {
+        let info = setting(
+            "nav_rth_altitude",
+            4,
+            0,
+            65000,
+            1000u32.to_le_bytes().to_vec(),
+        );
+        assert_eq!(setting_value(info.setting_type, &info.value), Some(1000.));
+        assert_eq!(
+            setting_bytes(&info, 70000.),
+            Some(65000u32.to_le_bytes().to_vec())
+        );
+
+        let info = setting("roll_rate", 3, -180, 180, (-90i16).to_le_bytes().to_vec());
+        assert_eq!(setting_value(info.setting_type, &info.value), Some(-90.));
+        assert_eq!(
+            setting_bytes(&info, -200.4),
+            Some((-180i16).to_le_bytes().to_vec())
+        );
+
+        let info = setting("name", 6, 0, 16, b"INAV".to_vec());
+        assert!(!is_param_setting(&info));
+        assert_eq!(setting_bytes(&info, 1.), None);
+
+        let settings = vec![
+            setting("gyro_hardware_lpf", 0, 0, 3, vec![1]),
+            setting("nav_fw_control_smoothness", 0, 0, 9, vec![2]),
+        ];
+        assert_eq!(find_setting(&settings, 1, ""), Some(1));
+        assert_eq!(find_setting(&settings, 2, ""), None);
+        assert_eq!(find_setting(&settings, -1, "nav_fw_control_s"), Some(1));
+        assert_eq!(find_setting(&settings, -1, "gyro_lpf"), None);
+        match setting_param(&settings[1], 1, 2) {
+            PARAM_VALUE(data) => {
+                assert_eq!(from_param_id(&data.param_id), "nav_fw_control_s");
+                assert_eq!(data.param_value, 2.);
+                assert_eq!(data.param_count, 2);
+            }
+            _ => panic!("not a PARAM_VALUE"),
+        }
+    }
+
+    #[test]
+    fn sensor_bitmask() {
+        let (present, health) = sensors(0b1011);
+        assert_eq!(
+            present,
+            MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_GYRO
+                | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_ACCEL
+                | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_ABSOLUTE_PRESSURE
+                | MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_GPS
+        );
+        assert_eq!(health, present);
+
+        let (present, health) = sensors(SENSOR_FAILURE | 1);
+        assert!(present.contains(MavSysStatusSensor::MAV_SYS_STATUS_SENSOR_3D_ACCEL));
+        assert!(health.is_empty());
+    }
+
+    #[test]
+    fn battery_cells() {
+        let voltages = cell_voltages(4, 1600);
+        assert_eq!(voltages[..4], [4000; 4]);
+        assert_eq!(voltages[4..], [u16::MAX; 6]);
+        let voltages = cell_voltages(0, 1234);
+        assert_eq!(voltages[0], 12340);
+        assert_eq!(voltages[1], u16::MAX);
+
+        assert_eq!(capacity_remaining(1300, 325), Some(75));
+        assert_eq!(capacity_remaining(1300, 2000), Some(0));
+        assert_eq!(capacity_remaining(0, 100), None);
+    }
+
+    #[test]
+    fn attitude_rates_from_previous() {
+        let previous = ATTITUDE_DATA {
+            time_boot_ms: 1000,
+            roll: 0.1,
+            pitch: 0.,
+            yaw: 3.1,
+            rollspeed: 0.,
+            pitchspeed: 0.,
+            yawspeed: 0.,
+        };
+        let mut current = ATTITUDE_DATA {
+            time_boot_ms: 1500,
+            roll: 0.2,
+            pitch: -0.1,
+            yaw: -3.1,
+            ..previous.clone()
+        };
+        attitude_rates(&mut current, &previous);
+        assert!((current.rollspeed - 0.2).abs() < 1e-5);
+        assert!((current.pitchspeed + 0.2).abs() < 1e-5);
+        // crossing from +pi to -pi is a small positive turn
+        let expected = (2. * std::f32::consts::PI - 6.2) / 0.5;
+        assert!((current.yawspeed - expected).abs() < 1e-4);
+
+        // identical timestamps leave the rates untouched
+        let mut same = ATTITUDE_DATA {
+            rollspeed: 0.,
+            pitchspeed: 0.,
+            yawspeed: 0.,
+            ..current.clone()
+        };
+        attitude_rates(&mut same, &current);
+        assert_eq!(same.yawspeed, 0.);
+    }
+
+    #[test]
+    fn motor_test_throttle() {
+        let command = |throttle_type: MotorTestThrottleType, value, timeout| COMMAND_LONG_DATA {
+            param1: 1.,
+            param2: throttle_type as u8 as f32,
+            param3: value,
+            param4: timeout,
+            command: MavCmd::MAV_CMD_DO_MOTOR_TEST,
+            ..Default::default()
+        };
+        let percent = MotorTestThrottleType::MOTOR_TEST_THROTTLE_PERCENT;
+        assert_eq!(motor_test_output(&command(percent, 15., 3.)), Some(1150));
+        assert_eq!(motor_test_output(&command(percent, 150., 3.)), Some(2000));
+        let pwm = MotorTestThrottleType::MOTOR_TEST_THROTTLE_PWM;
+        assert_eq!(motor_test_output(&command(pwm, 1234., 3.)), Some(1234));
+        let pilot = MotorTestThrottleType::MOTOR_TEST_THROTTLE_PILOT;
+        assert_eq!(motor_test_output(&command(pilot, 0., 3.)), None);
+
+        assert_eq!(
+            motor_test_duration(&command(pwm, 1100., 3.)),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            motor_test_duration(&command(pwm, 1100., 600.)),
+            MOTOR_TEST_MAX_DURATION
+        );
+        assert_eq!(
+            motor_test_duration(&command(pwm, 1100., -1.)),
+            Duration::from_secs(0)
+        );
+        for timeout in &[f32::INFINITY, 1e30] {
+            assert_eq!(
+                motor_test_duration(&command(pwm, 1100., *timeout)),
+                MOTOR_TEST_MAX_DURATION
+            );
+        }
+        assert_eq!(
+            motor_test_duration(&command(pwm, 1100., f32::NAN)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn pressure_of_standard_atmosphere() {
+        let (press_abs, temperature) = standard_atmosphere(0.);
+        assert!((press_abs - 1013.25).abs() < 0.01);
+        assert_eq!(temperature, 1500);
+
+        let (press_abs, temperature) = standard_atmosphere(1000.);
+        assert!((press_abs - 898.75).abs() < 0.1);
+        assert_eq!(temperature, 850);
+    }
+
+    #[test]
+    fn invalid_distance_is_unhealthy() {
+        let valid = distance_sensor_data(0, 150);
+        assert_eq!(valid.current_distance, 150);
+        assert_eq!(valid.covariance, 0);
+        assert_eq!(
+            valid.orientation,
+            MavSensorOrientation::MAV_SENSOR_ROTATION_PITCH_270
+        );
+
+        for distance in &[-1, 0, RANGEFINDER_MAX_CM as i32 + 1] {
+            let invalid = distance_sensor_data(0, *distance);
+            assert_eq!(invalid.current_distance, RANGEFINDER_MAX_CM);
+            assert_eq!(invalid.covariance, u8::MAX);
+        }
+    }
+
+    #[test]
+    fn servo_output_ports() {
+        let servos: Vec<u16> = (1001..=1016).collect();
+        let motors: Vec<u16> = (1101..=1116).collect();
+
+        let mut messages = super::servo_output_ports(0, &servos, 0);
+        messages.extend(super::servo_output_ports(0, &motors, MOTOR_PORT));
+        let ports: Vec<_> = messages
+            .iter()
+            .map(|message| match message {
+                SERVO_OUTPUT_RAW(data) => (data.port, data.servo1_raw, data.servo8_raw),
+                _ => panic!("unexpected {:?}", message),
+            })
+            .collect();
+        assert_eq!(
+            ports,
+            vec![
+                (0, 1001, 1008),
+                (1, 1009, 1016),
+                (2, 1101, 1108),
+                (3, 1109, 1116)
+            ]
+        );
+    }
+
+    #[test]
+    fn waypoint_to_mission_item() {
+        let target = MavHeader::default();
+        let wp = MspWp {
+            wp_no: 1,
+            lat: 525_200_000,
+            lon: 134_050_000,
+            alt_hold: 2500,
+            heading: 90,
+            time_to_stay: 3000,
+            nav_flag: 0,
+        };
+        let item = mission_item_from_wp(&wp, 0, &target);
+        assert_eq!(item.command, MavCmd::MAV_CMD_NAV_WAYPOINT);
+        assert_eq!((item.x, item.y), (525_200_000, 134_050_000));
+        assert_eq!(item.z, 25.);
+        assert_eq!(item.param1, 3.);
+        assert_eq!(item.param4, 90.);
+
+        let rth = MspWp {
+            lat: 0,
+            lon: 0,
+            nav_flag: MSP_WP_LAST,
+            ..wp
+        };
+        let item = mission_item_from_wp(&rth, 1, &target);
+        assert_eq!(item.command, MavCmd::MAV_CMD_NAV_RETURN_TO_LAUNCH);
+        assert_eq!(item.seq, 1);
+    }
+
+    #[test]
+    fn mission_item_out_of_range() {
+        use crate::replay::ReplayConnection;
+
+        let target = MavHeader::default();
+        for seq in &[255, u16::MAX] {
+            let e = mission_item(&mut ReplayConnection::default(), *seq, &target).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn mission_item_to_waypoint() {
+        let target = MavHeader::default();
+        let wp = MspWp {
+            wp_no: 3,
+            lat: 525_200_000,
+            lon: 134_050_000,
+            alt_hold: 2500,
+            heading: 90,
+            time_to_stay: 3000,
+            nav_flag: MSP_WP_LAST,
+        };
+        let set_wp = wp_from_mission_item(&mission_item_from_wp(&wp, 2, &target), true);
+        assert_eq!(set_wp.wp_no, wp.wp_no);
+        assert_eq!((set_wp.lat, set_wp.lon), (wp.lat, wp.lon));
+        assert_eq!(set_wp.alt_hold, wp.alt_hold);
+        assert_eq!(set_wp.heading, wp.heading);
+        assert_eq!(set_wp.time_to_stay, wp.time_to_stay);
+        assert_eq!(set_wp.nav_flag, MSP_WP_LAST);
+    }
+
+    #[test]
+    fn box_index_lookup() {
+        let names = "ARM;ANGLE;HORIZON;FAILSAFE;";
+        assert_eq!(box_index(names, "ARM"), Some(0));
+        assert_eq!(box_index(names, "FAILSAFE"), Some(3));
+        assert_eq!(box_index(names, "BEEPER"), None);
+    }
+
+    #[test]
+    fn rc_channels_read_through() {
+        let mut channels: Vec<u16> = (1001..=1016).collect();
+        channels[5] = 0;
+
+        let data = rc_channels_data(0, &channels, scale_rssi(1023, 1023));
+        assert_eq!(data.chan1_raw, 1001);
+        assert_eq!(data.chan6_raw, 0);
+        assert_eq!(data.chan16_raw, 1016);
+        assert_eq!(data.chan17_raw, u16::MAX);
+        assert_eq!(data.chan18_raw, u16::MAX);
+        assert_eq!(data.chancount, 16);
+        assert_eq!(data.rssi, 254);
+    }
+
+    #[test]
+    fn rssi_scaling() {
+        assert_eq!(scale_rssi(0, 1023), 0);
+        assert_eq!(scale_rssi(1023, 1023), 254);
+        assert_eq!(scale_rssi(255, 255), 254);
+        assert_eq!(scale_rssi(1023, 255), 254);
+        assert_eq!(scale_rssi(128, 255), 127);
+        assert_eq!(scale_rssi(0, 0), 0);
+
+        let channels = [1500, 900, 2000, 2100, 1000];
+        assert_eq!(channel_rssi(&channels, 1), 127);
+        assert_eq!(channel_rssi(&channels, 2), 0);
+        assert_eq!(channel_rssi(&channels, 3), 254);
+        assert_eq!(channel_rssi(&channels, 4), 254);
+        assert_eq!(channel_rssi(&channels, 5), 0);
+        assert_eq!(channel_rssi(&channels, 0), RSSI_UNKNOWN);
+        assert_eq!(channel_rssi(&channels, 6), RSSI_UNKNOWN);
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serialport::{SerialPort, SerialPortSettings};
+
+use crate::msp::MspConnection;
+
+/// The ways to reach a MSP FC
+///
+/// Parsed from `serial:/dev/ttyUSB0:115200`, `tcp:192.168.1.1:5761` or `udp:192.168.1.1:5761`.
+/// A string without scheme is taken as path to a serial port.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MspTransport {
+    Serial { path: String, baud: Option<u32> },
+    Tcp(String),
+    Udp(String),
+}
+
+impl FromStr for MspTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(MspTransport::Tcp(addr.to_string()))
+        } else if let Some(addr) = s.strip_prefix("udp:") {
+            Ok(MspTransport::Udp(addr.to_string()))
+        } else if let Some(port) = s.strip_prefix("serial:") {
+            match port.rsplit_once(':') {
+                Some((path, baud)) => Ok(MspTransport::Serial {
+                    path: path.to_string(),
+                    baud: Some(baud.parse().map_err(|_| format!("invalid baud {}", baud))?),
+                }),
+                None => Ok(MspTransport::Serial {
+                    path: port.to_string(),
+                    baud: None,
+                }),
+            }
+        } else {
+            Ok(MspTransport::Serial {
+                path: s.to_string(),
+                baud: None,
+            })
+        }
+    }
+}
+
+impl fmt::Display for MspTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MspTransport::Serial {
+                path,
+                baud: Some(baud),
+            } => write!(f, "serial:{}:{}", path, baud),
+            MspTransport::Serial { path, baud: None } => write!(f, "serial:{}", path),
+            MspTransport::Tcp(addr) => write!(f, "tcp:{}", addr),
+            MspTransport::Udp(addr) => write!(f, "udp:{}", addr),
+        }
+    }
+}
+
+impl MspTransport {
+    /// opens the connection, `baud` is used if a serial port does not specify one
+    ///
+    /// With `drain` the input left over by a failed request is discarded before the next one,
+    /// see `DrainAfterError`.
+    pub fn connect(
+        &self,
+        baud: u32,
+        timeout: Duration,
+        drain: bool,
+    ) -> io::Result<Box<dyn MspConnection + Send>> {
+        match self {
+            MspTransport::Serial { path, baud: b } => {
+                let settings = SerialPortSettings {
+                    baud_rate: b.unwrap_or(baud),
+                    timeout,
+                    ..Default::default()
+                };
+                let port = serialport::open_with_settings(path, &settings)?;
+                port.clear(serialport::ClearBuffer::All)?;
+                Ok(drain_after_error(port, drain))
+            }
+            MspTransport::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_nodelay(true)?;
+                Ok(drain_after_error(stream, drain))
+            }
+            MspTransport::Udp(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(addr)?;
+                socket.set_read_timeout(Some(timeout))?;
+                let conn = UdpConnection {
+                    socket,
+                    buf: Vec::new(),
+                    pos: 0,
+                };
+                Ok(drain_after_error(conn, drain))
+            }
+        }
+    }
+}
+
+/// A connection whose pending input can be discarded without blocking
+trait DiscardInput: MspConnection {
+    /// discards the received bytes not read yet, yields how many there were
+    fn discard_input(&mut self) -> io::Result<usize>;
+}
+
+impl DiscardInput for Box<dyn SerialPort> {
+    fn discard_input(&mut self) -> io::Result<usize> {
+        let pending = self.bytes_to_read()?;
+        self.clear(serialport::ClearBuffer::Input)?;
+        Ok(pending as usize)
+    }
+}
+
+impl DiscardInput for TcpStream {
+    fn discard_input(&mut self) -> io::Result<usize> {
+        self.set_nonblocking(true)?;
+        let mut buf = [0u8; 256];
+        let mut discarded = 0;
+        let result = loop {
+            match self.read(&mut buf) {
+                Ok(0) => break Ok(discarded),
+                Ok(len) => discarded += len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(discarded),
+                Err(e) => break Err(e),
+            }
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+/// wraps `conn` in `DrainAfterError` if `drain` is set
+fn drain_after_error<T>(conn: T, drain: bool) -> Box<dyn MspConnection + Send>
+where
+    T: DiscardInput + Send + 'static,
+{
+    if drain {
+        Box::new(DrainAfterError {
+            inner: conn,
+            failed: false,
+        })
+    } else {
+        Box::new(conn)
+    }
+}
+
+/// Discards stale input before the next request once a request failed
+///
+/// A response arriving after its request timed out, or the rest of a corrupt or unexpected frame,
+/// would otherwise precede the next response and need to be scanned past every time. Input is
+/// only discarded after a failed request, so a response in flight is never lost.
+struct DrainAfterError<T> {
+    inner: T,
+    failed: bool,
+}
+
+impl<T: DiscardInput> Read for DrainAfterError<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: DiscardInput> Write for DrainAfterError<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failed {
+            self.failed = false;
+            match self.inner.discard_input() {
+                Ok(0) => {}
+                Ok(discarded) => debug!("discarded {} stale bytes of MSP input", discarded),
+                Err(e) => debug!("unable to discard stale MSP input: {}", e),
+            }
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: DiscardInput> MspConnection for DrainAfterError<T> {
+    fn mark_failed(&mut self) {
+        self.failed = true;
+    }
+}
+
+impl MspConnection for Box<dyn SerialPort> {}
+impl MspConnection for TcpStream {}
+
+/// Buffers received datagrams, as the MSP decoder reads them in small chunks
+struct UdpConnection {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for UdpConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            self.buf.resize(u16::MAX as usize, 0);
+            let len = self.socket.recv(&mut self.buf[..])?;
+            self.buf.truncate(len);
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.buf.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl DiscardInput for UdpConnection {
+    fn discard_input(&mut self) -> io::Result<usize> {
+        let mut discarded = self.buf.len() - self.pos;
+        self.buf.clear();
+        self.pos = 0;
+        self.socket.set_nonblocking(true)?;
+        let mut datagram = [0u8; 512];
+        let result = loop {
+            match self.socket.recv(&mut datagram) {
+                Ok(len) => discarded += len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(discarded),
+                Err(e) => break Err(e),
+            }
+        };
+        self.socket.set_nonblocking(false)?;
+        result
+    }
+}
+
+impl Write for UdpConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MspConnection for UdpConnection {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::msp::*;
+
+    #[test]
+    fn parse_transports() {
+        assert_eq!(
+            "/dev/ttyACM0".parse(),
+            Ok(MspTransport::Serial {
+                path: "/dev/ttyACM0".to_string(),
+                baud: None
+            })
+        );
+        assert_eq!(
+            "serial:/dev/ttyUSB0:115200".parse(),
+            Ok(MspTransport::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                baud: Some(115200)
+            })
+        );
+        assert_eq!(
+            "tcp:192.168.1.1:5761".parse(),
+            Ok(MspTransport::Tcp("192.168.1.1:5761".to_string()))
+        );
+        assert_eq!(
+            "udp:192.168.1.1:5761".parse(),
+            Ok(MspTransport::Udp("192.168.1.1:5761".to_string()))
+        );
+        assert!("serial:/dev/ttyUSB0:fast".parse::<MspTransport>().is_err());
+    }
+
+    /// input which is read until it runs out, then times out
+    struct Stale {
+        input: Vec<u8>,
+    }
+
+    impl Read for Stale {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Stale {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MspConnection for Stale {}
+
+    impl DiscardInput for Stale {
+        fn discard_input(&mut self) -> io::Result<usize> {
+            Ok(self.input.drain(..).count())
+        }
+    }
+
+    #[test]
+    fn drain_only_after_error() {
+        let mut conn = DrainAfterError {
+            inner: Stale {
+                input: b"$X>".to_vec(),
+            },
+            failed: false,
+        };
+        conn.write_all(b"request").unwrap();
+        assert_eq!(conn.inner.input.len(), 3);
+        conn.mark_failed();
+        conn.write_all(b"request").unwrap();
+        assert!(conn.inner.input.is_empty());
+        assert!(!conn.failed);
+    }
+
+    #[test]
+    fn drain_after_failed_request() {
+        // a response too short for MSP_API_VERSION, followed by the start of the next frame
+        let mut input = Vec::new();
+        MspMessage {
+            version: MspVersion::V2,
+            direction: MspDirection::Response,
+            flag: Some(0),
+            function: MspApiVersion::ID,
+            payload: Some(MspSetHead { mag_hold: 90 }),
+        }
+        .encode(&mut input)
+        .unwrap();
+        input.extend_from_slice(b"$X>");
+        let mut conn = DrainAfterError {
+            inner: Stale { input },
+            failed: false,
+        };
+        let result = MspMessage::<MspApiVersion>::fetch(&mut conn);
+        assert!(matches!(result, Err(MspError::LengthMismatch { .. })));
+        assert!(conn.failed);
+        conn.write_all(b"request").unwrap();
+        assert!(conn.inner.input.is_empty());
+    }
+
+    #[test]
+    fn tcp_discards_pending_input() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        server.write_all(b"late response").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(client.discard_input().unwrap(), 13);
+        assert_eq!(client.discard_input().unwrap(), 0);
+        server.write_all(b"$").unwrap();
+        let mut buf = [0u8; 1];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"$");
+    }
+}